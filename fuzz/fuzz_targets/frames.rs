@@ -0,0 +1,19 @@
+#![no_main]
+// Only the receive paths of the network modules are reached from here
+#![allow(dead_code, unused_imports)]
+
+// The app is a UEFI binary without a library, the modules the network
+// stack needs are built into the target from their sources. cargo fuzz
+// sets cfg(fuzzing), which puts them on the virtual clock.
+#[path = "../../src/asyn/mod.rs"]
+mod asyn;
+#[path = "../../src/fs.rs"]
+mod fs;
+#[path = "../../src/metrics.rs"]
+mod metrics;
+#[path = "../../src/network/mod.rs"]
+mod network;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|d: &[u8]| network::fuzz::frames(d));
@@ -1,6 +1,8 @@
 use core::{alloc::*, ptr::*, sync::atomic::*};
 use uefi::boot::*;
 
+use crate::memory;
+
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator::new(PageAllocator::uefi());
 
@@ -15,7 +17,7 @@ struct Allocator {
 impl Allocator {
     pub const fn new(pager: PageAllocator) -> Self {
         Self {
-            pager: pager,
+            pager,
             start: AtomicPtr::new(null_mut()),
             end: AtomicPtr::new(null_mut()),
         }
@@ -34,15 +36,15 @@ impl Allocator {
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        memory::record_alloc(layout.size());
+
         // Larger sizes to their own page
         if layout.size() >= self.pager.page_size() / 2 {
-            return (self.pager.alloc)(
-                (layout.size() + self.pager.page_size() - 1) / self.pager.page_size(),
-            );
+            return (self.pager.alloc)(layout.size().div_ceil(self.pager.page_size()));
         }
 
         let mut v = self.start.load(Ordering::Relaxed);
-        if v == null_mut()
+        if v.is_null()
             || v.byte_add(v.align_offset(layout.align()) + layout.size())
                 > self.end.load(Ordering::Relaxed)
         {
@@ -55,10 +57,11 @@ unsafe impl GlobalAlloc for Allocator {
         self.start
             .store(v.byte_add(layout.size()), Ordering::Relaxed);
 
-        return v;
+        v
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        memory::record_dealloc();
         // TODO: dealloc implementation
     }
 }
@@ -74,12 +77,13 @@ impl PageAllocator {
         }
     }
     const fn page_size(&self) -> usize {
-        return 4096;
+        4096
     }
 }
 
 fn uefi_page_alloc(n: usize) -> *mut u8 {
-    return allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, n)
+    memory::record_pages(n);
+    allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, n)
         .unwrap()
-        .as_ptr();
+        .as_ptr()
 }
@@ -0,0 +1,173 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use uefi::{boot, proto::console::text::Key, system};
+
+use super::{Executor, Task};
+
+// Output waiting for the console, the oldest lines are dropped beyond it
+const MAX_BUFFERED: usize = 16 * 1024;
+// Bytes written to the console per round of the executor
+const CHUNK: usize = 256;
+
+static CONSOLE: Console = Console {
+    lock: AtomicBool::new(false),
+    buffer: UnsafeCell::new(Buffer {
+        text: String::new(),
+        dropped: 0,
+    }),
+};
+
+struct Buffer {
+    text: String,
+    // Lines dropped since the last chunk
+    dropped: usize,
+}
+
+impl Buffer {
+    fn push(&mut self, s: &str) {
+        while !self.text.is_empty() && self.text.len() + s.len() > MAX_BUFFERED {
+            let end = self.text.find('\n').map_or(self.text.len(), |i| i + 1);
+            self.text.drain(..end);
+            self.dropped += 1;
+        }
+        self.text.push_str(s);
+    }
+
+    // Up to max bytes from the front, cut at a char boundary
+    fn take(&mut self, max: usize) -> String {
+        let mut end = self.text.len().min(max);
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut chunk = String::new();
+        if self.dropped > 0 {
+            chunk = format!("[{} lines dropped]\n", self.dropped);
+            self.dropped = 0;
+        }
+        chunk.extend(self.text.drain(..end));
+        chunk
+    }
+}
+
+// Text for the console, written a chunk at a time by a task so a burst
+// of output does not hold up the other tasks. Same locking as the logger:
+// a write while the lock is taken goes straight to the console.
+struct Console {
+    lock: AtomicBool,
+    buffer: UnsafeCell<Buffer>,
+}
+
+// UEFI boot services run on a single processor
+unsafe impl Sync for Console {}
+unsafe impl Send for Console {}
+
+impl Console {
+    fn with_buffer<R>(&self, f: impl FnOnce(&mut Buffer) -> R) -> Option<R> {
+        if self.lock.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        let r = f(unsafe { &mut *self.buffer.get() });
+        self.lock.store(false, Ordering::Release);
+        Some(r)
+    }
+}
+
+fn output(s: &str) {
+    system::with_stdout(|stdout| {
+        let _ = stdout.write_str(s);
+    });
+}
+
+// Queues text for the console, returning at once
+pub fn console_write(s: &str) {
+    if CONSOLE.with_buffer(|b| b.push(s)).is_none() {
+        output(s);
+    }
+}
+
+// Writes out everything queued, for panics and before the executor goes
+pub fn console_flush() {
+    while let Some(chunk) = CONSOLE.with_buffer(|b| b.take(usize::MAX)) {
+        if chunk.is_empty() {
+            return;
+        }
+        output(&chunk);
+    }
+}
+
+// Writes queued text until the executor stops
+pub fn console_start(e: Arc<dyn Executor>) {
+    e.spawn(Task::new(task_console()));
+}
+
+async fn task_console() {
+    poll_fn(|_| {
+        if let Some(chunk) = CONSOLE.with_buffer(|b| b.take(CHUNK)) {
+            if !chunk.is_empty() {
+                output(&chunk);
+            }
+        }
+        Poll::<()>::Pending
+    })
+    .await
+}
+
+// Next key press, pending until the firmware signals the key event
+pub fn read_key() -> ReadKey {
+    ReadKey {}
+}
+
+pub struct ReadKey {}
+
+impl Future for ReadKey {
+    type Output = Key;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+        let signaled = system::with_stdin(|stdin| match stdin.wait_for_key_event() {
+            Some(event) => boot::check_event(event).unwrap_or(false),
+            // no event to wait on, reading tells as well
+            None => true,
+        });
+        if !signaled {
+            return Poll::Pending;
+        }
+        match system::with_stdin(|stdin| stdin.read_key()) {
+            Ok(Some(key)) => Poll::Ready(key),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_lines_are_dropped() {
+        let mut b = Buffer {
+            text: String::new(),
+            dropped: 0,
+        };
+        let line = "x".repeat(MAX_BUFFERED / 2 - 1) + "\n";
+        b.push(&line);
+        b.push(&line);
+        b.push("last\n");
+        assert_eq!(b.dropped, 1);
+        let chunk = b.take(CHUNK);
+        assert!(chunk.starts_with("[1 lines dropped]\nxxx"));
+        assert_eq!(b.take(usize::MAX).len(), MAX_BUFFERED / 2 - CHUNK + 5);
+        // multi-byte chars are not split
+        b.push("éé");
+        assert_eq!(b.take(3), "é");
+        assert_eq!(b.take(3), "é");
+    }
+}
@@ -1,30 +1,42 @@
 extern crate alloc;
 
-use alloc::sync::Arc;
-use core::task::{Context, Poll, Waker};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
 use crossbeam_queue::ArrayQueue;
 
-use super::{waker::NoOpWaker, Task};
+use super::Task;
 
 pub trait Executor {
     fn spawn(&self, task: Task);
+    // Spawned tasks that have not finished yet
+    fn task_count(&self) -> usize;
+    // Ends run once the current task yields. The tasks left are dropped,
+    // which cancels them.
+    fn stop(&self);
 }
 
 pub struct SimpleExecutor {
     task_queue: ArrayQueue<Task>,
+    stopped: AtomicBool,
 }
 
 impl SimpleExecutor {
     pub fn new() -> SimpleExecutor {
         SimpleExecutor {
             task_queue: ArrayQueue::new(256),
+            stopped: AtomicBool::new(false),
         }
     }
 
     pub fn run(&self) {
-        let waker = Waker::from(Arc::new(NoOpWaker {}));
-        let mut ctx = Context::from_waker(&waker);
+        let mut ctx = Context::from_waker(Waker::noop());
         loop {
+            if self.stopped.load(Ordering::Relaxed) {
+                while self.task_queue.pop().is_some() {}
+                return;
+            }
             let mut task = match self.task_queue.pop() {
                 Some(t) => t,
                 None => return,
@@ -35,10 +47,34 @@ impl SimpleExecutor {
             }
         }
     }
+
+    // Polls every task once, in spawn order, for tests that move a
+    // virtual clock between rounds
+    #[cfg(any(test, fuzzing))]
+    pub fn run_once(&self) {
+        let mut ctx = Context::from_waker(Waker::noop());
+        for _ in 0..self.task_queue.len() {
+            let Some(mut task) = self.task_queue.pop() else {
+                return;
+            };
+            if task.poll(&mut ctx).is_pending() {
+                self.spawn(task);
+            }
+        }
+    }
 }
 
 impl Executor for SimpleExecutor {
     fn spawn(&self, task: Task) {
         self.task_queue.push(task).expect("task queue full");
     }
+
+    fn task_count(&self) -> usize {
+        // the task being polled is out of the queue
+        self.task_queue.len() + 1
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
 }
@@ -1,14 +1,17 @@
+mod console;
 mod executor;
 mod mutex;
 mod or;
 mod queue;
 mod sleep;
 mod task;
-mod waker;
 
+pub use console::{console_flush, console_start, console_write, read_key};
 pub use executor::{Executor, SimpleExecutor};
 pub use mutex::Mutex;
 pub use or::OrFuture;
 pub use queue::{queue_pop, queue_pop_timeout};
-pub use sleep::sleep;
+#[cfg(any(test, fuzzing))]
+pub use sleep::advance;
+pub use sleep::{days, elapsed, sleep, timestamp};
 pub use task::Task;
@@ -28,7 +28,7 @@ impl<T> Mutex<T> {
         }
     }
 
-    pub fn lock(&self) -> MutexFuture<T> {
+    pub fn lock(&self) -> MutexFuture<'_, T> {
         MutexFuture { mutex: self }
     }
 }
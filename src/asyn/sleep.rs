@@ -4,34 +4,65 @@ use core::{
     task::{Context, Poll},
 };
 
+#[cfg(not(any(test, fuzzing)))]
 use uefi::runtime;
 
 pub struct SleepFuture {
     end_ts: u64,
 }
 
-impl SleepFuture {
-    fn get_ts() -> u64 {
-        let mut result: u64 = 0;
-        let t = runtime::get_time().unwrap();
-        result += t.day() as u64;
-        result *= 24;
-        result += t.hour() as u64;
-        result *= 60;
-        result += t.minute() as u64;
-        result *= 60;
-        result += t.second() as u64;
-        result *= 1_000_000_000;
-        result += t.nanosecond() as u64;
-        result
-    }
+// Nanoseconds since 1900, the earliest year the firmware clock holds.
+// Counting from the start of the month would go back when it rolls over.
+#[cfg(not(any(test, fuzzing)))]
+pub fn timestamp() -> u64 {
+    let t = runtime::get_time().unwrap();
+    let mut result = days(t.year(), t.month(), t.day()) - days(1900, 1, 1);
+    result *= 24;
+    result += t.hour() as u64;
+    result *= 60;
+    result += t.minute() as u64;
+    result *= 60;
+    result += t.second() as u64;
+    result *= 1_000_000_000;
+    result += t.nanosecond() as u64;
+    result
+}
+
+// Days from a fixed origin to a date, only differences mean anything.
+// Years start in March so the leap day is the last one of its year.
+pub fn days(year: u16, month: u8, day: u8) -> u64 {
+    let year = year as u64 - if month <= 2 { 1 } else { 0 };
+    let month = (month as u64 + 9) % 12;
+    year * 365 + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day as u64 - 1
+}
+
+// Host tests run on a virtual clock, each thread its own, moved on by
+// the simulator
+#[cfg(any(test, fuzzing))]
+std::thread_local! {
+    static CLOCK: core::cell::Cell<u64> = const { core::cell::Cell::new(0) };
+}
+
+#[cfg(any(test, fuzzing))]
+pub fn timestamp() -> u64 {
+    CLOCK.with(|c| c.get())
+}
+
+#[cfg(any(test, fuzzing))]
+pub fn advance(ns: u64) {
+    CLOCK.with(|c| c.set(c.get() + ns));
+}
+
+// Seconds elapsed since the given timestamp, 0 if the clock was set back
+pub fn elapsed(since: u64) -> f64 {
+    timestamp().saturating_sub(since) as f64 / 1_000_000_000.0
 }
 
 impl Future for SleepFuture {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
-        if SleepFuture::get_ts() >= self.end_ts {
+        if timestamp() >= self.end_ts {
             Poll::Ready(())
         } else {
             Poll::Pending
@@ -41,6 +72,19 @@ impl Future for SleepFuture {
 
 pub fn sleep(t: f64) -> SleepFuture {
     SleepFuture {
-        end_ts: SleepFuture::get_ts() + (t * 1_000_000_000.0) as u64,
+        end_ts: timestamp() + (t * 1_000_000_000.0) as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_between_dates() {
+        assert_eq!(days(1970, 1, 1) - days(1900, 1, 1), 25567);
+        assert_eq!(days(2000, 3, 1) - days(2000, 2, 28), 2);
+        assert_eq!(days(1900, 3, 1) - days(1900, 2, 28), 1);
+        assert_eq!(days(2026, 1, 1) - days(2025, 12, 31), 1);
     }
 }
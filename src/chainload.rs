@@ -0,0 +1,200 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::c_void, ptr};
+use log::info;
+use uefi::{
+    boot::{self, LoadImageSource},
+    guid,
+    proto::loaded_image::LoadedImage,
+    Guid, Handle, Status,
+};
+use uefi_raw::protocol::{
+    device_path::{DevicePathProtocol, DeviceSubType, DeviceType},
+    media::LoadFile2Protocol,
+};
+
+use crate::network::ethernet;
+
+// Vendor media device path Linux looks for to find its initrd
+const LINUX_EFI_INITRD_MEDIA_GUID: Guid = guid!("5568e427-68fc-4f3d-ac74-ca555231cc68");
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options<'a> {
+    // Passed to the image as its load options, the kernel command line
+    pub command_line: &'a str,
+    pub initrd: Option<&'a [u8]>,
+}
+
+// Loads a PE/COFF image from memory and starts it. The network interface
+// is shut down first, so the app cannot use it again if the image returns.
+pub fn chainload(image: &[u8], options: &Options, network: &ethernet::Service) -> uefi::Result {
+    let handle = boot::load_image(
+        boot::image_handle(),
+        LoadImageSource::FromBuffer {
+            buffer: image,
+            file_path: None,
+        },
+    )?;
+
+    // UCS-2 with a terminating null, kept alive until the image returns
+    let command_line: Vec<u16> = options
+        .command_line
+        .encode_utf16()
+        .chain(core::iter::once(0))
+        .collect();
+    if !options.command_line.is_empty() {
+        let result =
+            boot::open_protocol_exclusive::<LoadedImage>(handle).map(|mut loaded| unsafe {
+                loaded.set_load_options(
+                    command_line.as_ptr().cast(),
+                    (command_line.len() * 2) as u32,
+                )
+            });
+        if let Err(e) = result {
+            let _ = boot::unload_image(handle);
+            return Err(e);
+        }
+    }
+
+    let initrd = match options.initrd {
+        Some(data) => match Initrd::install(data) {
+            Ok(i) => Some(i),
+            Err(e) => {
+                let _ = boot::unload_image(handle);
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+
+    if let Err(e) = network.shutdown() {
+        info!("failed to shut down the network interface: {:?}", e);
+    }
+
+    info!("starting image of {} bytes", image.len());
+    let result = boot::start_image(handle);
+    info!("image returned: {:?}", result);
+
+    if let Some(i) = initrd {
+        i.uninstall();
+    }
+    drop(command_line);
+    result
+}
+
+// Device path with a single vendor media node and the end node
+#[repr(C, packed)]
+struct InitrdDevicePath {
+    vendor: DevicePathProtocol,
+    guid: [u8; 16],
+    end: DevicePathProtocol,
+}
+
+// LoadFile2 instance that hands out the initrd. The protocol must come
+// first so the interface pointer can be cast back.
+#[repr(C)]
+struct InitrdLoader {
+    protocol: LoadFile2Protocol,
+    data: *const u8,
+    len: usize,
+}
+
+// Initrd published on its own handle, the way the Linux EFI stub expects
+// it (LINUX_EFI_INITRD_MEDIA_GUID)
+struct Initrd {
+    handle: Handle,
+    device_path: Box<InitrdDevicePath>,
+    loader: Box<InitrdLoader>,
+}
+
+impl Initrd {
+    fn install(data: &[u8]) -> uefi::Result<Initrd> {
+        let device_path = Box::new(InitrdDevicePath {
+            vendor: DevicePathProtocol {
+                major_type: DeviceType::MEDIA,
+                sub_type: DeviceSubType::MEDIA_VENDOR,
+                length: 20u16.to_le_bytes(),
+            },
+            guid: LINUX_EFI_INITRD_MEDIA_GUID.to_bytes(),
+            end: DevicePathProtocol {
+                major_type: DeviceType::END,
+                sub_type: DeviceSubType::END_ENTIRE,
+                length: 4u16.to_le_bytes(),
+            },
+        });
+        let loader = Box::new(InitrdLoader {
+            protocol: LoadFile2Protocol {
+                load_file: load_initrd,
+            },
+            data: data.as_ptr(),
+            len: data.len(),
+        });
+
+        let handle = unsafe {
+            boot::install_protocol_interface(
+                None,
+                &DevicePathProtocol::GUID,
+                ptr::from_ref(device_path.as_ref()).cast::<c_void>(),
+            )?
+        };
+        let installed = unsafe {
+            boot::install_protocol_interface(
+                Some(handle),
+                &LoadFile2Protocol::GUID,
+                ptr::from_ref(loader.as_ref()).cast::<c_void>(),
+            )
+        };
+        let initrd = Initrd {
+            handle,
+            device_path,
+            loader,
+        };
+        match installed {
+            Ok(_) => Ok(initrd),
+            Err(e) => {
+                initrd.uninstall();
+                Err(e)
+            }
+        }
+    }
+
+    fn uninstall(self) {
+        unsafe {
+            let _ = boot::uninstall_protocol_interface(
+                self.handle,
+                &LoadFile2Protocol::GUID,
+                ptr::from_ref(self.loader.as_ref()).cast::<c_void>(),
+            );
+            let _ = boot::uninstall_protocol_interface(
+                self.handle,
+                &DevicePathProtocol::GUID,
+                ptr::from_ref(self.device_path.as_ref()).cast::<c_void>(),
+            );
+        }
+    }
+}
+
+unsafe extern "efiapi" fn load_initrd(
+    this: *mut LoadFile2Protocol,
+    _file_path: *const DevicePathProtocol,
+    boot_policy: bool,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status {
+    if this.is_null() || buffer_size.is_null() {
+        return Status::INVALID_PARAMETER;
+    }
+    // LoadFile2 is never used for boot selection
+    if boot_policy {
+        return Status::UNSUPPORTED;
+    }
+    let loader = &*this.cast::<InitrdLoader>();
+    if buffer.is_null() || *buffer_size < loader.len {
+        *buffer_size = loader.len;
+        return Status::BUFFER_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(loader.data, buffer.cast::<u8>(), loader.len);
+    *buffer_size = loader.len;
+    Status::SUCCESS
+}
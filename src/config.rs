@@ -0,0 +1,475 @@
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use log::LevelFilter;
+use uefi::{
+    boot, guid,
+    proto::{loaded_image::LoadedImage, media::file::FileMode},
+    runtime::{self, VariableVendor},
+    CStr16, Status,
+};
+
+use crate::{
+    crash, fs, logger,
+    network::{ethernet, ip},
+};
+
+// File on the app's own volume
+pub const PATH: &str = "config.ini";
+// Vendor of the variables overriding the file, named like the keys
+pub const VARIABLE_VENDOR: VariableVendor =
+    VariableVendor(guid!("5b0e8f2c-6a3d-4b71-9c1e-2f8d4a6b7c90"));
+
+const MAX_FILE_SIZE: u64 = 65536;
+const MAX_VARIABLE_SIZE: usize = 1024;
+// Seconds before a reset when the panic policy does not say
+const DEFAULT_RESET_DELAY: u32 = 10;
+
+// Every key, as section.name
+const KEYS: [&str; 19] = [
+    "network.mode",
+    "network.hostname",
+    "network.vlan",
+    "network.address",
+    "network.netmask",
+    "network.gateway",
+    "network.dns",
+    "services.shell",
+    "services.status",
+    "services.status_token",
+    "services.lldp",
+    "services.ping",
+    "services.traceroute",
+    "services.download",
+    "services.fetch",
+    "log.level",
+    "log.syslog",
+    "log.file",
+    "panic.policy",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Static,
+    // address, netmask and gateway come from a DHCP server
+    Dhcp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub mode: Mode,
+    // Name advertised to neighbors
+    pub hostname: String,
+    // VLAN the stack runs on, None for untagged frames
+    pub vlan: Option<ethernet::Tag>,
+    pub address: ip::Address,
+    pub netmask: ip::Address,
+    pub gateway: ip::Address,
+    pub dns: Vec<ip::Address>,
+    pub shell: bool,
+    // Port of the http status server, None to not start it
+    pub status: Option<u16>,
+    // Bearer token the status server's control endpoints require, they
+    // are disabled without one
+    pub status_token: Option<String>,
+    // Advertise us and keep a neighbor table with lldp
+    pub lldp: bool,
+    pub ping: Vec<ip::Address>,
+    pub traceroute: Vec<ip::Address>,
+    // Urls fetched over http at startup
+    pub download: Vec<String>,
+    // server/path pairs fetched over tftp into the volume at startup
+    pub fetch: Vec<(ip::Address, String)>,
+    pub log_level: LevelFilter,
+    // Syslog server and port records are sent to
+    pub syslog: Option<(ip::Address, u16)>,
+    // File on the volume records are appended to
+    pub log_file: Option<String>,
+    pub panic: crash::Policy,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: Mode::Static,
+            hostname: String::from("rust-uefi-app"),
+            vlan: None,
+            address: ip::Address([172, 23, 71, 108]),
+            netmask: ip::Address([255, 255, 255, 0]),
+            gateway: ip::Address([172, 23, 71, 1]),
+            dns: vec![ip::Address([8, 8, 8, 8])],
+            shell: true,
+            status: Some(80),
+            status_token: None,
+            lldp: true,
+            ping: Vec::new(),
+            traceroute: Vec::new(),
+            download: Vec::new(),
+            fetch: Vec::new(),
+            log_level: LevelFilter::Info,
+            syslog: None,
+            log_file: None,
+            panic: crash::Policy::Halt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    // Line number in the file
+    File(usize),
+    Variable,
+    LoadOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub origin: Origin,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.origin {
+            Origin::File(line) => write!(f, "{} line {}: ", PATH, line)?,
+            Origin::Variable => write!(f, "variable {}: ", self.key)?,
+            Origin::LoadOptions => write!(f, "load option {}: ", self.key)?,
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl Config {
+    // Reads the file, then applies variables and load options on top.
+    // Invalid entries are reported and leave the previous value in place.
+    pub fn load() -> (Config, Vec<Error>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+
+        match read_file() {
+            Ok(Some(text)) => config.apply_file(&text, &mut errors),
+            Ok(None) => {}
+            Err(e) => errors.push(Error {
+                origin: Origin::File(0),
+                key: String::new(),
+                message: format!("cannot be read: {:?}", e),
+            }),
+        }
+        for key in KEYS {
+            if let Some(value) = read_variable(key) {
+                config.apply(key, &value, Origin::Variable, &mut errors);
+            }
+        }
+        if let Some(options) = load_options() {
+            config.apply_load_options(&options, &mut errors);
+        }
+
+        (config, errors)
+    }
+
+    // INI style: [section] headers, key = value lines, # or ; comments
+    pub fn apply_file(&mut self, text: &str, errors: &mut Vec<Error>) {
+        let mut section = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let origin = Origin::File(i + 1);
+            let line = line.split(['#', ';']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                match name.strip_suffix(']') {
+                    Some(name) => section = name.trim().to_ascii_lowercase(),
+                    None => errors.push(Error {
+                        origin,
+                        key: String::new(),
+                        message: format!("unterminated section header {}", line),
+                    }),
+                }
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    let key = format!("{}.{}", section, key.trim().to_ascii_lowercase());
+                    self.apply(&key, value.trim(), origin, errors);
+                }
+                None => errors.push(Error {
+                    origin,
+                    key: String::new(),
+                    message: format!("expected key = value, found {}", line),
+                }),
+            }
+        }
+    }
+
+    // Space separated section.key=value words, others (like the image
+    // path the shell passes first) are ignored
+    pub fn apply_load_options(&mut self, options: &str, errors: &mut Vec<Error>) {
+        for word in options.split_whitespace() {
+            if let Some((key, value)) = word.split_once('=') {
+                let key = key.to_ascii_lowercase();
+                self.apply(&key, value, Origin::LoadOptions, errors);
+            }
+        }
+    }
+
+    fn apply(&mut self, key: &str, value: &str, origin: Origin, errors: &mut Vec<Error>) {
+        if let Err(message) = self.set(key, value) {
+            errors.push(Error {
+                origin,
+                key: key.into(),
+                message,
+            });
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "network.mode" => {
+                self.mode = match value.to_ascii_lowercase().as_str() {
+                    "static" => Mode::Static,
+                    "dhcp" => Mode::Dhcp,
+                    _ => return Err(format!("mode must be static or dhcp, not {}", value)),
+                }
+            }
+            "network.hostname" => {
+                // a single dns label
+                if value.is_empty()
+                    || value.len() > 63
+                    || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    return Err(format!("{} is not a valid hostname", value));
+                }
+                self.hostname = value.into();
+            }
+            "network.vlan" => {
+                self.vlan = match value.split_once(':') {
+                    _ if value == "off" => None,
+                    None => Some(ethernet::Tag::new(vlan_id(value)?, 0)),
+                    Some((id, priority)) => Some(ethernet::Tag::new(
+                        vlan_id(id)?,
+                        priority
+                            .parse()
+                            .ok()
+                            .filter(|p| *p < 8)
+                            .ok_or(format!("{} is not a priority from 0 to 7", priority))?,
+                    )),
+                }
+            }
+            "network.address" => self.address = address(value)?,
+            "network.netmask" => {
+                let netmask = address(value)?;
+                // the ones must be contiguous
+                if u32::from(netmask).leading_ones() + u32::from(netmask).trailing_zeros() != 32 {
+                    return Err(format!("{} is not a valid netmask", value));
+                }
+                self.netmask = netmask;
+            }
+            "network.gateway" => self.gateway = address(value)?,
+            "network.dns" => self.dns = list(value, address)?,
+            "services.shell" => self.shell = boolean(value)?,
+            "services.status" => {
+                self.status = match value {
+                    "off" | "false" | "no" => None,
+                    _ => Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|p| *p != 0)
+                            .ok_or(format!("status must be a port or off, not {}", value))?,
+                    ),
+                }
+            }
+            "services.status_token" => {
+                self.status_token = match value {
+                    "" => None,
+                    _ if value.contains(char::is_whitespace) => {
+                        return Err(String::from("status_token must not contain spaces"))
+                    }
+                    _ => Some(value.to_string()),
+                }
+            }
+            "services.lldp" => self.lldp = boolean(value)?,
+            "services.ping" => self.ping = list(value, address)?,
+            "services.traceroute" => self.traceroute = list(value, address)?,
+            "services.download" => {
+                self.download = list(value, |u| {
+                    if u.starts_with("http://") {
+                        Ok(u.to_string())
+                    } else {
+                        Err(format!("{} is not an http url", u))
+                    }
+                })?
+            }
+            "services.fetch" => {
+                self.fetch = list(value, |f| {
+                    let (server, path) = f
+                        .split_once('/')
+                        .ok_or(format!("{} is not server/path", f))?;
+                    Ok((address(server)?, path.to_string()))
+                })?
+            }
+            "log.level" => {
+                self.log_level = value
+                    .parse()
+                    .map_err(|_| format!("unknown log level {}", value))?
+            }
+            "log.syslog" => {
+                self.syslog = match value {
+                    "" | "off" => None,
+                    _ => {
+                        let (server, port) = match value.split_once(':') {
+                            Some((s, p)) => {
+                                (s, p.parse().map_err(|_| format!("{} is not a port", p))?)
+                            }
+                            None => (value, logger::SYSLOG_PORT),
+                        };
+                        Some((address(server)?, port))
+                    }
+                }
+            }
+            "log.file" => {
+                self.log_file = match value {
+                    "" | "off" => None,
+                    _ => Some(value.into()),
+                }
+            }
+            "panic.policy" => {
+                self.panic = match value.split_once(':') {
+                    None if value == "halt" => crash::Policy::Halt,
+                    None if value == "exit" => crash::Policy::Exit,
+                    None if value == "reset" => crash::Policy::Reset(DEFAULT_RESET_DELAY),
+                    Some(("reset", seconds)) => crash::Policy::Reset(
+                        seconds
+                            .parse()
+                            .map_err(|_| format!("{} is not a number of seconds", seconds))?,
+                    ),
+                    _ => {
+                        return Err(format!(
+                            "policy must be halt, exit, reset or reset:<seconds>, not {}",
+                            value
+                        ))
+                    }
+                }
+            }
+            _ => return Err(format!("unknown key {}", key)),
+        }
+        Ok(())
+    }
+}
+
+fn address(value: &str) -> Result<ip::Address, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not an ipv4 address", value))
+}
+
+fn vlan_id(value: &str) -> Result<u16, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|id| (1..4095).contains(id))
+        .ok_or(format!("{} is not a vlan id from 1 to 4094", value))
+}
+
+fn boolean(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("{} is not a boolean", value)),
+    }
+}
+
+// Comma separated values, empty for none
+fn list<T>(value: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(parse)
+        .collect()
+}
+
+// None when there is no file
+fn read_file() -> uefi::Result<Option<String>> {
+    let mut file = match fs::open(PATH, FileMode::Read) {
+        Ok(f) => f,
+        Err(e) if e.status() == Status::NOT_FOUND => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let size = fs::size(&mut file)?;
+    if size > MAX_FILE_SIZE {
+        return Err(Status::BAD_BUFFER_SIZE.into());
+    }
+    let mut data = vec![0; size as usize];
+    let n = file.read(&mut data).map_err(|e| e.status())?;
+    data.truncate(n);
+    String::from_utf8(data)
+        .map(Some)
+        .map_err(|_| Status::INVALID_PARAMETER.into())
+}
+
+fn read_variable(key: &str) -> Option<String> {
+    let mut name = [0; 32];
+    let name = CStr16::from_str_with_buf(key, &mut name).ok()?;
+    let mut buf = [0; MAX_VARIABLE_SIZE];
+    let (data, _) = runtime::get_variable(name, &VARIABLE_VENDOR, &mut buf).ok()?;
+    let value = core::str::from_utf8(data).ok()?;
+    // values written by tools often carry a terminating null
+    Some(value.trim_end_matches('\0').trim().into())
+}
+
+fn load_options() -> Option<String> {
+    let image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+    let options = image.load_options_as_cstr16().ok()?;
+    Some(format!("{}", options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_and_load_options() {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        config.apply_file(
+            "# lab machine\n[network]\nmode = dhcp\naddress = 10.0.0.2 ; static fallback\n\
+             netmask = 255.0.255.0\ndns = 1.1.1.1, 9.9.9.9\n[services]\nstatus = off\n\
+             status_token = s3cret\n\
+             fetch = 10.0.0.1/boot/vmlinuz\ncolour = blue\n",
+            &mut errors,
+        );
+        config.apply_load_options(
+            "\\EFI\\app.efi log.level=debug network.gateway=10.0.0.1 network.vlan=42:5 \
+             network.hostname=lab-7",
+            &mut errors,
+        );
+
+        assert_eq!(config.mode, Mode::Dhcp);
+        assert_eq!(config.address, ip::Address([10, 0, 0, 2]));
+        assert_eq!(config.netmask, ip::Address([255, 255, 255, 0]));
+        assert_eq!(config.gateway, ip::Address([10, 0, 0, 1]));
+        assert_eq!(
+            config.dns,
+            vec![ip::Address([1, 1, 1, 1]), ip::Address([9, 9, 9, 9])]
+        );
+        assert_eq!(config.status, None);
+        assert_eq!(config.status_token.as_deref(), Some("s3cret"));
+        assert_eq!(
+            config.fetch,
+            vec![(ip::Address([10, 0, 0, 1]), "boot/vmlinuz".into())]
+        );
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(config.vlan, Some(ethernet::Tag::new(42, 5)));
+        assert_eq!(config.hostname, "lab-7");
+        let lines: Vec<Origin> = errors.into_iter().map(|e| e.origin).collect();
+        assert_eq!(lines, vec![Origin::File(5), Origin::File(11)]);
+    }
+}
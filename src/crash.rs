@@ -0,0 +1,155 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc};
+use core::{
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering},
+};
+use log::error;
+use uefi::{
+    boot,
+    runtime::{self, ResetType, VariableAttributes},
+    CStr16, Status,
+};
+
+use crate::{
+    asyn::{self, Executor, SimpleExecutor},
+    config, logger, memory,
+};
+
+// Variable holding the report of the last panic, under the config vendor
+const VARIABLE: &str = "LastPanic";
+const MAX_REPORT: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    // Stall forever, leaving the message on screen
+    Halt,
+    // Reset the machine after this many seconds
+    Reset(u32),
+    // Return an error status to whatever started the app
+    Exit,
+}
+
+const HALT: u32 = 0;
+const RESET: u32 = 1;
+const EXIT: u32 = 2;
+
+static POLICY: AtomicU32 = AtomicU32::new(HALT);
+static RESET_DELAY: AtomicU32 = AtomicU32::new(0);
+// The panic handler is left out of host tests
+#[cfg_attr(test, allow(dead_code))]
+static PANICKING: AtomicBool = AtomicBool::new(false);
+static EXECUTOR: AtomicPtr<SimpleExecutor> = AtomicPtr::new(ptr::null_mut());
+
+pub fn set_policy(policy: Policy) {
+    let v = match policy {
+        Policy::Halt => HALT,
+        Policy::Reset(seconds) => {
+            RESET_DELAY.store(seconds, Ordering::Relaxed);
+            RESET
+        }
+        Policy::Exit => EXIT,
+    };
+    POLICY.store(v, Ordering::Relaxed);
+}
+
+#[cfg_attr(test, allow(dead_code))]
+fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        RESET => Policy::Reset(RESET_DELAY.load(Ordering::Relaxed)),
+        EXIT => Policy::Exit,
+        _ => Policy::Halt,
+    }
+}
+
+// The executor whose tasks are counted in reports, it must outlive its
+// registration
+pub fn set_executor(executor: Option<&Arc<SimpleExecutor>>) {
+    let p = executor.map_or(ptr::null_mut(), |e| Arc::as_ptr(e).cast_mut());
+    EXECUTOR.store(p, Ordering::Relaxed);
+}
+
+// Logs the report a previous panic left behind and clears it
+pub fn report_previous() {
+    let mut name = [0; 16];
+    let Ok(name) = CStr16::from_str_with_buf(VARIABLE, &mut name) else {
+        return;
+    };
+    let mut buf = [0; MAX_REPORT];
+    if let Ok((data, _)) = runtime::get_variable(name, &config::VARIABLE_VENDOR, &mut buf) {
+        error!("previous run panicked: {}", String::from_utf8_lossy(data));
+        let _ = runtime::delete_variable(name, &config::VARIABLE_VENDOR);
+    }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub fn handle(info: &PanicInfo) -> ! {
+    // a panic while reporting one goes straight to the policy
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        report(info);
+    }
+    asyn::console_flush();
+
+    match policy() {
+        Policy::Halt => {
+            // Give the user some time to read the message
+            loop {
+                boot::stall(10_000_000);
+            }
+        }
+        Policy::Reset(seconds) => {
+            boot::stall(seconds as usize * 1_000_000);
+            runtime::reset(ResetType::COLD, Status::ABORTED, None)
+        }
+        Policy::Exit => unsafe {
+            boot::exit(boot::image_handle(), Status::ABORTED, 0, ptr::null_mut())
+        },
+    }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+fn report(info: &PanicInfo) {
+    error!("[PANIC]: {}", info);
+
+    let executor = EXECUTOR.load(Ordering::Relaxed);
+    let tasks = match unsafe { executor.as_ref() } {
+        Some(e) => format!("{}", e.task_count()),
+        None => String::from("unknown"),
+    };
+    let stats = memory::stats();
+    let context = format!(
+        "tasks: {}, pages: {}, allocated: {} bytes, allocations: {}, deallocations: {}",
+        tasks, stats.pages, stats.allocated, stats.allocations, stats.deallocations
+    );
+    error!("[PANIC]: {}", context);
+
+    let unsent = logger::flush();
+    if unsent > 0 {
+        error!("[PANIC]: {} records were not sent to syslog", unsent);
+    }
+
+    let mut record = format!("{}; {}", info, context);
+    if record.len() > MAX_REPORT {
+        let mut end = MAX_REPORT;
+        while !record.is_char_boundary(end) {
+            end -= 1;
+        }
+        record.truncate(end);
+    }
+    let mut name = [0; 16];
+    if let Ok(name) = CStr16::from_str_with_buf(VARIABLE, &mut name) {
+        let attributes = VariableAttributes::NON_VOLATILE
+            | VariableAttributes::BOOTSERVICE_ACCESS
+            | VariableAttributes::RUNTIME_ACCESS;
+        if let Err(e) = runtime::set_variable(
+            name,
+            &config::VARIABLE_VENDOR,
+            attributes,
+            record.as_bytes(),
+        ) {
+            error!("[PANIC]: failed to save the report: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+extern crate alloc;
+
+use alloc::vec;
+use uefi::{
+    boot,
+    proto::media::file::{Directory, File, FileAttribute, FileMode, RegularFile},
+    CStr16, Status,
+};
+
+// Root directory of the volume the app was loaded from
+pub fn root() -> uefi::Result<Directory> {
+    boot::get_image_file_system(boot::image_handle())?.open_volume()
+}
+
+// Opens an existing regular file, paths may use either slash
+pub fn open(path: &str, mode: FileMode) -> uefi::Result<RegularFile> {
+    let path = path.replace('/', "\\");
+    let mut buf = vec![0; path.len() + 1];
+    let path = CStr16::from_str_with_buf(&path, &mut buf)
+        .map_err(|_| uefi::Error::from(Status::INVALID_PARAMETER))?;
+    root()?
+        .open(path, mode, FileAttribute::empty())?
+        .into_regular_file()
+        .ok_or(Status::INVALID_PARAMETER.into())
+}
+
+// Creates an empty file, replacing any existing one
+pub fn create(path: &str) -> uefi::Result<RegularFile> {
+    if let Ok(existing) = open(path, FileMode::ReadWrite) {
+        existing.delete()?;
+    }
+    open(path, FileMode::CreateReadWrite)
+}
+
+pub fn size(file: &mut RegularFile) -> uefi::Result<u64> {
+    let position = file.get_position()?;
+    file.set_position(RegularFile::END_OF_FILE)?;
+    let size = file.get_position()?;
+    file.set_position(position)?;
+    Ok(size)
+}
@@ -1,88 +1,266 @@
 extern crate alloc;
 
-use alloc::sync::Arc;
-use log::info;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use log::{info, LevelFilter};
+use uefi::Status;
 
 use crate::{
-    asyn::{sleep, Executor, SimpleExecutor, Task},
-    network::{arp, ethernet, icmp, ip},
+    asyn::{self, sleep, Executor, SimpleExecutor, Task},
+    config::{self, Config},
+    crash, fs, logger,
+    network::{
+        http, icmp, ip, lldp,
+        stack::{Addressing, NetworkStack, Protocols},
+        tftp,
+    },
+    shell,
+    shutdown::{self, Exit, Shutdown},
+    status,
 };
 
-pub fn init() {
+pub fn init() -> Status {
     uefi::helpers::init().unwrap();
+    logger::init(LevelFilter::Info);
     // disable watchdog
     uefi::boot::set_watchdog_timer(0, 0xDEADBEEF, None).unwrap();
 
     let executor = Arc::new(SimpleExecutor::new());
-    executor.spawn(Task::new(init_async(executor.clone())));
+    let shutdown = Arc::new(Shutdown::new());
+    crash::set_executor(Some(&executor));
+    asyn::console_start(executor.clone());
+    executor.spawn(Task::new(init_async(executor.clone(), shutdown.clone())));
 
     executor.run();
+    crash::set_executor(None);
+    asyn::console_flush();
+    match shutdown.exit() {
+        Some(exit) => shutdown::finish(exit),
+        // the console task never ends, so the executor only stops once a
+        // shutdown was requested
+        None => unreachable!("executor stopped without a shutdown request"),
+    }
 }
 
-async fn init_async(executor: Arc<dyn Executor>) {
-    let mut network_service = ethernet::Service::new();
-    let mac_address = network_service.mac_address();
-    log::info!("mac address: {:?}", mac_address);
-
-    let ip_address = ip::Address([172, 23, 71, 108]);
-    log::info!("ip address: {:?}", ip_address);
-    let netmask = ip::Address([255, 255, 255, 0]);
-    log::info!("netmask: {:?}", netmask);
-    let gateway = ip::Address([172, 23, 71, 1]);
-    log::info!("gateway: {:?}", gateway);
-
-    let arp_service = Arc::new(arp::Service::new(
-        ip_address,
-        mac_address,
-        &mut network_service,
-    ));
-    let ip_service = Arc::new(ip::Service::new(
-        &mut network_service,
-        arp_service.clone(),
-        ip_address,
-        netmask,
-        gateway,
-    ));
-    let mut icmp_service = icmp::Service::new(ip_service.clone()).await;
-
-    executor.spawn(Task::new(hello_world(0)));
-    executor.spawn(Task::new(hello_world(1)));
-    executor.spawn(Task::new(hello_world(2)));
-
-    let pinger1 = icmp_service.open(ip::Address([172, 23, 71, 14]));
-    executor.spawn(Task::new(ping(pinger1)));
-
-    let pinger2 = icmp_service.open(ip::Address([8, 8, 8, 8]));
-    executor.spawn(Task::new(ping(pinger2)));
-
-    // let pinger3 = icmp_service.open(ip::Address([172, 23, 71, 213]));
-    // executor.spawn(Task::new(ping(pinger3)));
-
-    network_service.start(executor.clone());
-    arp_service.start(executor.clone());
-    ip_service.start(executor.clone());
-    icmp_service.start(executor.clone());
+async fn init_async(executor: Arc<dyn Executor>, shutdown: Arc<Shutdown>) {
+    let (config, errors) = Config::load();
+    for e in errors.iter() {
+        log::error!("config: {}", e);
+    }
+    log::set_max_level(config.log_level);
+    crash::set_policy(config.panic);
+    if let Some(path) = config.log_file.as_ref() {
+        if let Err(e) = logger::set_file(path) {
+            log::error!("failed to open log file {}: {:?}", path, e);
+        }
+    }
+    if config.syslog.is_none() {
+        logger::disable_syslog();
+    }
+    crash::report_previous();
+
+    let addressing = match config.mode {
+        config::Mode::Static => {
+            log::info!("ip address: {:?}", config.address);
+            log::info!("netmask: {:?}", config.netmask);
+            log::info!("gateway: {:?}", config.gateway);
+            Addressing::Static {
+                address: config.address,
+                netmask: config.netmask,
+                gateway: config.gateway,
+            }
+        }
+        config::Mode::Dhcp => {
+            log::info!("ip address: dhcp");
+            Addressing::Dhcp
+        }
+    };
+    if let Some(tag) = config.vlan {
+        log::info!("vlan: {} priority {}", tag.id, tag.priority);
+    }
+
+    let stack = NetworkStack::builder()
+        .vlan(config.vlan)
+        .addressing(addressing)
+        .dns(config.dns.clone())
+        .protocols(Protocols {
+            wol: true,
+            lldp: config.lldp,
+            ..Protocols::default()
+        })
+        .lldp(lldp::Options {
+            system_name: config.hostname.clone(),
+            ..lldp::Options::default()
+        })
+        .build()
+        .await;
+    let stack = match stack {
+        Ok(stack) => Arc::new(stack),
+        Err(e) => {
+            log::error!("failed to set up the network stack: {}", e);
+            shutdown.request(Exit::Return(Status::ABORTED));
+            return executor.stop();
+        }
+    };
+    log::info!("mac address: {:?}", stack.mac_address());
+    // all enabled above
+    let icmp_service = stack.icmp().unwrap().clone();
+    let udp_service = stack.udp().unwrap().clone();
+    let tcp_service = stack.tcp().unwrap().clone();
+    let resolver = stack.resolver().unwrap().clone();
+
+    if let Some((server, port)) = config.syslog {
+        logger::start_syslog(
+            udp_service.open(0).await.expect("no free udp port"),
+            server,
+            port,
+            executor.clone(),
+        );
+    }
+
+    for address in config.ping.iter() {
+        let pinger = icmp_service.open(*address).await;
+        executor.spawn(Task::new(ping(pinger)));
+    }
+
+    for address in config.traceroute.iter() {
+        let tracer = icmp_service
+            .traceroute(*address, icmp::Options::default())
+            .await;
+        executor.spawn(Task::new(traceroute(tracer)));
+    }
+
+    if !config.download.is_empty() {
+        let http_client = http::Client::new(tcp_service.clone(), Some(resolver.clone()));
+        executor.spawn(Task::new(download(http_client, config.download.clone())));
+    }
+
+    if !config.fetch.is_empty() {
+        let tftp_client = tftp::Client::new(
+            udp_service.open(0).await.expect("no free udp port"),
+            tftp::Options::default(),
+        );
+        executor.spawn(Task::new(fetch(tftp_client, config.fetch.clone())));
+    }
+
+    if let Some(port) = config.status {
+        match tcp_service.listen(port).await {
+            Ok(listener) => {
+                let status = Arc::new(status::Status::new(
+                    executor.clone(),
+                    stack.mac_address(),
+                    stack.counters(),
+                    stack.arp().clone(),
+                    stack.ip().clone(),
+                    icmp_service.clone(),
+                    udp_service.clone(),
+                    tcp_service.clone(),
+                    config.status_token.clone(),
+                ));
+                if config.status_token.is_none() {
+                    info!("no services.status_token, status control endpoints disabled");
+                }
+                http::Server::new(listener, status).start(executor.clone());
+            }
+            Err(e) => info!("port {}: {}, status server not started", port, e),
+        }
+    }
+
+    if config.shell {
+        let mut shell = shell::Shell::new();
+        shell::register_builtins(
+            &mut shell,
+            Arc::new(shell::Services {
+                executor: executor.clone(),
+                stack: stack.clone(),
+                shutdown: shutdown.clone(),
+            }),
+        );
+        shell.start(executor.clone());
+    }
+
+    stack.start(executor.clone());
+    executor.spawn(Task::new(stop(executor.clone(), stack, shutdown)));
 }
 
-async fn hello_world(x: u64) {
-    loop {
-        info!("hello world {}", x);
-        sleep(60.0).await;
+// Waits for a shutdown request, then lets queued frames go out, stops the
+// interface and ends the executor, which cancels the other tasks
+async fn stop(executor: Arc<dyn Executor>, stack: Arc<NetworkStack>, shutdown: Arc<Shutdown>) {
+    shutdown.requested().await;
+    info!("shutting down");
+    // syslog goes out over the stack
+    logger::disable_syslog();
+    if let Err(e) = stack.shutdown().await {
+        log::error!("failed to stop the interface: {}", e);
     }
+    logger::flush();
+    executor.stop();
 }
 
 async fn ping(pinger: icmp::Socket) {
     loop {
-        pinger.send(&[1, 2, 3]).await;
+        if let Err(e) = pinger.send(&[1, 2, 3]).await {
+            info!("ping {:?}: {}", pinger.ip_address(), e);
+            sleep(1.0).await;
+            continue;
+        }
         match pinger.receive(1.0).await {
-            Some(response) => {
+            Ok(response) => {
                 info!("response from {:?}", response.ip.source_address());
                 sleep(1.0).await
             }
-            None => info!(
+            Err(_) => info!(
                 "timeout waiting for pinger response from {:?}",
                 pinger.ip_address(),
             ),
         };
     }
 }
+
+async fn traceroute(tracer: icmp::Traceroute) {
+    info!("traceroute to {:?}", tracer.destination());
+    let hops = match tracer.run().await {
+        Ok(hops) => hops,
+        Err(e) => return info!("traceroute to {:?} failed: {}", tracer.destination(), e),
+    };
+    for hop in hops {
+        for probe in hop.probes.iter() {
+            match probe {
+                Some(p) => info!("{:>2} {:?} {:.3} ms", hop.ttl, p.address, p.rtt * 1000.0),
+                None => info!("{:>2} *", hop.ttl),
+            }
+        }
+        if hop.reached() {
+            info!("reached {:?} in {} hops", tracer.destination(), hop.ttl);
+        }
+    }
+}
+
+// Stores each file in the root of the volume under its own name
+async fn fetch(client: tftp::Client, files: Vec<(ip::Address, String)>) {
+    for (server, path) in files {
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        let mut file = match fs::create(name) {
+            Ok(f) => f,
+            Err(e) => {
+                info!("failed to create {}: {:?}", name, e);
+                continue;
+            }
+        };
+        match client.read(server, &path, &mut file).await {
+            Ok(size) => info!("fetched {} bytes of {} from {:?}", size, path, server),
+            Err(e) => info!("tftp of {} from {:?} failed: {}", path, server, e),
+        }
+    }
+}
+
+async fn download(client: http::Client, urls: Vec<String>) {
+    for url in urls {
+        let mut data = Vec::new();
+        let request = http::Request::get(&url);
+        let mut progress = |received, total| info!("{}: {} of {:?} bytes", url, received, total);
+        match client.send(&request, &mut data, &mut progress).await {
+            Ok(response) => info!("{}: {} {}", url, response.status, response.reason),
+            Err(e) => info!("{} failed: {:?}", url, e),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+extern crate alloc;
+
+use alloc::{collections::VecDeque, format, string::String, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use uefi::{
+    proto::media::file::{File, FileMode, RegularFile},
+    runtime,
+};
+
+use crate::{
+    asyn, fs,
+    network::{ip, udp},
+};
+
+pub const SYSLOG_PORT: u16 = 514;
+const APP_NAME: &str = "rust-uefi-app";
+// Records kept for syslog until the network is up, the oldest are dropped
+const MAX_PENDING: usize = 256;
+// How often the syslog task looks for new records
+const SYSLOG_INTERVAL: f64 = 0.05;
+// How long the syslog task waits after a failed send
+const SYSLOG_RETRY_INTERVAL: f64 = 1.0;
+
+static LOGGER: Logger = Logger::new();
+
+// One formatted record waiting for the syslog task
+struct Pending {
+    severity: u8,
+    timestamp: String,
+    message: String,
+}
+
+struct State {
+    file: Option<RegularFile>,
+    pending: VecDeque<Pending>,
+}
+
+// Queues records for the console and tees them to a file on the volume and
+// to a syslog server. The state is only touched with the lock held; a
+// record logged while it is taken (from inside the logger) only reaches
+// the console.
+struct Logger {
+    lock: AtomicBool,
+    // Set while the syslog task sends, records from the network stack
+    // during that time would feed back into it
+    sending: AtomicBool,
+    // Cleared when syslog is disabled so nothing piles up
+    syslog: AtomicBool,
+    state: UnsafeCell<State>,
+}
+
+// UEFI boot services run on a single processor
+unsafe impl Sync for Logger {}
+unsafe impl Send for Logger {}
+
+impl Logger {
+    const fn new() -> Logger {
+        Logger {
+            lock: AtomicBool::new(false),
+            sending: AtomicBool::new(false),
+            syslog: AtomicBool::new(true),
+            state: UnsafeCell::new(State {
+                file: None,
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> Option<R> {
+        if self.lock.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        let r = f(unsafe { &mut *self.state.get() });
+        self.lock.store(false, Ordering::Release);
+        Some(r)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "[{:>5}]: {:>12}@{:03}: {}",
+            record.level(),
+            record.file().unwrap_or(""),
+            record.line().unwrap_or(0),
+            record.args()
+        );
+        asyn::console_write(&line);
+        asyn::console_write("\n");
+
+        let forward = self.syslog.load(Ordering::Relaxed) && !self.sending.load(Ordering::Relaxed);
+        self.with_state(|state| {
+            if let Some(file) = state.file.as_mut() {
+                let written = file
+                    .write(line.as_bytes())
+                    .and_then(|_| file.write(b"\n"))
+                    .map_err(|e| e.status())
+                    .and_then(|_| file.flush().map_err(|e| e.status()));
+                // a failing file is given up instead of failing every record
+                if written.is_err() {
+                    state.file = None;
+                }
+            }
+            if forward {
+                if state.pending.len() == MAX_PENDING {
+                    state.pending.pop_front();
+                }
+                state.pending.push_back(Pending {
+                    severity: severity(record.level()),
+                    timestamp: timestamp(),
+                    message: format!("{}: {}", record.target(), record.args()),
+                });
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Appends records to a file on the app's volume
+pub fn set_file(path: &str) -> uefi::Result {
+    let mut file = match fs::open(path, FileMode::ReadWrite) {
+        Ok(f) => f,
+        Err(_) => fs::create(path)?,
+    };
+    file.set_position(RegularFile::END_OF_FILE)?;
+    LOGGER.with_state(|state| state.file = Some(file));
+    Ok(())
+}
+
+// Flushes the file, returning how many records syslog has not sent yet
+pub fn flush() -> usize {
+    LOGGER
+        .with_state(|state| {
+            if let Some(file) = state.file.as_mut() {
+                let _ = file.flush();
+            }
+            state.pending.len()
+        })
+        .unwrap_or(0)
+}
+
+// Stops buffering records for a syslog server that will never be set
+pub fn disable_syslog() {
+    LOGGER.syslog.store(false, Ordering::Relaxed);
+    LOGGER.with_state(|state| state.pending.clear());
+}
+
+// Stops logging for good, for when the console and the volume go away
+pub fn close() {
+    log::set_max_level(LevelFilter::Off);
+    disable_syslog();
+    LOGGER.with_state(|state| state.file = None);
+}
+
+// Sends buffered and new records to a syslog server (RFC 5424 over
+// RFC 5426 udp)
+pub fn start_syslog(
+    socket: udp::Socket,
+    server: ip::Address,
+    port: u16,
+    e: Arc<dyn asyn::Executor>,
+) {
+    e.spawn(asyn::Task::new(task_syslog(&LOGGER, socket, server, port)));
+}
+
+async fn task_syslog(logger: &'static Logger, socket: udp::Socket, server: ip::Address, port: u16) {
+    loop {
+        let next = logger
+            .with_state(|state| state.pending.pop_front())
+            .flatten();
+        let Some(p) = next else {
+            asyn::sleep(SYSLOG_INTERVAL).await;
+            continue;
+        };
+        // PRI is facility local0 (16) and the severity
+        let message = format!(
+            "<{}>1 {} {} {} - - - {}",
+            16 * 8 + p.severity as u16,
+            p.timestamp,
+            socket.address(),
+            APP_NAME,
+            p.message
+        );
+        logger.sending.store(true, Ordering::Relaxed);
+        let sent = socket.send(server, port, message.as_bytes()).await;
+        logger.sending.store(false, Ordering::Relaxed);
+        // kept for another try unless newer records filled its place,
+        // the server may not be resolved yet
+        if sent.is_err() {
+            logger.with_state(|state| {
+                if state.pending.len() < MAX_PENDING {
+                    state.pending.push_front(p);
+                }
+            });
+            asyn::sleep(SYSLOG_RETRY_INTERVAL).await;
+        }
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// RFC 3339 time from the firmware clock, the nil value when unavailable
+#[cfg(not(any(test, fuzzing)))]
+fn timestamp() -> String {
+    match runtime::get_time() {
+        Ok(t) => rfc3339(&t),
+        Err(_) => String::from("-"),
+    }
+}
+
+// Host tests have no firmware clock
+#[cfg(any(test, fuzzing))]
+fn timestamp() -> String {
+    String::from("-")
+}
+
+fn rfc3339(t: &runtime::Time) -> String {
+    let offset = match t.time_zone() {
+        Some(minutes) => format!(
+            "{}{:02}:{:02}",
+            if minutes < 0 { '-' } else { '+' },
+            minutes.unsigned_abs() / 60,
+            minutes.unsigned_abs() % 60
+        ),
+        // the clock is local time at an unknown offset (RFC 3339, 4.3)
+        None => String::from("-00:00"),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        t.year(),
+        t.month(),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+        offset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        ethernet::MacAddress,
+        sim::{Link, Simulator},
+    };
+    use alloc::{boxed::Box, vec::Vec};
+    use uefi::runtime::{Daylight, Time, TimeParams};
+
+    const HOST: ip::Address = ip::Address([10, 0, 0, 1]);
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 2]);
+
+    fn log(logger: &Logger, level: Level, message: &str) {
+        log::set_max_level(LevelFilter::Trace);
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target("app")
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    fn pending(logger: &Logger) -> Vec<String> {
+        logger
+            .with_state(|state| state.pending.iter().map(|p| p.message.clone()).collect())
+            .unwrap()
+    }
+
+    #[test]
+    fn sends_rfc_5424_lines() {
+        let logger: &'static Logger = Box::leak(Box::new(Logger::new()));
+        log(logger, Level::Error, "disk on fire");
+        log(logger, Level::Info, "all good");
+        let time = Time::new(TimeParams {
+            year: 2026,
+            month: 3,
+            day: 7,
+            hour: 9,
+            minute: 5,
+            second: 1,
+            nanosecond: 0,
+            time_zone: Some(-90),
+            daylight: Daylight::empty(),
+        })
+        .unwrap();
+        logger.with_state(|state| state.pending[1].timestamp = rfc3339(&time));
+
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let host = sim.host(
+            switch,
+            MacAddress([2, 0, 0, 0, 0, 1]),
+            HOST,
+            Link::default(),
+        );
+        let server = sim.host(
+            switch,
+            MacAddress([2, 0, 0, 0, 0, 2]),
+            SERVER,
+            Link::default(),
+        );
+        let (socket, receiver) = sim.block_on(async move {
+            let socket = host.udp.open(0).await.unwrap();
+            (socket, server.udp.open(SYSLOG_PORT).await.unwrap())
+        });
+        sim.spawn(task_syslog(logger, socket, SERVER, SYSLOG_PORT));
+        let lines = sim.block_on(async move {
+            let mut lines = Vec::new();
+            for _ in 0..2 {
+                let p = receiver.receive(5.0).await.unwrap();
+                lines.push(String::from_utf8(p.data().to_vec()).unwrap());
+            }
+            lines
+        });
+        // local0 (16) with the severities of error (3) and info (6)
+        assert_eq!(
+            lines,
+            [
+                "<131>1 - 10.0.0.1 rust-uefi-app - - - app: disk on fire",
+                "<134>1 2026-03-07T09:05:01-01:30 10.0.0.1 rust-uefi-app - - - app: all good",
+            ]
+        );
+    }
+
+    #[test]
+    fn records_logged_while_sending_are_not_queued() {
+        let logger = Logger::new();
+        log(&logger, Level::Warn, "before");
+        logger.sending.store(true, Ordering::Relaxed);
+        log(&logger, Level::Warn, "from the network stack");
+        logger.sending.store(false, Ordering::Relaxed);
+        log(&logger, Level::Warn, "after");
+        assert_eq!(pending(&logger), ["app: before", "app: after"]);
+    }
+
+    #[test]
+    fn drops_the_oldest_pending_records() {
+        let logger = Logger::new();
+        for i in 0..MAX_PENDING + 2 {
+            log(&logger, Level::Debug, &format!("{}", i));
+        }
+        let pending = pending(&logger);
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending[0], "app: 2");
+        assert_eq!(
+            pending[MAX_PENDING - 1],
+            format!("app: {}", MAX_PENDING + 1)
+        );
+    }
+}
@@ -1,12 +1,25 @@
 #![cfg_attr(not(test), no_main)]
 #![cfg_attr(not(test), no_std)]
+// Boot services run on one processor, the executor shares its services
+// through Arcs without them having to be Send or Sync
+#![allow(clippy::arc_with_non_send_sync)]
 
 // mod arp;
 mod asyn;
+mod chainload;
+mod config;
+mod crash;
+mod fs;
 // mod icmp;
 mod init;
+mod logger;
 // mod ip;
+mod memory;
+mod metrics;
 mod network;
+mod shell;
+mod shutdown;
+mod status;
 
 #[cfg(not(test))]
 mod main_uefi;
@@ -19,5 +32,5 @@ mod panic_handler;
 
 #[cfg(test)]
 fn main() {
-    init::init();
+    let _ = init::init();
 }
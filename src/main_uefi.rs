@@ -2,6 +2,5 @@ use uefi::prelude::*;
 
 #[entry]
 fn uefi_entry() -> Status {
-    crate::init::init();
-    Status::SUCCESS
+    crate::init::init()
 }
@@ -0,0 +1,43 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// Allocator statistics, recorded by the global allocator
+static PAGES: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    // Pages taken from the firmware
+    pub pages: u64,
+    // Bytes handed out, freed memory is not reused
+    pub allocated: u64,
+    pub allocations: u64,
+    pub deallocations: u64,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        pages: PAGES.load(Ordering::Relaxed),
+        allocated: ALLOCATED.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+// Called by the allocator, which host tests do without
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn record_pages(n: usize) {
+    PAGES.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn record_alloc(size: usize) {
+    ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn record_dealloc() {
+    DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
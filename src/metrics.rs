@@ -0,0 +1,201 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+pub type Labels = &'static [(&'static str, &'static str)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    kind: Kind,
+    value: AtomicU64,
+}
+
+// Monotonic count of events
+#[derive(Clone)]
+pub struct Counter(Arc<Metric>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+    pub fn add(&self, n: u64) {
+        self.0.value.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn get(&self) -> u64 {
+        self.0.value.load(Ordering::Relaxed)
+    }
+}
+
+// Value that goes up and down
+#[derive(Clone)]
+pub struct Gauge(Arc<Metric>);
+
+impl Gauge {
+    pub fn set(&self, v: u64) {
+        self.0.value.store(v, Ordering::Relaxed);
+    }
+    // Keeps the highest value seen, for high-watermarks
+    pub fn set_max(&self, v: u64) {
+        self.0.value.fetch_max(v, Ordering::Relaxed);
+    }
+}
+
+// The metrics of one network stack, in registration order. Services
+// register when they are created; the same name and labels give back the
+// same metric.
+#[derive(Default)]
+pub struct Registry {
+    lock: AtomicBool,
+    metrics: UnsafeCell<Vec<Arc<Metric>>>,
+}
+
+// UEFI boot services run on a single processor
+unsafe impl Sync for Registry {}
+unsafe impl Send for Registry {}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    fn with_metrics<R>(&self, f: impl FnOnce(&mut Vec<Arc<Metric>>) -> R) -> R {
+        if self.lock.swap(true, Ordering::Acquire) {
+            panic!("metrics registry used re-entrantly");
+        }
+        let r = f(unsafe { &mut *self.metrics.get() });
+        self.lock.store(false, Ordering::Release);
+        r
+    }
+
+    fn register(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: Labels,
+        kind: Kind,
+    ) -> Arc<Metric> {
+        self.with_metrics(|metrics| {
+            if let Some(m) = metrics
+                .iter()
+                .find(|m| m.name == name && m.labels == labels)
+            {
+                assert_eq!(m.kind, kind, "metric {} registered as two kinds", name);
+                return m.clone();
+            }
+            let m = Arc::new(Metric {
+                name,
+                help,
+                labels,
+                kind,
+                value: AtomicU64::new(0),
+            });
+            metrics.push(m.clone());
+            m
+        })
+    }
+
+    pub fn counter(&self, name: &'static str, help: &'static str, labels: Labels) -> Counter {
+        Counter(self.register(name, help, labels, Kind::Counter))
+    }
+
+    pub fn gauge(&self, name: &'static str, help: &'static str, labels: Labels) -> Gauge {
+        Gauge(self.register(name, help, labels, Kind::Gauge))
+    }
+
+    // One "name{labels} value" line per metric
+    pub fn text(&self) -> String {
+        let metrics = self.with_metrics(|metrics| metrics.clone());
+        let mut out = String::new();
+        for m in metrics.iter() {
+            let _ = writeln!(out, "{} {}", series(m), m.value.load(Ordering::Relaxed));
+        }
+        out
+    }
+
+    // Prometheus text exposition format, version 0.0.4
+    pub fn prometheus(&self) -> String {
+        let mut metrics = self.with_metrics(|metrics| metrics.clone());
+        // samples of one family have to be adjacent
+        metrics.sort_by_key(|m| m.name);
+        let mut out = String::new();
+        let mut family = "";
+        for m in metrics.iter() {
+            if m.name != family {
+                family = m.name;
+                let kind = match m.kind {
+                    Kind::Counter => "counter",
+                    Kind::Gauge => "gauge",
+                };
+                let _ = writeln!(out, "# HELP {} {}", m.name, m.help);
+                let _ = writeln!(out, "# TYPE {} {}", m.name, kind);
+            }
+            let _ = writeln!(out, "{} {}", series(m), m.value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+fn series(m: &Metric) -> String {
+    if m.labels.is_empty() {
+        return String::from(m.name);
+    }
+    let labels: Vec<String> = m
+        .labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v))
+        .collect();
+    format!("{}{{{}}}", m.name, labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition() {
+        let registry = Registry::new();
+        let sent = registry.counter("test_frames_total", "Frames.", &[("direction", "sent")]);
+        let received =
+            registry.counter("test_frames_total", "Frames.", &[("direction", "received")]);
+        let depth = registry.gauge("test_queue_high_watermark", "Deepest queue.", &[]);
+        sent.add(3);
+        received.inc();
+        depth.set_max(5);
+        depth.set_max(2);
+        // registering again shares the value
+        registry
+            .counter("test_frames_total", "Frames.", &[("direction", "sent")])
+            .inc();
+
+        let text = registry.prometheus();
+        assert!(text.contains(
+            "# HELP test_frames_total Frames.\n# TYPE test_frames_total counter\n\
+             test_frames_total{direction=\"sent\"} 4\n\
+             test_frames_total{direction=\"received\"} 1\n"
+        ));
+        assert!(
+            text.contains("# TYPE test_queue_high_watermark gauge\ntest_queue_high_watermark 5\n")
+        );
+
+        // another stack counts on its own
+        let other = Registry::new();
+        other
+            .counter("test_frames_total", "Frames.", &[("direction", "sent")])
+            .inc();
+        assert_eq!(sent.get(), 4);
+        assert_eq!(other.text(), "test_frames_total{direction=\"sent\"} 1\n");
+    }
+}
@@ -2,39 +2,76 @@ extern crate alloc;
 
 use super::{HardwareType, Operation, Packet};
 use crate::{
-    asyn,
-    network::{ethernet, ip},
+    asyn, metrics,
+    network::{ethernet, ip, Error},
 };
 use alloc::sync::Arc;
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use hashbrown::HashMap;
 
 pub struct Service {
-    pub ip: ip::Address,
+    ip: AtomicU32,
     pub socket: ethernet::Socket,
-    pub mac: ethernet::MacAddress,
+    mac: Cell<ethernet::MacAddress>,
     pub table: asyn::Mutex<HashMap<ip::Address, ethernet::MacAddress>>,
+    hits: metrics::Counter,
+    misses: metrics::Counter,
+    entries: metrics::Gauge,
 }
 
 impl Service {
     pub fn new(
         ip: ip::Address,
         mac: ethernet::MacAddress,
-        service: &mut ethernet::Service,
+        service: &impl ethernet::Network,
     ) -> Service {
+        let metrics = service.metrics();
         Service {
-            ip: ip,
+            ip: AtomicU32::new(ip.into()),
             socket: service.open(ethernet::Type::ARP),
-            mac: mac,
+            mac: Cell::new(mac),
             table: asyn::Mutex::new(HashMap::new()),
+            hits: metrics.counter(
+                "arp_lookups_total",
+                "ARP table lookups, by result.",
+                &[("result", "hit")],
+            ),
+            misses: metrics.counter(
+                "arp_lookups_total",
+                "ARP table lookups, by result.",
+                &[("result", "miss")],
+            ),
+            entries: metrics.gauge("arp_table_entries", "Addresses in the ARP table.", &[]),
         }
     }
-    pub async fn lookup(&self, addr: &ip::Address) -> Option<ethernet::MacAddress> {
+    pub fn mac(&self) -> ethernet::MacAddress {
+        self.mac.get()
+    }
+    pub fn set_mac(&self, mac: ethernet::MacAddress) {
+        self.mac.set(mac);
+    }
+    pub fn address(&self) -> ip::Address {
+        self.ip.load(Ordering::Relaxed).into()
+    }
+    pub fn set_address(&self, ip: ip::Address) {
+        self.ip.store(ip.into(), Ordering::Relaxed);
+    }
+
+    // The mac of an address in the table. On a miss a request goes out
+    // and the caller tries again later.
+    pub async fn lookup(&self, addr: &ip::Address) -> Result<ethernet::MacAddress, Error> {
         let table = self.table.lock().await;
 
         match table.get(addr).copied() {
-            Some(a) => Some(a),
+            Some(a) => {
+                self.hits.inc();
+                Ok(a)
+            }
             None => {
-                // TODO: wait for response
+                self.misses.inc();
                 let mut request_raw = ethernet::Packet::new();
                 request_raw.set_mac_destination(ethernet::MAC_BROADCAST);
                 request_raw.set_size(28);
@@ -44,14 +81,14 @@ impl Service {
                 request.set_hardware_len(6);
                 request.set_protocol_len(4);
                 request.set_operation(Operation::REQUEST);
-                request.set_sender_hardware_address(&self.mac);
-                request.set_sender_protocol_address(&self.ip);
+                request.set_sender_hardware_address(&self.mac());
+                request.set_sender_protocol_address(&self.address());
                 request.set_target_hardware_address(&ethernet::MAC_BROADCAST);
                 request.set_target_protocol_address(addr);
 
-                self.socket.send(request_raw);
+                self.socket.send(request_raw)?;
 
-                None
+                Err(Error::Unresolved(*addr))
             }
         }
     }
@@ -63,6 +100,10 @@ impl Service {
     async fn task_receive(self: Arc<Self>) {
         loop {
             let mut received_raw = self.socket.receive().await;
+            // too short for ipv4 over ethernet
+            if received_raw.size() < 28 {
+                continue;
+            }
             let received = Packet(received_raw.data_mut());
 
             if received.hardware_type() != HardwareType::ETHERNET {
@@ -77,12 +118,13 @@ impl Service {
                 received.sender_protocol_address(),
                 received.sender_hardware_address(),
             );
+            self.entries.set(table.len() as u64);
 
             if received.operation() != Operation::REQUEST {
                 continue;
             }
 
-            if received.target_protocol_address() == self.ip {
+            if received.target_protocol_address() == self.address() {
                 let mut response_raw = ethernet::Packet::new();
                 response_raw.set_mac_destination(received.sender_hardware_address());
                 response_raw.set_size(28);
@@ -92,12 +134,13 @@ impl Service {
                 response.set_hardware_len(6);
                 response.set_protocol_len(4);
                 response.set_operation(Operation::RESPONSE);
-                response.set_sender_hardware_address(&self.mac);
-                response.set_sender_protocol_address(&self.ip);
+                response.set_sender_hardware_address(&self.mac());
+                response.set_sender_protocol_address(&self.address());
                 response.set_target_hardware_address(&received.sender_hardware_address());
                 response.set_target_protocol_address(&received.sender_protocol_address());
 
-                self.socket.send(response_raw);
+                // with the send queue full the peer asks again
+                let _ = self.socket.send(response_raw);
             }
         }
     }
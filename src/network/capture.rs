@@ -0,0 +1,423 @@
+extern crate alloc;
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+use log::info;
+use uefi::{proto::media::file::File, runtime};
+
+use super::{ethernet, ip, tcp, udp};
+use crate::{asyn, fs};
+
+// Memory kept for the ring buffer, the oldest frames are dropped first
+const RING_BYTES: usize = 1 << 20;
+// Blocks waiting for the stream task, more are dropped
+const STREAM_QUEUE: usize = 256;
+const SNAP_LENGTH: u32 = 65535;
+// Frame bytes kept when streaming over udp so a block fits one datagram,
+// 1472 bytes of payload less 44 bytes of block overhead
+const UDP_SNAP_LENGTH: usize = 1428;
+
+const SECTION_HEADER: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const LINKTYPE_ETHERNET: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+// Frames must match every field that is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub ether_type: Option<ethernet::Type>,
+    // Source or destination of ipv4 packets
+    pub host: Option<ip::Address>,
+}
+
+impl Filter {
+    fn matches(&self, frame: &[u8]) -> bool {
+        let Some((ether_type, payload)) = payload(frame) else {
+            return false;
+        };
+        if self.ether_type.is_some_and(|t| t != ether_type) {
+            return false;
+        }
+        match self.host {
+            Some(host) => {
+                ether_type == ethernet::Type::IPV4
+                    && (ipv4_address(payload, 12) == Some(host)
+                        || ipv4_address(payload, 16) == Some(host))
+            }
+            None => true,
+        }
+    }
+}
+
+// Receiver of a live copy of the capture
+struct Stream {
+    queue: Arc<ArrayQueue<Vec<u8>>>,
+    closed: Arc<AtomicBool>,
+    // Collector address and port, its own traffic is not captured
+    collector: (ip::Address, u16),
+    // Frames are cut to this many bytes in blocks for the stream
+    snap: usize,
+}
+
+struct State {
+    filter: Filter,
+    ring: VecDeque<Vec<u8>>,
+    ring_bytes: usize,
+    stream: Option<Stream>,
+}
+
+// Records frames passing through ethernet::Service in pcapng format
+pub struct Capture {
+    mac: ethernet::MacAddress,
+    running: AtomicBool,
+    captured: AtomicU64,
+    dropped: AtomicU64,
+    state: asyn::Mutex<State>,
+}
+
+impl Capture {
+    pub fn new(mac: ethernet::MacAddress) -> Capture {
+        Capture {
+            mac,
+            running: AtomicBool::new(false),
+            captured: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            state: asyn::Mutex::new(State {
+                filter: Filter::default(),
+                ring: VecDeque::new(),
+                ring_bytes: 0,
+                stream: None,
+            }),
+        }
+    }
+
+    // Starts over with an empty ring buffer
+    pub async fn start(&self, filter: Filter) {
+        let mut state = self.state.lock().await;
+        state.filter = filter;
+        state.ring.clear();
+        state.ring_bytes = 0;
+        self.captured.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.running.store(true, Ordering::Relaxed);
+    }
+
+    // Stops capturing and ends any stream, the ring buffer is kept
+    pub async fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(s) = self.state.lock().await.stream.take() {
+            s.closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    // Frames captured and frames the stream could not keep up with
+    pub fn counts(&self) -> (u64, u64) {
+        (
+            self.captured.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+        )
+    }
+
+    pub async fn filter(&self) -> Filter {
+        self.state.lock().await.filter
+    }
+
+    // The ring buffer as a pcapng file
+    pub async fn snapshot(&self) -> Vec<u8> {
+        let mut data = self.header();
+        for block in self.state.lock().await.ring.iter() {
+            data.extend_from_slice(block);
+        }
+        data
+    }
+
+    // Writes the ring buffer to a file on the volume, returning its size
+    pub async fn save(&self, path: &str) -> uefi::Result<usize> {
+        let data = self.snapshot().await;
+        let mut file = fs::create(path)?;
+        file.write(&data).map_err(|e| e.status())?;
+        file.flush()?;
+        Ok(data.len())
+    }
+
+    // Sends the capture to a collector, one block per datagram
+    pub async fn stream_udp(
+        self: &Arc<Self>,
+        socket: udp::Socket,
+        address: ip::Address,
+        port: u16,
+        e: Arc<dyn asyn::Executor>,
+    ) {
+        let (queue, closed) = self.subscribe((address, port), UDP_SNAP_LENGTH).await;
+        let header = self.header();
+        e.spawn(asyn::Task::new(async move {
+            // without the header the blocks cannot be read
+            while let Err(e) = socket.send(address, port, &header).await {
+                if !e.is_transient() {
+                    return;
+                }
+                asyn::sleep(0.1).await;
+            }
+            while let Some(block) = next_block(&queue, &closed).await {
+                let _ = socket.send(address, port, &block).await;
+            }
+        }));
+    }
+
+    // Sends the capture as a pcapng byte stream over a tcp connection
+    pub async fn stream_tcp(
+        self: &Arc<Self>,
+        tcp: &Arc<tcp::Service>,
+        address: ip::Address,
+        port: u16,
+        e: Arc<dyn asyn::Executor>,
+    ) -> Result<(), tcp::Error> {
+        let mut stream = tcp.connect(address, port).await?;
+        let (queue, closed) = self.subscribe((address, port), usize::MAX).await;
+        let header = self.header();
+        e.spawn(asyn::Task::new(async move {
+            let mut result = stream.write(&header).await;
+            while result.is_ok() {
+                match next_block(&queue, &closed).await {
+                    Some(block) => result = stream.write(&block).await,
+                    None => break,
+                }
+            }
+            if let Err(e) = result {
+                info!("capture stream to {:?} ended: {:?}", address, e);
+                closed.store(true, Ordering::Relaxed);
+            }
+            stream.close().await;
+        }));
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        collector: (ip::Address, u16),
+        snap: usize,
+    ) -> (Arc<ArrayQueue<Vec<u8>>>, Arc<AtomicBool>) {
+        let stream = Stream {
+            queue: Arc::new(ArrayQueue::new(STREAM_QUEUE)),
+            closed: Arc::new(AtomicBool::new(false)),
+            collector,
+            snap,
+        };
+        let r = (stream.queue.clone(), stream.closed.clone());
+        let mut state = self.state.lock().await;
+        if let Some(old) = state.stream.replace(stream) {
+            old.closed.store(true, Ordering::Relaxed);
+        }
+        r
+    }
+
+    // Tap for ethernet::Service
+    pub(super) async fn record(&self, frame: &[u8], direction: Direction) {
+        if !self.running() {
+            return;
+        }
+        let mut state = self.state.lock().await;
+        if !state.filter.matches(frame) {
+            return;
+        }
+        if let Some(s) = state.stream.as_ref() {
+            if s.closed.load(Ordering::Relaxed) {
+                state.stream = None;
+            } else if is_collector_traffic(frame, s.collector) {
+                return;
+            }
+        }
+
+        let block = enhanced_packet(frame, direction, usize::MAX);
+        self.captured.fetch_add(1, Ordering::Relaxed);
+        if let Some(s) = state.stream.as_ref() {
+            let streamed = if frame.len() > s.snap {
+                enhanced_packet(frame, direction, s.snap)
+            } else {
+                block.clone()
+            };
+            if s.queue.push(streamed).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        state.ring_bytes += block.len();
+        state.ring.push_back(block);
+        while state.ring_bytes > RING_BYTES {
+            match state.ring.pop_front() {
+                Some(b) => state.ring_bytes -= b.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Section header and the description of the single interface
+    fn header(&self) -> Vec<u8> {
+        let mut section = Vec::new();
+        section.extend_from_slice(&0x1a2b3c4du32.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        // section length unknown
+        section.extend_from_slice(&u64::MAX.to_le_bytes());
+        let mut data = block(SECTION_HEADER, &section);
+
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&SNAP_LENGTH.to_le_bytes());
+        // if_name, if_MACaddr, end of options
+        option(&mut interface, 2, b"snp0");
+        option(&mut interface, 6, &self.mac.0);
+        option(&mut interface, 0, &[]);
+        data.extend_from_slice(&block(INTERFACE_DESCRIPTION, &interface));
+        data
+    }
+}
+
+async fn next_block(queue: &Arc<ArrayQueue<Vec<u8>>>, closed: &AtomicBool) -> Option<Vec<u8>> {
+    loop {
+        if let Some(b) = queue.pop() {
+            return Some(b);
+        }
+        if closed.load(Ordering::Relaxed) {
+            return None;
+        }
+        asyn::sleep(0.01).await;
+    }
+}
+
+// Block for a frame, keeping at most snap bytes of it
+fn enhanced_packet(frame: &[u8], direction: Direction, snap: usize) -> Vec<u8> {
+    let time = unix_time_micros();
+    let captured = &frame[..frame.len().min(snap)];
+    let mut body = Vec::with_capacity(captured.len() + 32);
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((time >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(time as u32).to_le_bytes());
+    body.extend_from_slice(&(captured.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    body.extend_from_slice(captured);
+    pad(&mut body);
+    // epb_flags with the direction, end of options
+    let flags: u32 = match direction {
+        Direction::Inbound => 1,
+        Direction::Outbound => 2,
+    };
+    option(&mut body, 2, &flags.to_le_bytes());
+    option(&mut body, 0, &[]);
+    block(ENHANCED_PACKET, &body)
+}
+
+fn block(typ: u32, body: &[u8]) -> Vec<u8> {
+    let length = (12 + body.len()) as u32;
+    let mut b = Vec::with_capacity(length as usize);
+    b.extend_from_slice(&typ.to_le_bytes());
+    b.extend_from_slice(&length.to_le_bytes());
+    b.extend_from_slice(body);
+    b.extend_from_slice(&length.to_le_bytes());
+    b
+}
+
+fn option(data: &mut Vec<u8>, code: u16, value: &[u8]) {
+    data.extend_from_slice(&code.to_le_bytes());
+    data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    data.extend_from_slice(value);
+    pad(data);
+}
+
+fn pad(data: &mut Vec<u8>) {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+}
+
+// Ether type and payload, behind the VLAN tag if there is one
+fn payload(frame: &[u8]) -> Option<(ethernet::Type, &[u8])> {
+    let ether_type = |o: usize| {
+        frame
+            .get(o..o + 2)
+            .map(|t| ethernet::Type(u16::from_be_bytes([t[0], t[1]])))
+    };
+    match ether_type(12)? {
+        ethernet::Type::VLAN => Some((ether_type(16)?, frame.get(18..)?)),
+        t => Some((t, frame.get(14..)?)),
+    }
+}
+
+fn ipv4_address(packet: &[u8], offset: usize) -> Option<ip::Address> {
+    Some(ip::Address(
+        packet.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Udp or tcp packets to or from the collector port
+fn is_collector_traffic(frame: &[u8], (address, port): (ip::Address, u16)) -> bool {
+    let Some((ethernet::Type::IPV4, packet)) = payload(frame) else {
+        return false;
+    };
+    let header = match packet.first() {
+        Some(b) => (b & 0xf) as usize * 4,
+        None => return false,
+    };
+    let ports = |o: usize| {
+        packet
+            .get(o..o + 2)
+            .map(|p| u16::from_be_bytes([p[0], p[1]]))
+    };
+    let (source_port, destination_port) = (ports(header), ports(header + 2));
+    (ipv4_address(packet, 16) == Some(address) && destination_port == Some(port))
+        || (ipv4_address(packet, 12) == Some(address) && source_port == Some(port))
+}
+
+// Microseconds since 1970 from the firmware clock, read as utc
+fn unix_time_micros() -> u64 {
+    let Ok(t) = runtime::get_time() else {
+        return 0;
+    };
+    let days = asyn::days(t.year(), t.month(), t.day()).saturating_sub(asyn::days(1970, 1, 1));
+    let seconds =
+        days * 86400 + t.hour() as u64 * 3600 + t.minute() as u64 * 60 + t.second() as u64;
+    seconds * 1_000_000 + t.nanosecond() as u64 / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_and_blocks() {
+        let mut frame = [0u8; 34];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[26..30].copy_from_slice(&[10, 0, 0, 1]);
+        frame[30..34].copy_from_slice(&[10, 0, 0, 2]);
+
+        let mut filter = Filter {
+            ether_type: Some(ethernet::Type::IPV4),
+            host: Some(ip::Address([10, 0, 0, 2])),
+        };
+        assert!(filter.matches(&frame));
+        filter.ether_type = Some(ethernet::Type::ARP);
+        assert!(!filter.matches(&frame));
+        filter.ether_type = None;
+        filter.host = Some(ip::Address([10, 0, 0, 3]));
+        assert!(!filter.matches(&frame));
+
+        // lengths at both ends, body padded to 32 bits
+        let mut body = Vec::from([1u8, 2, 3]);
+        pad(&mut body);
+        let b = block(ENHANCED_PACKET, &body);
+        assert_eq!(b.len(), 16);
+        assert_eq!(b[4..8], 16u32.to_le_bytes());
+        assert_eq!(b[12..16], 16u32.to_le_bytes());
+    }
+}
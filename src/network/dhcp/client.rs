@@ -0,0 +1,452 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use log::info;
+
+use super::message::{Message, MessageType, OptionCode, SERVER_PORT};
+use crate::{
+    asyn,
+    network::{self, dns, ethernet, ip, udp},
+};
+
+const BROADCAST: ip::Address = ip::Address([255; 4]);
+const INITIAL_TIMEOUT: f64 = 2.0;
+const ROUNDS: usize = 4;
+// Seconds before trying again after failing to get or renew a lease
+const RETRY: f64 = 10.0;
+// When an acknowledgement leaves the lease time out
+const DEFAULT_LEASE_TIME: u32 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Timeout,
+    // The server answered with a NAK
+    Refused,
+    Network(network::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => f.write_str("no answer from a server"),
+            Error::Refused => f.write_str("refused by the server"),
+            Error::Network(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub address: ip::Address,
+    pub netmask: ip::Address,
+    // 0.0.0.0 without a router
+    pub gateway: ip::Address,
+    pub server: ip::Address,
+    pub dns: Vec<ip::Address>,
+    // Seconds from when it was acknowledged until it is renewed, until
+    // any server is asked and until it runs out
+    pub renewal: u32,
+    pub rebinding: u32,
+    pub time: u32,
+    // asyn::timestamp() of the acknowledgement
+    pub acquired: u64,
+}
+
+impl Lease {
+    fn new(ack: &Message, server: ip::Address) -> Lease {
+        let time = ack.lease_time.unwrap_or(DEFAULT_LEASE_TIME);
+        Lease {
+            address: ack.your_address,
+            // servers send it unless asked not to, a /24 is the usual guess
+            netmask: ack.netmask.unwrap_or(ip::Address([255, 255, 255, 0])),
+            gateway: ack.router.unwrap_or(ip::Address([0; 4])),
+            server: ack.server.unwrap_or(server),
+            dns: ack.dns.clone(),
+            renewal: ack.renewal_time.unwrap_or(time / 2),
+            rebinding: ack.rebinding_time.unwrap_or(time / 8 * 7),
+            time,
+            acquired: asyn::timestamp(),
+        }
+    }
+}
+
+// Gets the address of the ip service from a DHCP server (RFC 2131) and,
+// once started, keeps renewing it
+pub struct Client {
+    socket: asyn::Mutex<udp::Socket>,
+    mac: ethernet::MacAddress,
+    ip: Arc<ip::Service>,
+    // Gets the name servers of the lease
+    resolver: Option<Arc<dns::Resolver>>,
+    lease: asyn::Mutex<Option<Lease>>,
+    next_xid: AtomicU32,
+    started: AtomicBool,
+}
+
+impl Client {
+    // The socket is bound to the client port, 68
+    pub fn new(
+        socket: udp::Socket,
+        mac: ethernet::MacAddress,
+        ip: Arc<ip::Service>,
+        resolver: Option<Arc<dns::Resolver>>,
+    ) -> Client {
+        let seed = u32::from_be_bytes([mac.0[2], mac.0[3], mac.0[4], mac.0[5]]);
+        Client {
+            socket: asyn::Mutex::new(socket),
+            mac,
+            ip,
+            resolver,
+            lease: asyn::Mutex::new(None),
+            next_xid: AtomicU32::new(seed ^ asyn::timestamp() as u32),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    // Keeps a lease from then on, only the first call does
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        e.spawn(asyn::Task::new(self.task_lease()));
+    }
+
+    pub async fn lease(&self) -> Option<Lease> {
+        self.lease.lock().await.clone()
+    }
+
+    // Gets a new lease, broadcasting a discover and requesting the first
+    // offer, and configures the ip service with it
+    pub async fn configure(&self) -> Result<Lease, Error> {
+        let socket = self.socket.lock().await;
+        let xid = self.next_xid.fetch_add(1, Ordering::Relaxed);
+        let discover = self.message(MessageType::DISCOVER, xid);
+        let offer = self
+            .exchange(&socket, &discover, BROADCAST, |m| {
+                m.typ == MessageType::OFFER && m.server.is_some()
+            })
+            .await?;
+
+        let mut request = self.message(MessageType::REQUEST, xid);
+        request.requested_address = Some(offer.your_address);
+        request.server = offer.server;
+        let ack = self
+            .exchange(&socket, &request, BROADCAST, |m| {
+                matches!(m.typ, MessageType::ACK | MessageType::NAK)
+            })
+            .await?;
+        drop(socket);
+        if ack.typ == MessageType::NAK {
+            return Err(Error::Refused);
+        }
+        let lease = Lease::new(&ack, offer.server.unwrap());
+        self.apply(&lease).await;
+        Ok(lease)
+    }
+
+    // Extends the lease with the server it came from, or any server
+    async fn renew(&self, lease: &Lease, server: ip::Address) -> Result<Lease, Error> {
+        let socket = self.socket.lock().await;
+        let xid = self.next_xid.fetch_add(1, Ordering::Relaxed);
+        let mut request = self.message(MessageType::REQUEST, xid);
+        request.client_address = lease.address;
+        request.broadcast = false;
+        let ack = self
+            .exchange(&socket, &request, server, |m| {
+                matches!(m.typ, MessageType::ACK | MessageType::NAK)
+            })
+            .await?;
+        drop(socket);
+        if ack.typ == MessageType::NAK {
+            return Err(Error::Refused);
+        }
+        let lease = Lease::new(&ack, lease.server);
+        self.apply(&lease).await;
+        Ok(lease)
+    }
+
+    fn message(&self, typ: MessageType, xid: u32) -> Message {
+        let mut m = Message::new(typ, xid, self.mac);
+        // the reply has to reach us before we have an address
+        m.broadcast = true;
+        m.parameters = Vec::from([
+            OptionCode::SUBNET_MASK,
+            OptionCode::ROUTER,
+            OptionCode::DNS,
+            OptionCode::LEASE_TIME,
+            OptionCode::SERVER_ID,
+            OptionCode::RENEWAL_TIME,
+            OptionCode::REBINDING_TIME,
+        ]);
+        m
+    }
+
+    // Sends the message until a server answers it, doubling the timeout
+    // after each try
+    async fn exchange(
+        &self,
+        socket: &udp::Socket,
+        message: &Message,
+        to: ip::Address,
+        accept: impl Fn(&Message) -> bool,
+    ) -> Result<Message, Error> {
+        let data = message.build();
+        let mut timeout = INITIAL_TIMEOUT;
+        for _ in 0..ROUNDS {
+            // a message that does not go out times out like a lost one
+            match socket.send(to, SERVER_PORT, &data).await {
+                Err(e) if !e.is_transient() => return Err(Error::Network(e)),
+                _ => {}
+            }
+            let start = asyn::timestamp();
+            loop {
+                let remaining = timeout - asyn::elapsed(start);
+                if remaining <= 0.0 {
+                    break;
+                }
+                let Ok(received) = socket.receive(remaining).await else {
+                    break;
+                };
+                if received.source_port() != SERVER_PORT {
+                    continue;
+                }
+                match Message::parse(received.data()) {
+                    Some(m)
+                        if m.reply && m.xid == message.xid && m.mac == self.mac && accept(&m) =>
+                    {
+                        return Ok(m)
+                    }
+                    _ => {}
+                }
+            }
+            timeout *= 2.0;
+        }
+        Err(Error::Timeout)
+    }
+
+    async fn apply(&self, lease: &Lease) {
+        self.ip
+            .set_config(lease.address, lease.netmask, lease.gateway);
+        info!(
+            "dhcp: {} netmask {} gateway {} from {} for {} s",
+            lease.address, lease.netmask, lease.gateway, lease.server, lease.time
+        );
+        if let Some(resolver) = self.resolver.as_ref() {
+            resolver.set_dhcp_servers(lease.dns.clone()).await;
+        }
+        *self.lease.lock().await = Some(lease.clone());
+    }
+
+    // Gets a lease, renews it with its server once half of it is gone and
+    // with any server past seven eighths. Starts over when it runs out.
+    async fn task_lease(self: Arc<Self>) {
+        loop {
+            let lease = match self.lease().await {
+                Some(lease) => lease,
+                None => match self.configure().await {
+                    Ok(lease) => lease,
+                    Err(e) => {
+                        info!("dhcp: {}", e);
+                        asyn::sleep(RETRY).await;
+                        continue;
+                    }
+                },
+            };
+            asyn::sleep((lease.renewal as f64 - asyn::elapsed(lease.acquired)).max(0.0)).await;
+            // a lease configured meanwhile has times of its own
+            if self.lease().await.as_ref() != Some(&lease) {
+                continue;
+            }
+            if !self.extend(&lease).await {
+                info!("dhcp: lease of {} ran out", lease.address);
+                let none = ip::Address([0; 4]);
+                self.ip.set_config(none, none, none);
+                if let Some(resolver) = self.resolver.as_ref() {
+                    resolver.set_dhcp_servers(Vec::new()).await;
+                }
+                *self.lease.lock().await = None;
+            }
+        }
+    }
+
+    // Renews until the lease runs out, false if it did
+    async fn extend(&self, lease: &Lease) -> bool {
+        loop {
+            let elapsed = asyn::elapsed(lease.acquired);
+            if elapsed >= lease.time as f64 {
+                return false;
+            }
+            let server = match elapsed < lease.rebinding as f64 {
+                true => lease.server,
+                false => BROADCAST,
+            };
+            match self.renew(lease, server).await {
+                Ok(_) => return true,
+                Err(Error::Refused) => return false,
+                Err(e) => {
+                    info!("dhcp: renewing {}: {}", lease.address, e);
+                    let left = lease.time as f64 - asyn::elapsed(lease.acquired);
+                    asyn::sleep(RETRY.min(left / 2.0).max(0.0)).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        dhcp::CLIENT_PORT,
+        ethernet::MacAddress,
+        sim::{Link, Simulator},
+    };
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 1]);
+    const OFFERED: ip::Address = ip::Address([10, 0, 0, 50]);
+    const STATIC_DNS: ip::Address = ip::Address([9, 9, 9, 9]);
+    const MAC: MacAddress = MacAddress([2, 0, 0, 0, 0, 1]);
+
+    type Log = Rc<RefCell<Vec<(ip::Address, Message)>>>;
+
+    // Offers OFFERED to every discover and acknowledges every request,
+    // or refuses them. Replies go to the client address once it has one.
+    async fn serve(socket: udp::Socket, refuse: bool, lease_time: u32, log: Log) {
+        loop {
+            let Ok(p) = socket.receive(1000.0).await else {
+                continue;
+            };
+            let Some(m) = Message::parse(p.data()) else {
+                continue;
+            };
+            log.borrow_mut()
+                .push((p.ip.destination_address(), m.clone()));
+            let typ = match m.typ {
+                MessageType::DISCOVER => MessageType::OFFER,
+                MessageType::REQUEST if refuse => MessageType::NAK,
+                MessageType::REQUEST => MessageType::ACK,
+                _ => continue,
+            };
+            let mut reply = Message::new(typ, m.xid, m.mac);
+            reply.reply = true;
+            reply.server = Some(SERVER);
+            if typ != MessageType::NAK {
+                reply.your_address = OFFERED;
+                reply.netmask = Some(ip::Address([255, 255, 0, 0]));
+                reply.router = Some(SERVER);
+                reply.dns = Vec::from([SERVER, STATIC_DNS]);
+                reply.lease_time = Some(lease_time);
+            }
+            let to = match m.client_address {
+                ip::Address([0, 0, 0, 0]) => BROADCAST,
+                a => a,
+            };
+            socket.send(to, CLIENT_PORT, &reply.build()).await.unwrap();
+        }
+    }
+
+    fn hosts(refuse: bool, lease_time: u32) -> (Simulator, Arc<Client>, Arc<ip::Service>, Log) {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let client = sim.host(switch, MAC, ip::Address([0; 4]), Link::default());
+        let server = sim.host(
+            switch,
+            MacAddress([2, 0, 0, 0, 0, 2]),
+            SERVER,
+            Link::default(),
+        );
+        let log = Log::default();
+        let socket = sim.block_on(async move { server.udp.open(SERVER_PORT).await.unwrap() });
+        sim.spawn(serve(socket, refuse, lease_time, log.clone()));
+        let ip = client.ip.clone();
+        let udp = client.udp.clone();
+        let (socket, resolver) = sim.block_on(async move {
+            let socket = udp.open(CLIENT_PORT).await.unwrap();
+            let resolver = dns::Resolver::new(udp.open(0).await.unwrap(), Vec::from([STATIC_DNS]));
+            (socket, resolver)
+        });
+        let client = Arc::new(Client::new(
+            socket,
+            MAC,
+            ip.clone(),
+            Some(Arc::new(resolver)),
+        ));
+        (sim, client, ip, log)
+    }
+
+    #[test]
+    fn acquires_and_applies_a_lease() {
+        let (sim, client, ip, log) = hosts(false, 3600);
+        let lease = sim.block_on({
+            let client = client.clone();
+            async move { client.configure().await }
+        });
+        let lease = lease.unwrap();
+        assert_eq!(lease.address, OFFERED);
+        assert_eq!(lease.netmask, ip::Address([255, 255, 0, 0]));
+        assert_eq!(lease.gateway, SERVER);
+        assert_eq!(lease.server, SERVER);
+        assert_eq!((lease.renewal, lease.rebinding), (1800, 3150));
+        assert_eq!(lease.dns, [SERVER, STATIC_DNS]);
+        assert_eq!(ip.address(), OFFERED);
+        let (current, servers) = sim.block_on(async move {
+            let servers = client.resolver.as_ref().unwrap().servers().await;
+            (client.lease().await, servers)
+        });
+        assert_eq!(current, Some(lease));
+        // the configured server stays first
+        assert_eq!(servers, [STATIC_DNS, SERVER]);
+
+        let log = log.borrow();
+        let types: Vec<MessageType> = log.iter().map(|(_, m)| m.typ).collect();
+        assert_eq!(types, [MessageType::DISCOVER, MessageType::REQUEST]);
+        let (to, request) = &log[1];
+        assert_eq!(*to, BROADCAST);
+        assert_eq!(request.requested_address, Some(OFFERED));
+        assert_eq!(request.server, Some(SERVER));
+    }
+
+    #[test]
+    fn renews_with_the_server_before_the_lease_runs_out() {
+        let (sim, client, ip, log) = hosts(false, 20);
+        client.clone().start(sim.executor());
+        let lease = sim.block_on({
+            let client = client.clone();
+            async move {
+                asyn::sleep(5.0).await;
+                let first = client.lease().await.unwrap();
+                // renewed at 10 seconds, past the 20 of the first lease
+                asyn::sleep(20.0).await;
+                (first, client.lease().await.unwrap())
+            }
+        });
+        let (first, renewed) = lease;
+        assert!(renewed.acquired > first.acquired);
+        assert_eq!(ip.address(), OFFERED);
+
+        let log = log.borrow();
+        let (to, renewal) = &log[2];
+        assert_eq!(*to, SERVER);
+        assert_eq!(renewal.typ, MessageType::REQUEST);
+        assert_eq!(renewal.client_address, OFFERED);
+        assert_eq!(renewal.requested_address, None);
+    }
+
+    #[test]
+    fn a_refused_request_configures_nothing() {
+        let (sim, client, ip, _) = hosts(true, 3600);
+        let result = sim.block_on({
+            let client = client.clone();
+            async move { client.configure().await }
+        });
+        assert_eq!(result, Err(Error::Refused));
+        assert_eq!(ip.address(), ip::Address([0; 4]));
+        assert_eq!(sim.block_on(async move { client.lease().await }), None);
+    }
+}
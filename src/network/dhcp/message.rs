@@ -0,0 +1,284 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use uefi_raw::newtype_enum;
+
+use crate::network::{ethernet::MacAddress, ip};
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const BOOT_REQUEST: u8 = 1;
+const BOOT_REPLY: u8 = 2;
+const HARDWARE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+// op to file, the fields before the magic cookie
+const FIXED_SIZE: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+newtype_enum! {
+    pub enum MessageType: u8 => {
+        DISCOVER = 1,
+        OFFER = 2,
+        REQUEST = 3,
+        DECLINE = 4,
+        ACK = 5,
+        NAK = 6,
+        RELEASE = 7,
+        INFORM = 8,
+    }
+}
+
+newtype_enum! {
+    pub enum OptionCode: u8 => {
+        PAD = 0,
+        SUBNET_MASK = 1,
+        ROUTER = 3,
+        DNS = 6,
+        REQUESTED_ADDRESS = 50,
+        LEASE_TIME = 51,
+        MESSAGE_TYPE = 53,
+        SERVER_ID = 54,
+        PARAMETER_REQUEST_LIST = 55,
+        RENEWAL_TIME = 58,
+        REBINDING_TIME = 59,
+        CLIENT_ID = 61,
+        END = 255,
+    }
+}
+
+// A DHCP message (RFC 2131) with the options the client uses, either way
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    // From a server, else from a client
+    pub reply: bool,
+    pub typ: MessageType,
+    pub xid: u32,
+    // Asks the server to broadcast its reply
+    pub broadcast: bool,
+    pub mac: MacAddress,
+    // ciaddr, set by a client that has its address
+    pub client_address: ip::Address,
+    // yiaddr, the address the server offers or acknowledges
+    pub your_address: ip::Address,
+    pub requested_address: Option<ip::Address>,
+    pub server: Option<ip::Address>,
+    pub netmask: Option<ip::Address>,
+    // The first router of the option
+    pub router: Option<ip::Address>,
+    // Domain name servers, in order of preference
+    pub dns: Vec<ip::Address>,
+    // Seconds
+    pub lease_time: Option<u32>,
+    pub renewal_time: Option<u32>,
+    pub rebinding_time: Option<u32>,
+    pub parameters: Vec<OptionCode>,
+}
+
+impl Message {
+    pub fn new(typ: MessageType, xid: u32, mac: MacAddress) -> Message {
+        Message {
+            reply: false,
+            typ,
+            xid,
+            broadcast: false,
+            mac,
+            client_address: ip::Address([0; 4]),
+            your_address: ip::Address([0; 4]),
+            requested_address: None,
+            server: None,
+            netmask: None,
+            router: None,
+            dns: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+            parameters: Vec::new(),
+        }
+    }
+
+    // None for anything but an ethernet DHCP message
+    pub fn parse(data: &[u8]) -> Option<Message> {
+        if data.len() < FIXED_SIZE + 4
+            || data[1] != HARDWARE_ETHERNET
+            || data[2] != 6
+            || data[FIXED_SIZE..FIXED_SIZE + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let reply = match data[0] {
+            BOOT_REQUEST => false,
+            BOOT_REPLY => true,
+            _ => return None,
+        };
+        let address = |at: usize| ip::Address(data[at..at + 4].try_into().unwrap());
+        let mut m = Message::new(
+            MessageType(0),
+            u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            MacAddress(data[28..34].try_into().unwrap()),
+        );
+        m.reply = reply;
+        m.broadcast = u16::from_be_bytes([data[10], data[11]]) & FLAG_BROADCAST != 0;
+        m.client_address = address(12);
+        m.your_address = address(16);
+
+        for (code, value) in options(&data[FIXED_SIZE + 4..]) {
+            let address = || Some(ip::Address(value.get(..4)?.try_into().unwrap()));
+            let seconds = || Some(u32::from_be_bytes(value.get(..4)?.try_into().unwrap()));
+            match code {
+                OptionCode::MESSAGE_TYPE => m.typ = MessageType(*value.first()?),
+                OptionCode::SUBNET_MASK => m.netmask = address(),
+                OptionCode::ROUTER => m.router = address(),
+                OptionCode::DNS => {
+                    m.dns = value
+                        .as_chunks::<4>()
+                        .0
+                        .iter()
+                        .map(|a| ip::Address(*a))
+                        .collect()
+                }
+                OptionCode::REQUESTED_ADDRESS => m.requested_address = address(),
+                OptionCode::SERVER_ID => m.server = address(),
+                OptionCode::LEASE_TIME => m.lease_time = seconds(),
+                OptionCode::RENEWAL_TIME => m.renewal_time = seconds(),
+                OptionCode::REBINDING_TIME => m.rebinding_time = seconds(),
+                OptionCode::PARAMETER_REQUEST_LIST => {
+                    m.parameters = value.iter().map(|c| OptionCode(*c)).collect()
+                }
+                _ => {}
+            }
+        }
+        match m.typ {
+            MessageType(0) => None,
+            _ => Some(m),
+        }
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(300);
+        r.push(if self.reply { BOOT_REPLY } else { BOOT_REQUEST });
+        r.extend_from_slice(&[HARDWARE_ETHERNET, 6, 0]);
+        r.extend_from_slice(&self.xid.to_be_bytes());
+        // secs
+        r.extend_from_slice(&[0, 0]);
+        let flags = if self.broadcast { FLAG_BROADCAST } else { 0 };
+        r.extend_from_slice(&flags.to_be_bytes());
+        r.extend_from_slice(&self.client_address.0);
+        r.extend_from_slice(&self.your_address.0);
+        // siaddr and giaddr
+        r.extend_from_slice(&[0; 8]);
+        r.extend_from_slice(&self.mac.0);
+        // rest of chaddr, sname and file
+        r.resize(FIXED_SIZE, 0);
+        r.extend_from_slice(&MAGIC_COOKIE);
+
+        let mut option = |code: OptionCode, value: &[u8]| {
+            r.push(code.0);
+            r.push(value.len() as u8);
+            r.extend_from_slice(value);
+        };
+        option(OptionCode::MESSAGE_TYPE, &[self.typ.0]);
+        if !self.reply {
+            let mut id = Vec::from([HARDWARE_ETHERNET]);
+            id.extend_from_slice(&self.mac.0);
+            option(OptionCode::CLIENT_ID, &id);
+        }
+        let addresses = [
+            (OptionCode::REQUESTED_ADDRESS, self.requested_address),
+            (OptionCode::SERVER_ID, self.server),
+            (OptionCode::SUBNET_MASK, self.netmask),
+            (OptionCode::ROUTER, self.router),
+        ];
+        for (code, address) in addresses {
+            if let Some(a) = address {
+                option(code, &a.0);
+            }
+        }
+        if !self.dns.is_empty() {
+            let addresses: Vec<u8> = self.dns.iter().flat_map(|a| a.0).collect();
+            option(OptionCode::DNS, &addresses);
+        }
+        let times = [
+            (OptionCode::LEASE_TIME, self.lease_time),
+            (OptionCode::RENEWAL_TIME, self.renewal_time),
+            (OptionCode::REBINDING_TIME, self.rebinding_time),
+        ];
+        for (code, seconds) in times {
+            if let Some(s) = seconds {
+                option(code, &s.to_be_bytes());
+            }
+        }
+        if !self.parameters.is_empty() {
+            let codes: Vec<u8> = self.parameters.iter().map(|c| c.0).collect();
+            option(OptionCode::PARAMETER_REQUEST_LIST, &codes);
+        }
+        r.push(OptionCode::END.0);
+        // some relays drop BOOTP messages under 300 bytes (RFC 1542)
+        if r.len() < 300 {
+            r.resize(300, 0);
+        }
+        r
+    }
+}
+
+// Code and value of each option up to the end, a truncated one ends them
+fn options(mut data: &[u8]) -> impl Iterator<Item = (OptionCode, &[u8])> {
+    core::iter::from_fn(move || loop {
+        let code = OptionCode(*data.first()?);
+        match code {
+            OptionCode::END => return None,
+            OptionCode::PAD => data = &data[1..],
+            _ => {
+                let len = *data.get(1)? as usize;
+                let value = data.get(2..2 + len)?;
+                data = &data[2 + len..];
+                return Some((code, value));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mac = MacAddress([2, 0, 0, 0, 0, 1]);
+        let mut m = Message::new(MessageType::ACK, 0x12345678, mac);
+        m.reply = true;
+        m.broadcast = true;
+        m.your_address = ip::Address([10, 0, 0, 50]);
+        m.server = Some(ip::Address([10, 0, 0, 1]));
+        m.netmask = Some(ip::Address([255, 255, 255, 0]));
+        m.router = Some(ip::Address([10, 0, 0, 1]));
+        m.dns = Vec::from([ip::Address([10, 0, 0, 1]), ip::Address([9, 9, 9, 9])]);
+        m.lease_time = Some(3600);
+        m.renewal_time = Some(1800);
+        m.rebinding_time = Some(3150);
+        let data = m.build();
+        assert_eq!(data.len(), 300);
+        assert_eq!(Message::parse(&data), Some(m));
+
+        let mut request = Message::new(MessageType::REQUEST, 1, mac);
+        request.requested_address = Some(ip::Address([10, 0, 0, 50]));
+        request.parameters = Vec::from([OptionCode::SUBNET_MASK, OptionCode::ROUTER]);
+        assert_eq!(Message::parse(&request.build()), Some(request));
+    }
+
+    #[test]
+    fn skips_pads_and_stops_at_truncated_options() {
+        let mut data = Message::new(MessageType::OFFER, 7, MacAddress([0; 6])).build();
+        let end = FIXED_SIZE + 4 + 3;
+        data.truncate(end);
+        // a pad, a router, then a mask cut short
+        data.extend_from_slice(&[0, 3, 8, 10, 0, 0, 1, 10, 0, 0, 2, 1, 4, 255]);
+        let m = Message::parse(&data).unwrap();
+        assert_eq!(m.router, Some(ip::Address([10, 0, 0, 1])));
+        assert_eq!(m.netmask, None);
+        // without a message type it is BOOTP, not DHCP
+        data.truncate(FIXED_SIZE + 4);
+        assert_eq!(Message::parse(&data), None);
+    }
+}
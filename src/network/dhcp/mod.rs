@@ -0,0 +1,5 @@
+mod client;
+mod message;
+
+pub use client::Client;
+pub use message::CLIENT_PORT;
@@ -0,0 +1,264 @@
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use uefi_raw::newtype_enum;
+
+use crate::network::ip;
+
+const HEADER_SIZE: usize = 12;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+// Upper bound on compression pointers followed while reading one name
+const MAX_POINTERS: usize = 64;
+
+newtype_enum! {
+    pub enum RecordType: u16 => {
+        A = 1,
+        NS = 2,
+        CNAME = 5,
+        SOA = 6,
+        PTR = 12,
+        MX = 15,
+        TXT = 16,
+        AAAA = 28,
+        OPT = 41,
+    }
+}
+
+newtype_enum! {
+    pub enum ResponseCode: u8 => {
+        NO_ERROR = 0,
+        FORMAT_ERROR = 1,
+        SERVER_FAILURE = 2,
+        NAME_ERROR = 3,
+        NOT_IMPLEMENTED = 4,
+        REFUSED = 5,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    A(ip::Address),
+    Aaaa([u8; 16]),
+    Cname(String),
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub name: String,
+    pub typ: RecordType,
+    pub ttl: u32,
+    pub data: Data,
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub id: u16,
+    pub truncated: bool,
+    pub response_code: ResponseCode,
+    pub question: Option<(String, RecordType)>,
+    pub answers: Vec<Record>,
+}
+
+// Builds a recursive query for a single name, optionally advertising a
+// larger udp payload through an EDNS0 OPT record
+pub fn build_query(
+    id: u16,
+    name: &str,
+    typ: RecordType,
+    edns_payload: Option<u16>,
+) -> Option<Vec<u8>> {
+    let mut q = Vec::with_capacity(HEADER_SIZE + name.len() + 16);
+    q.extend_from_slice(&id.to_be_bytes());
+    q.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    q.extend_from_slice(&1u16.to_be_bytes());
+    q.extend_from_slice(&0u16.to_be_bytes());
+    q.extend_from_slice(&0u16.to_be_bytes());
+    q.extend_from_slice(&(edns_payload.is_some() as u16).to_be_bytes());
+
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return None;
+        }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    if q.len() - HEADER_SIZE > 255 {
+        return None;
+    }
+    q.extend_from_slice(&typ.0.to_be_bytes());
+    q.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    if let Some(payload) = edns_payload {
+        q.push(0);
+        q.extend_from_slice(&RecordType::OPT.0.to_be_bytes());
+        q.extend_from_slice(&payload.to_be_bytes());
+        q.extend_from_slice(&0u32.to_be_bytes());
+        q.extend_from_slice(&0u16.to_be_bytes());
+    }
+
+    Some(q)
+}
+
+pub fn parse(data: &[u8]) -> Option<Message> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let flags = read_u16(data, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    let question_count = read_u16(data, 4)?;
+    let answer_count = read_u16(data, 6)?;
+
+    let mut message = Message {
+        id: read_u16(data, 0)?,
+        truncated: flags & FLAG_TRUNCATED != 0,
+        response_code: ResponseCode((flags & 0xf) as u8),
+        question: None,
+        answers: Vec::new(),
+    };
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..question_count {
+        let (name, next) = read_name(data, offset)?;
+        let typ = RecordType(read_u16(data, next)?);
+        offset = next + 4;
+        if message.question.is_none() {
+            message.question = Some((name, typ));
+        }
+    }
+
+    for _ in 0..answer_count {
+        let record = match read_record(data, offset) {
+            Some((record, next)) => {
+                offset = next;
+                record
+            }
+            // answers cut off by truncation are dropped
+            None if message.truncated => break,
+            None => return None,
+        };
+        message.answers.push(record);
+    }
+
+    Some(message)
+}
+
+fn read_record(data: &[u8], offset: usize) -> Option<(Record, usize)> {
+    let (name, offset) = read_name(data, offset)?;
+    let typ = RecordType(read_u16(data, offset)?);
+    let ttl = u32::from_be_bytes(data.get(offset + 4..offset + 8)?.try_into().unwrap());
+    let length = read_u16(data, offset + 8)? as usize;
+    let start = offset + 10;
+    let rdata = data.get(start..start + length)?;
+
+    let record_data = match typ {
+        RecordType::A if length == 4 => Data::A(ip::Address(rdata.try_into().unwrap())),
+        RecordType::AAAA if length == 16 => Data::Aaaa(rdata.try_into().unwrap()),
+        RecordType::CNAME => Data::Cname(read_name(data, start)?.0),
+        _ => Data::Other,
+    };
+
+    Some((
+        Record {
+            name,
+            typ,
+            // ttls with the top bit set are treated as 0 (RFC 2181)
+            ttl: if ttl > i32::MAX as u32 { 0 } else { ttl },
+            data: record_data,
+        },
+        start + length,
+    ))
+}
+
+// Reads a possibly compressed name, returning it lowercased without the
+// trailing dot together with the offset just past it
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+
+    for _ in 0..MAX_POINTERS {
+        loop {
+            let len = *data.get(offset)? as usize;
+            match len & 0xc0 {
+                0x00 => {}
+                0xc0 => break,
+                _ => return None,
+            }
+            if len == 0 {
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            let label = data.get(offset + 1..offset + 1 + len)?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            for c in label.iter() {
+                name.push(c.to_ascii_lowercase() as char);
+            }
+            if name.len() > 255 {
+                return None;
+            }
+            offset += 1 + len;
+        }
+
+        let pointer = (read_u16(data, offset)? & 0x3fff) as usize;
+        if end.is_none() {
+            end = Some(offset + 2);
+        }
+        offset = pointer;
+    }
+
+    None
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_query, parse, Data, RecordType, ResponseCode};
+    use crate::network::ip;
+
+    #[test]
+    fn response_with_cname() {
+        let mut r = build_query(0x1234, "www.example.com", RecordType::A, None).unwrap();
+        // response, recursion desired and available, 2 answers
+        r[2..4].clone_from_slice(&[0x81, 0x80]);
+        r[6..8].clone_from_slice(&[0, 2]);
+        // www.example.com CNAME example.com, compressed against the question
+        r.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16]);
+        // example.com A 93.184.216.34
+        r.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 1, 0, 0, 4, 93, 184, 216, 34]);
+
+        let m = parse(&r).unwrap();
+        assert_eq!(m.id, 0x1234);
+        assert!(!m.truncated);
+        assert_eq!(m.response_code, ResponseCode::NO_ERROR);
+        assert_eq!(m.question.unwrap().0, "www.example.com");
+        assert_eq!(m.answers.len(), 2);
+        assert_eq!(m.answers[0].data, Data::Cname("example.com".into()));
+        assert_eq!(m.answers[0].ttl, 60);
+        assert_eq!(m.answers[1].name, "example.com");
+        assert_eq!(m.answers[1].data, Data::A(ip::Address([93, 184, 216, 34])));
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let mut r = build_query(1, "a", RecordType::A, None).unwrap();
+        r[2] = 0x80;
+        r[6..8].clone_from_slice(&[0, 1]);
+        let offset = r.len() as u8;
+        r.extend_from_slice(&[0xc0, offset, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(parse(&r).is_none());
+    }
+}
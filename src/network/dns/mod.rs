@@ -0,0 +1,4 @@
+mod message;
+mod resolver;
+
+pub use resolver::{Error, Resolver};
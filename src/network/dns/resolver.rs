@@ -0,0 +1,264 @@
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
+use hashbrown::HashMap;
+
+use super::message::{self, Data, Message, RecordType, ResponseCode};
+use crate::{
+    asyn,
+    network::{self, ip, udp},
+};
+
+pub const PORT: u16 = 53;
+
+const INITIAL_TIMEOUT: f64 = 1.0;
+const ROUNDS: usize = 3;
+const MAX_CNAMES: usize = 8;
+// Largest payload that fits a single 1500 byte frame, since ip
+// fragments are not reassembled
+const EDNS_PAYLOAD: u16 = 1472;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    NoServers,
+    InvalidName,
+    Timeout,
+    NoSuchName,
+    NoData,
+    ServerFailure(ResponseCode),
+    Truncated,
+    TooManyCnames,
+    Network(network::Error),
+}
+
+struct CacheEntry {
+    records: Vec<Data>,
+    expires: u64,
+}
+
+pub struct Resolver {
+    socket: asyn::Mutex<udp::Socket>,
+    servers: asyn::Mutex<Vec<ip::Address>>,
+    // From the DHCP lease, asked after the configured ones
+    dhcp_servers: asyn::Mutex<Vec<ip::Address>>,
+    cache: asyn::Mutex<HashMap<(String, RecordType), CacheEntry>>,
+    next_id: AtomicU16,
+}
+
+impl Resolver {
+    pub fn new(socket: udp::Socket, servers: Vec<ip::Address>) -> Resolver {
+        Resolver {
+            socket: asyn::Mutex::new(socket),
+            servers: asyn::Mutex::new(servers),
+            dhcp_servers: asyn::Mutex::new(Vec::new()),
+            cache: asyn::Mutex::new(HashMap::new()),
+            next_id: AtomicU16::new(asyn::timestamp() as u16),
+        }
+    }
+
+    // The configured servers, then those of the DHCP lease
+    pub async fn servers(&self) -> Vec<ip::Address> {
+        let mut servers = self.servers.lock().await.clone();
+        for server in self.dhcp_servers.lock().await.iter() {
+            if !servers.contains(server) {
+                servers.push(*server);
+            }
+        }
+        servers
+    }
+
+    pub async fn set_servers(&self, servers: Vec<ip::Address>) {
+        *self.servers.lock().await = servers;
+    }
+
+    pub async fn set_dhcp_servers(&self, servers: Vec<ip::Address>) {
+        *self.dhcp_servers.lock().await = servers;
+    }
+
+    pub async fn lookup(&self, name: &str) -> Result<Vec<ip::Address>, Error> {
+        let records = self.resolve(name, RecordType::A).await?;
+        Ok(records
+            .into_iter()
+            .filter_map(|r| match r {
+                Data::A(a) => Some(a),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub async fn lookup_ipv6(&self, name: &str) -> Result<Vec<[u8; 16]>, Error> {
+        let records = self.resolve(name, RecordType::AAAA).await?;
+        Ok(records
+            .into_iter()
+            .filter_map(|r| match r {
+                Data::Aaaa(a) => Some(a),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Resolves records of the given type, following CNAMEs both inside a
+    // single answer and across queries
+    pub async fn resolve(&self, name: &str, typ: RecordType) -> Result<Vec<Data>, Error> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(records) = self.cached(&name, typ).await {
+            return Ok(records);
+        }
+
+        let mut target = name.clone();
+        let mut ttl = u32::MAX;
+        let mut cnames = 0;
+        loop {
+            let response = self.query(&target, typ).await?;
+            let queried = target.clone();
+
+            while let Some(r) = response
+                .answers
+                .iter()
+                .find(|r| r.typ == RecordType::CNAME && r.name == target)
+            {
+                cnames += 1;
+                if cnames > MAX_CNAMES {
+                    return Err(Error::TooManyCnames);
+                }
+                ttl = ttl.min(r.ttl);
+                if let Data::Cname(next) = &r.data {
+                    target = next.clone();
+                }
+            }
+
+            let records: Vec<_> = response
+                .answers
+                .iter()
+                .filter(|r| r.typ == typ && r.name == target)
+                .collect();
+            if !records.is_empty() {
+                let ttl = records.iter().fold(ttl, |t, r| t.min(r.ttl));
+                let records: Vec<Data> = records.into_iter().map(|r| r.data.clone()).collect();
+                self.cache.lock().await.insert(
+                    (name, typ),
+                    CacheEntry {
+                        records: records.clone(),
+                        expires: asyn::timestamp() + ttl as u64 * 1_000_000_000,
+                    },
+                );
+                return Ok(records);
+            }
+
+            if target == queried {
+                return Err(Error::NoData);
+            }
+        }
+    }
+
+    async fn cached(&self, name: &str, typ: RecordType) -> Option<Vec<Data>> {
+        let mut cache = self.cache.lock().await;
+        let key = (String::from(name), typ);
+        match cache.get(&key) {
+            Some(entry) if entry.expires > asyn::timestamp() => Some(entry.records.clone()),
+            Some(_) => {
+                cache.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // Sends the query to every server in turn, doubling the timeout after
+    // each round. Truncated answers are retried with EDNS0 and only used
+    // when no server returns a complete one.
+    async fn query(&self, name: &str, typ: RecordType) -> Result<Message, Error> {
+        let servers = self.servers().await;
+        if servers.is_empty() {
+            return Err(Error::NoServers);
+        }
+        let socket = self.socket.lock().await;
+
+        let mut timeout = INITIAL_TIMEOUT;
+        let mut error = Error::Timeout;
+        let mut truncated = None;
+        for _ in 0..ROUNDS {
+            for server in servers.iter() {
+                let mut edns = None;
+                loop {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    let query =
+                        message::build_query(id, name, typ, edns).ok_or(Error::InvalidName)?;
+                    // a query that does not go out times out like a lost one
+                    match socket.send(*server, PORT, &query).await {
+                        Err(e) if !e.is_transient() => return Err(Error::Network(e)),
+                        _ => {}
+                    }
+
+                    let response =
+                        match Self::wait_response(&socket, *server, id, name, typ, timeout).await {
+                            Some(r) => r,
+                            None => break,
+                        };
+                    match response.response_code {
+                        ResponseCode::NO_ERROR => {}
+                        ResponseCode::NAME_ERROR => return Err(Error::NoSuchName),
+                        c => {
+                            error = Error::ServerFailure(c);
+                            break;
+                        }
+                    }
+                    if !response.truncated {
+                        return Ok(response);
+                    }
+                    if edns.is_none() {
+                        edns = Some(EDNS_PAYLOAD);
+                        continue;
+                    }
+                    truncated = Some(response);
+                    break;
+                }
+            }
+            if truncated.is_some() {
+                break;
+            }
+            timeout *= 2.0;
+        }
+
+        match truncated {
+            Some(r) if !r.answers.is_empty() => Ok(r),
+            Some(_) => Err(Error::Truncated),
+            None => Err(error),
+        }
+    }
+
+    async fn wait_response(
+        socket: &udp::Socket,
+        server: ip::Address,
+        id: u16,
+        name: &str,
+        typ: RecordType,
+        timeout: f64,
+    ) -> Option<Message> {
+        let start = asyn::timestamp();
+        loop {
+            let remaining = timeout - asyn::elapsed(start);
+            if remaining <= 0.0 {
+                return None;
+            }
+            let received = socket.receive(remaining).await.ok()?;
+            if received.ip.source_address() != server || received.source_port() != PORT {
+                continue;
+            }
+            let response = match message::parse(received.data()) {
+                Some(r) => r,
+                None => continue,
+            };
+            if response.id != id {
+                continue;
+            }
+            match &response.question {
+                Some((n, t)) if n == name && *t == typ => return Some(response),
+                // some servers omit the question in error responses
+                None if response.response_code != ResponseCode::NO_ERROR => return Some(response),
+                _ => continue,
+            }
+        }
+    }
+}
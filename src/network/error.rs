@@ -0,0 +1,64 @@
+use core::fmt;
+
+use uefi::Status;
+
+use super::ip;
+
+// What the send, receive and open calls of every layer fail with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // The device failed the operation
+    Device(Status),
+    // A queue between two layers is full, trying again later may work
+    QueueFull,
+    // The destination is off the subnet and there is no gateway
+    NoRoute,
+    // Nobody answered the arp request for the next hop yet
+    Unresolved(ip::Address),
+    Timeout,
+    // Received data that does not parse
+    Malformed,
+    // The port is taken, or no free one is left
+    AddressInUse,
+    // The peer refused the connection
+    Refused,
+    // The peer reset the connection
+    Reset,
+    Closed,
+}
+
+impl Error {
+    // Sending again a little later may work, the way a lost datagram is
+    // retried
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::QueueFull | Error::Unresolved(_) | Error::Timeout
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Device(status) => write!(f, "device error {:?}", status),
+            Error::QueueFull => f.write_str("queue full"),
+            Error::NoRoute => f.write_str("no route to host"),
+            Error::Unresolved(address) => write!(f, "{} did not answer arp", address),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Malformed => f.write_str("malformed packet"),
+            Error::AddressInUse => f.write_str("address in use"),
+            Error::Refused => f.write_str("connection refused"),
+            Error::Reset => f.write_str("connection reset"),
+            Error::Closed => f.write_str("connection closed"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl From<uefi::Error> for Error {
+    fn from(e: uefi::Error) -> Error {
+        Error::Device(e.status())
+    }
+}
@@ -0,0 +1,35 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::future::Future;
+
+use super::{MacAddress, ReceiveFilters};
+use crate::network::Error;
+
+// Which frames the device hands up, changed by the layers that join
+// multicast groups
+pub trait Filters {
+    fn receive_filters(&self) -> ReceiveFilters;
+    fn set_receive_filters(&self, filters: ReceiveFilters) -> uefi::Result;
+}
+
+// What the ethernet service needs from the hardware. SimpleNetwork is the
+// firmware's, tests plug in their own.
+pub trait Device: Filters {
+    fn mac_address(&self) -> MacAddress;
+    // The permanent address for None
+    fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result;
+    // Ready with the size of the next frame, copied into buf
+    fn receive<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize, Error>> + 'a;
+    // Ready once the device took the first len bytes of the buffer
+    fn transmit(
+        &self,
+        buffer: Box<[u8]>,
+        len: usize,
+    ) -> impl Future<Output = Result<(), Error>> + '_;
+    // Frames the device took but has not finished sending
+    fn in_flight(&self) -> usize;
+    // Brings the device back after a failed receive
+    fn recover(&self) -> Result<(), Error>;
+    fn shutdown(&self) -> uefi::Result;
+}
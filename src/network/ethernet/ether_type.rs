@@ -5,5 +5,7 @@ newtype_enum! {
         IPV4 = 0x0800,
         ARP = 0x0806,
         WOL = 0x0842,
+        LLDP = 0x88CC,
+        VLAN = 0x8100,
     }
 }
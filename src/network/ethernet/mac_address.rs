@@ -1,4 +1,4 @@
-use core::fmt;
+use core::{fmt, str::FromStr};
 use uefi::proto::network::MacAddress as UefiMacAddress;
 
 pub const MAC_BROADCAST: MacAddress = MacAddress([255, 255, 255, 255, 255, 255]);
@@ -7,10 +7,10 @@ pub const MAC_BROADCAST: MacAddress = MacAddress([255, 255, 255, 255, 255, 255])
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub struct MacAddress(pub [u8; 6]);
 
-impl Into<UefiMacAddress> for MacAddress {
-    fn into(self) -> UefiMacAddress {
+impl From<MacAddress> for UefiMacAddress {
+    fn from(mac: MacAddress) -> UefiMacAddress {
         let mut result = UefiMacAddress([0; 32]);
-        result.0[0..6].clone_from_slice(&self.0);
+        result.0[0..6].clone_from_slice(&mac.0);
         result
     }
 }
@@ -23,3 +23,33 @@ impl fmt::Debug for MacAddress {
         ))
     }
 }
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5],
+        ))
+    }
+}
+
+// Six hex pairs separated by : or -
+impl FromStr for MacAddress {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<MacAddress, ()> {
+        let mut result = MacAddress([0; 6]);
+        let mut parts = s.split([':', '-']);
+        for b in result.0.iter_mut() {
+            let part = parts.next().ok_or(())?;
+            if part.len() != 2 {
+                return Err(());
+            }
+            *b = u8::from_str_radix(part, 16).map_err(|_| ())?;
+        }
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(result),
+        }
+    }
+}
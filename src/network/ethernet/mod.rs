@@ -1,14 +1,33 @@
 extern crate alloc;
 
+mod device;
 mod ether_type;
 mod mac_address;
 mod packet;
 mod service;
 mod simple_network;
 mod socket;
+mod vlan;
 
+use alloc::sync::Arc;
+
+use crate::metrics;
+
+pub use device::{Device, Filters};
 pub use ether_type::Type;
 pub use mac_address::{MacAddress, MAC_BROADCAST};
 pub use packet::Packet;
-pub use service::Service;
+pub use service::{Counters, Service};
+pub use simple_network::{ReceiveFilters, SimpleNetwork};
 pub use socket::Socket;
+pub use vlan::{Tag, Vlan};
+
+// Where the layers above open their sockets: the interface itself or one
+// of its VLANs
+pub trait Network {
+    fn open(&self, p: Type) -> Socket;
+    // The receive filters of the device underneath
+    fn filters(&self) -> Arc<dyn Filters>;
+    // The registry of the stack on the device
+    fn metrics(&self) -> Arc<metrics::Registry>;
+}
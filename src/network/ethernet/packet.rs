@@ -3,8 +3,12 @@ extern crate alloc;
 use alloc::boxed::Box;
 use core::fmt;
 
-use super::{MacAddress, Type};
+use super::{MacAddress, Tag, Type};
 
+// Ether type and tag control information of an 802.1Q tag
+const TAG_SIZE: usize = 4;
+
+#[derive(Clone)]
 pub struct Packet {
     pub(super) data: Box<[u8; 3000]>,
     size: usize,
@@ -40,18 +44,51 @@ impl Packet {
     pub fn set_mac_source(&mut self, s: MacAddress) {
         self.data[6..12].clone_from_slice(&s.0);
     }
+    // The type of the payload, behind the VLAN tag if there is one
     pub fn ether_type(&self) -> Type {
-        Type(u16::from_be_bytes(self.data[12..14].try_into().unwrap()))
+        let offset = self.header_size() - 2;
+        Type(u16::from_be_bytes(
+            self.data[offset..offset + 2].try_into().unwrap(),
+        ))
     }
     pub fn set_ether_type(&mut self, t: Type) {
-        self.data[12..14].clone_from_slice(&t.0.to_be_bytes());
+        let offset = self.header_size() - 2;
+        self.data[offset..offset + 2].clone_from_slice(&t.0.to_be_bytes());
+    }
+
+    pub fn vlan(&self) -> Option<Tag> {
+        if self.data[12..14] != Type::VLAN.0.to_be_bytes() {
+            return None;
+        }
+        Some(Tag::from_tci(u16::from_be_bytes(
+            self.data[14..16].try_into().unwrap(),
+        )))
+    }
+    // Adds, changes or removes the tag, moving the payload along
+    pub fn set_vlan(&mut self, tag: Option<Tag>) {
+        let end = self.header_size() + self.size;
+        match (self.vlan().is_some(), tag) {
+            (false, Some(_)) => {
+                self.data.copy_within(12..end, 12 + TAG_SIZE);
+                self.data[12..14].clone_from_slice(&Type::VLAN.0.to_be_bytes());
+            }
+            (true, None) => self.data.copy_within(12 + TAG_SIZE..end, 12),
+            _ => {}
+        }
+        if let Some(tag) = tag {
+            self.data[14..16].clone_from_slice(&tag.tci().to_be_bytes());
+        }
     }
 
     pub fn size(&self) -> usize {
         self.size
     }
     pub fn header_size(&self) -> usize {
-        14
+        if self.data[12..14] == Type::VLAN.0.to_be_bytes() {
+            14 + TAG_SIZE
+        } else {
+            14
+        }
     }
     pub fn set_size(&mut self, s: usize) {
         self.size = s;
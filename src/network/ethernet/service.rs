@@ -1,73 +1,426 @@
 extern crate alloc;
 
-use alloc::sync::Arc;
-use core::panic;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use crossbeam_queue::ArrayQueue;
 use hashbrown::HashMap;
+use log::error;
+use uefi::Status;
 
-use super::{ether_type::Type, simple_network, MacAddress, Packet, Socket};
-use crate::asyn::{self, Executor, Task};
+use super::{
+    ether_type::Type, Device, Filters, MacAddress, Network, Packet, SimpleNetwork, Socket, Tag,
+    Vlan,
+};
+use crate::{
+    asyn::{self, Executor, Task},
+    metrics,
+    network::{
+        capture::{Capture, Direction},
+        queues, Error,
+    },
+};
 
-pub struct Service {
-    network: simple_network::SimpleNetwork,
-    sockets: HashMap<Type, Arc<ArrayQueue<Packet>>>,
+// Seconds between recovery attempts of a failing device
+const RECOVERY_BACKOFF: f64 = 0.01;
+const MAX_RECOVERY_BACKOFF: f64 = 1.0;
+// How often flush checks for frames still going out
+const FLUSH_POLL_INTERVAL: f64 = 0.01;
+
+// Frame counters, shared so they can be read after the service started
+pub struct Counters {
+    pub received: metrics::Counter,
+    pub received_bytes: metrics::Counter,
+    pub sent: metrics::Counter,
+    pub sent_bytes: metrics::Counter,
+    // Frames for an ether type nobody listens to
+    pub unknown_type: metrics::Counter,
+    // Frames for a socket whose receive queue is full
+    pub queue_full: metrics::Counter,
+    // Runts and frames too big for a buffer
+    pub malformed: metrics::Counter,
+    // Frames the driver failed or never took
+    pub transmit_errors: metrics::Counter,
+    // Receive calls the driver failed, each followed by a recovery
+    pub receive_errors: metrics::Counter,
+    pub send_queue: metrics::Gauge,
+    pub receive_queue: metrics::Gauge,
+}
+
+impl Counters {
+    fn new(registry: &metrics::Registry) -> Counters {
+        let frames = "Ethernet frames received or sent.";
+        let bytes = "Ethernet bytes received or sent.";
+        let dropped = "Received ethernet frames dropped, by reason.";
+        let queue = "Most frames waiting in an ethernet queue.";
+        Counters {
+            received: registry.counter(
+                "ethernet_frames_total",
+                frames,
+                &[("direction", "received")],
+            ),
+            received_bytes: registry.counter(
+                "ethernet_bytes_total",
+                bytes,
+                &[("direction", "received")],
+            ),
+            sent: registry.counter("ethernet_frames_total", frames, &[("direction", "sent")]),
+            sent_bytes: registry.counter("ethernet_bytes_total", bytes, &[("direction", "sent")]),
+            unknown_type: registry.counter(
+                "ethernet_dropped_total",
+                dropped,
+                &[("reason", "unknown_type")],
+            ),
+            queue_full: registry.counter(
+                "ethernet_dropped_total",
+                dropped,
+                &[("reason", "queue_full")],
+            ),
+            malformed: registry.counter(
+                "ethernet_dropped_total",
+                dropped,
+                &[("reason", "malformed")],
+            ),
+            receive_errors: registry.counter(
+                "ethernet_receive_errors_total",
+                "Receive calls the ethernet device failed.",
+                &[],
+            ),
+            transmit_errors: registry.counter(
+                "ethernet_transmit_errors_total",
+                "Ethernet frames the device failed to send.",
+                &[],
+            ),
+            send_queue: registry.gauge(
+                "ethernet_queue_high_watermark",
+                queue,
+                &[("queue", "send")],
+            ),
+            receive_queue: registry.gauge(
+                "ethernet_queue_high_watermark",
+                queue,
+                &[("queue", "receive")],
+            ),
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.unknown_type.get() + self.queue_full.get() + self.malformed.get()
+    }
+}
+
+type Sockets = HashMap<(Option<u16>, Type), Vec<Arc<ArrayQueue<Packet>>>>;
+
+// Frames between the sockets and a device, the firmware's interface unless
+// another is given
+pub struct Service<D: Device = SimpleNetwork> {
+    network: Arc<D>,
+    // keyed by VLAN id, None for untagged frames. Never borrowed across
+    // an await, so sockets can be opened while the tasks run.
+    sockets: RefCell<Sockets>,
     send_queue: Arc<ArrayQueue<Packet>>,
+    counters: Arc<Counters>,
+    capture: Arc<Capture>,
+    stopped: AtomicBool,
+    // A frame taken from the send queue is on its way to the device
+    sending: AtomicBool,
+    // Metrics of the whole stack on top, each device has its own
+    metrics: Arc<metrics::Registry>,
 }
 
 impl Service {
     pub fn new() -> Service {
+        Service::with_device(SimpleNetwork::new())
+    }
+}
+
+impl<D: Device + 'static> Service<D> {
+    pub fn with_device(device: D) -> Service<D> {
+        let network = Arc::new(device);
+        let capture = Arc::new(Capture::new(network.mac_address()));
+        let metrics = Arc::new(metrics::Registry::new());
         Service {
-            network: simple_network::SimpleNetwork::new(),
-            sockets: HashMap::new(),
+            network,
+            sockets: RefCell::new(HashMap::new()),
             send_queue: Arc::new(ArrayQueue::new(16)),
+            counters: Arc::new(Counters::new(&metrics)),
+            capture,
+            stopped: AtomicBool::new(false),
+            sending: AtomicBool::new(false),
+            metrics,
         }
     }
 
-    pub fn start(self, e: Arc<dyn Executor>) {
-        let arc = Arc::new(self);
-        e.spawn(Task::new(arc.clone().task_receive()));
-        e.spawn(Task::new(arc.clone().task_send()));
+    pub fn start(self: Arc<Self>, e: Arc<dyn Executor>) {
+        e.spawn(Task::new(self.clone().task_receive()));
+        e.spawn(Task::new(self.task_send()));
+    }
+
+    // Stops the interface for good, the tasks end and sockets stay silent
+    pub fn shutdown(&self) -> uefi::Result {
+        if self.stopped.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.network.shutdown()
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    // Waits until the frames queued so far are handed to the device and
+    // the device is done sending them, at most timeout seconds
+    pub async fn flush(&self, timeout: f64) {
+        let start = asyn::timestamp();
+        while (!self.send_queue.is_empty()
+            || self.sending.load(Ordering::Relaxed)
+            || self.network.in_flight() > 0)
+            && asyn::elapsed(start) < timeout
+        {
+            asyn::sleep(FLUSH_POLL_INTERVAL).await;
+        }
     }
 
     pub fn mac_address(&self) -> MacAddress {
         self.network.mac_address()
     }
 
-    pub fn open(&mut self, p: Type) -> Socket {
+    // Frames go out from the new address at once, the permanent one for
+    // None
+    pub fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result {
+        self.network.set_mac_address(address)
+    }
+
+    // The interface itself, for statistics, filters and link state
+    pub fn interface(&self) -> Arc<D> {
+        self.network.clone()
+    }
+
+    pub fn counters(&self) -> Arc<Counters> {
+        self.counters.clone()
+    }
+
+    pub fn capture(&self) -> Arc<Capture> {
+        self.capture.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<metrics::Registry> {
+        self.metrics.clone()
+    }
+
+    // Untagged sockets, see vlan for tagged ones
+    pub fn open(&self, p: Type) -> Socket {
+        self.open_tagged(None, p)
+    }
+
+    // A virtual interface for a VLAN, priority is used for sent frames
+    pub fn vlan(&self, id: u16, priority: u8) -> Vlan<'_, D> {
+        Vlan {
+            service: self,
+            tag: Tag::new(id, priority),
+        }
+    }
+
+    // Every socket open for an ether type gets its own copy of a frame
+    pub(super) fn open_tagged(&self, vlan: Option<Tag>, p: Type) -> Socket {
         let s = Socket {
             protocol: p,
+            vlan,
             recv_queue: Arc::new(ArrayQueue::new(16)),
             send_queue: self.send_queue.clone(),
         };
-        self.sockets.insert(p, s.recv_queue.clone());
+        let mut sockets = self.sockets.borrow_mut();
+        let queues = sockets.entry((vlan.map(|t| t.id), p)).or_default();
+        queues.retain(queues::is_open);
+        queues.push(s.recv_queue.clone());
         s
     }
 
     async fn task_send(self: Arc<Self>) {
+        // only the first of a run of failures is logged, the log may go
+        // out over this interface
+        let mut failing = false;
         loop {
             let mut p = asyn::queue_pop(self.send_queue.clone()).await;
+            self.sending.store(true, Ordering::Relaxed);
+            self.counters
+                .send_queue
+                .set_max(self.send_queue.len() as u64 + 1);
+            if self.stopped.load(Ordering::Relaxed) {
+                self.sending.store(false, Ordering::Relaxed);
+                return;
+            }
             if p.mac_source() == MacAddress([0; 6]) {
                 p.set_mac_source(self.mac_address());
             }
-            if let Err(e) = self.network.transmit(&p.data[..p.header_size() + p.size()]) {
-                panic!("{}", e);
+            let len = p.header_size() + p.size();
+            if self.capture.running() {
+                self.capture
+                    .record(&p.data[..len], Direction::Outbound)
+                    .await;
+            }
+            // the frame is dropped on failure, the peer's retransmits or
+            // timeouts take it from there
+            let result = self.network.transmit(p.data, len).await;
+            self.sending.store(false, Ordering::Relaxed);
+            match result {
+                Ok(()) => {
+                    failing = false;
+                    self.counters.sent.inc();
+                    self.counters.sent_bytes.add(len as u64);
+                }
+                Err(e) => {
+                    self.counters.transmit_errors.inc();
+                    if !failing {
+                        error!("transmit failed: {:?}", e);
+                    }
+                    failing = true;
+                }
             }
         }
     }
 
     async fn task_receive(self: Arc<Self>) {
+        // seconds to wait after a failed receive, doubled while the device
+        // keeps failing
+        let mut backoff = RECOVERY_BACKOFF;
         loop {
             let mut p = Packet::new();
 
             let usize = match self.network.receive(p.data.as_mut()).await {
-                Ok(v) => v,
-                Err(e) => panic!("{}", e),
+                Ok(v) => {
+                    backoff = RECOVERY_BACKOFF;
+                    v
+                }
+                Err(_) if self.stopped.load(Ordering::Relaxed) => return,
+                Err(Error::Device(Status::BUFFER_TOO_SMALL)) => {
+                    self.counters.malformed.inc();
+                    continue;
+                }
+                Err(e) => {
+                    self.counters.receive_errors.inc();
+                    // like transmit failures, only the first is logged
+                    if backoff == RECOVERY_BACKOFF {
+                        error!("receive failed: {}, resetting the interface", e);
+                    }
+                    let _ = self.network.recover();
+                    asyn::sleep(backoff).await;
+                    backoff = (backoff * 2.0).min(MAX_RECOVERY_BACKOFF);
+                    continue;
+                }
             };
-            p.set_size(usize);
+            if usize < p.header_size() {
+                self.counters.malformed.inc();
+                continue;
+            }
+            p.set_size(usize - p.header_size());
+            self.counters.received.inc();
+            self.counters.received_bytes.add(usize as u64);
+            if self.capture.running() {
+                self.capture
+                    .record(&p.data[..usize], Direction::Inbound)
+                    .await;
+            }
 
-            if let Some(s) = self.sockets.get(&p.ether_type()) {
-                s.push(p).unwrap();
+            // VLAN 0 only carries a priority, the frame is untagged
+            let vlan = p.vlan().map(|t| t.id).filter(|id| *id != 0);
+            let sockets = self.sockets.borrow();
+            let Some(sockets) = sockets.get(&(vlan, p.ether_type())) else {
+                self.counters.unknown_type.inc();
+                continue;
+            };
+            match queues::deliver(sockets, p) {
+                (0, 0) => self.counters.unknown_type.inc(),
+                (_, full) => {
+                    self.counters.queue_full.add(full as u64);
+                    let deepest = sockets.iter().map(|q| q.len()).max().unwrap_or(0);
+                    self.counters.receive_queue.set_max(deepest as u64);
+                }
             }
         }
     }
 }
+
+impl<D: Device + 'static> Network for Service<D> {
+    fn open(&self, p: Type) -> Socket {
+        Service::open(self, p)
+    }
+
+    fn filters(&self) -> Arc<dyn Filters> {
+        self.network.clone()
+    }
+
+    fn metrics(&self) -> Arc<metrics::Registry> {
+        Service::metrics(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{mock::Mock, sim::Simulator};
+
+    #[test]
+    fn flush_waits_for_frames_in_flight() {
+        let sim = Simulator::new(1);
+        let service = Arc::new(Service::with_device(Mock::new(MacAddress([
+            2, 0, 0, 0, 0, 1,
+        ]))));
+        service.clone().start(sim.executor());
+        let device = service.interface();
+        let socket = service.open(Type::ARP);
+
+        // the device holds the frame for half a second
+        socket.send(Packet::new()).unwrap();
+        device.set_in_flight(1);
+        let d = device.clone();
+        sim.spawn(async move {
+            asyn::sleep(0.5).await;
+            d.set_in_flight(0);
+        });
+        let s = service.clone();
+        let waited = sim.block_on(async move {
+            let start = asyn::timestamp();
+            s.flush(2.0).await;
+            asyn::elapsed(start)
+        });
+        assert!((0.5..0.6).contains(&waited), "flushed after {} s", waited);
+        assert_eq!(device.transmitted().len(), 1);
+
+        // a device that never finishes only holds shutdown up to the timeout
+        device.set_in_flight(1);
+        let s = service.clone();
+        let waited = sim.block_on(async move {
+            let start = asyn::timestamp();
+            s.flush(1.0).await;
+            asyn::elapsed(start)
+        });
+        assert!((1.0..1.1).contains(&waited), "flushed after {} s", waited);
+    }
+
+    #[test]
+    fn frames_go_out_from_a_changed_mac_address() {
+        let sim = Simulator::new(1);
+        let permanent = MacAddress([2, 0, 0, 0, 0, 1]);
+        let changed = MacAddress([2, 0, 0, 0, 0, 9]);
+        let service = Arc::new(Service::with_device(Mock::new(permanent)));
+        service.clone().start(sim.executor());
+        let device = service.interface();
+        let socket = service.open(Type::ARP);
+
+        let mut sources = Vec::new();
+        for address in [Some(changed), None] {
+            service.set_mac_address(address).unwrap();
+            socket.send(Packet::new()).unwrap();
+            let s = service.clone();
+            sim.block_on(async move { s.flush(1.0).await });
+            sources.push(MacAddress(
+                device.transmitted()[0][6..12].try_into().unwrap(),
+            ));
+        }
+        assert_eq!(sources, [changed, permanent]);
+        assert_eq!(service.mac_address(), permanent);
+    }
+}
@@ -1,18 +1,72 @@
 extern crate alloc;
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{
-    error::Error,
+    cell::RefCell,
     future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
-use uefi::{boot, proto::network::snp, Status};
+use log::warn;
+use uefi::{
+    boot,
+    proto::network::{
+        snp::{self, NetworkState, ReceiveFlags},
+        MacAddress as UefiMacAddress,
+    },
+    Status,
+};
+
+use super::{Device, Filters, MacAddress};
+use crate::{asyn, network::Error};
+
+// Frames handed to the driver and not recycled yet
+const MAX_IN_FLIGHT: usize = 8;
+// How long a frame may wait for the driver to take it
+const TRANSMIT_TIMEOUT: f64 = 1.0;
+
+pub use snp::NetworkStats as Statistics;
+
+// Frames the interface hands up, applied with set_receive_filters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiveFilters {
+    pub unicast: bool,
+    pub broadcast: bool,
+    pub promiscuous: bool,
+    // Every multicast frame, not only the listed groups
+    pub all_multicast: bool,
+    pub multicast: Vec<MacAddress>,
+}
+
+impl Default for ReceiveFilters {
+    fn default() -> ReceiveFilters {
+        ReceiveFilters {
+            unicast: true,
+            broadcast: true,
+            promiscuous: false,
+            all_multicast: false,
+            multicast: Vec::new(),
+        }
+    }
+}
 
-use super::MacAddress;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    pub initialized: bool,
+    // None when the driver cannot tell
+    pub media_present: Option<bool>,
+    pub permanent_address: MacAddress,
+    pub mac_address_changeable: bool,
+    pub max_packet_size: u32,
+    pub max_multicast: u32,
+}
 
 pub struct SimpleNetwork {
     sn: boot::ScopedProtocol<snp::SimpleNetwork>,
+    filters: RefCell<ReceiveFilters>,
+    // The driver reads these until it recycles them
+    in_flight: RefCell<Vec<Box<[u8]>>>,
+    max_in_flight: usize,
 }
 
 impl SimpleNetwork {
@@ -27,7 +81,24 @@ impl SimpleNetwork {
         sn.get_interrupt_status().unwrap();
         sn.reset_statistics().unwrap();
 
-        SimpleNetwork { sn }
+        let max_in_flight = if sn.mode().multiple_tx_supported {
+            MAX_IN_FLIGHT
+        } else {
+            1
+        };
+        let network = SimpleNetwork {
+            sn,
+            filters: RefCell::new(ReceiveFilters::default()),
+            in_flight: RefCell::new(Vec::new()),
+            max_in_flight,
+        };
+        if let Err(e) = network.set_receive_filters(ReceiveFilters::default()) {
+            warn!(
+                "failed to set receive filters, using firmware defaults: {:?}",
+                e
+            );
+        }
+        network
     }
 
     pub fn receive<'a>(&'a self, buf: &'a mut [u8]) -> ReceiveFuture<'a> {
@@ -37,15 +108,178 @@ impl SimpleNetwork {
         }
     }
 
-    // TODO: improve speed
-    pub fn transmit<'a>(&'a self, buf: &'a [u8]) -> Result<(), Box<dyn Error>> {
-        self.sn.transmit(0, buf, None, None, None)?;
-        while self.sn.get_recycled_transmit_buffer_status()?.is_none() {}
+    // Hands the first len bytes of the buffer to the driver, ready once it
+    // took them. Several frames can be in flight; busy drivers are retried.
+    pub fn transmit(&self, buffer: Box<[u8]>, len: usize) -> TransmitFuture<'_> {
+        TransmitFuture {
+            network: self,
+            buffer: Some(buffer),
+            len,
+            start: asyn::timestamp(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.recycle();
+        self.in_flight.borrow().len()
+    }
+
+    // Releases the buffers the driver is done with
+    fn recycle(&self) {
+        let mut in_flight = self.in_flight.borrow_mut();
+        while !in_flight.is_empty() {
+            match self.sn.get_recycled_transmit_buffer_status() {
+                Ok(Some(done)) => {
+                    let done = done.as_ptr().cast_const();
+                    in_flight.retain(|b| b.as_ptr() != done);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    // Brings the interface back after a device error: started and
+    // initialized again if it was stopped, reset otherwise. Frames in
+    // flight are given up and the receive filters applied again.
+    pub fn recover(&self) -> Result<(), Error> {
+        match self.sn.mode().state {
+            NetworkState::STOPPED => {
+                self.sn.start()?;
+                self.sn.initialize(0, 0)?;
+            }
+            NetworkState::STARTED => self.sn.initialize(0, 0)?,
+            _ => self.sn.reset(false)?,
+        }
+        self.in_flight.borrow_mut().clear();
+        self.set_receive_filters(self.receive_filters())?;
         Ok(())
     }
 
+    // Leaves the interface stopped, as the firmware expects it before
+    // another image takes over
+    pub fn shutdown(&self) -> uefi::Result {
+        self.sn.shutdown()?;
+        let r = self.sn.stop();
+        self.in_flight.borrow_mut().clear();
+        r
+    }
+
     pub fn mac_address(&self) -> MacAddress {
-        MacAddress(self.sn.mode().current_address.0[0..6].try_into().unwrap())
+        mac(&self.sn.mode().current_address)
+    }
+
+    // Sets a new station address, or the permanent one for None
+    pub fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result {
+        match address {
+            Some(a) => self.sn.station_address(false, Some(&a.into())),
+            None => self.sn.station_address(true, None),
+        }
+    }
+
+    pub fn statistics(&self) -> uefi::Result<Statistics> {
+        self.sn.collect_statistics()
+    }
+
+    pub fn reset_statistics(&self) -> uefi::Result {
+        self.sn.reset_statistics()
+    }
+
+    pub fn receive_filters(&self) -> ReceiveFilters {
+        self.filters.borrow().clone()
+    }
+
+    // Settings the interface does not support are left off. A multicast
+    // list longer than the interface takes falls back to all multicast.
+    pub fn set_receive_filters(&self, filters: ReceiveFilters) -> uefi::Result {
+        let mode = self.sn.mode();
+        let supported = ReceiveFlags::from_bits_truncate(mode.receive_filter_mask);
+        let mut enable = ReceiveFlags::empty();
+        enable.set(ReceiveFlags::UNICAST, filters.unicast);
+        enable.set(ReceiveFlags::BROADCAST, filters.broadcast);
+        enable.set(ReceiveFlags::PROMISCUOUS, filters.promiscuous);
+        enable.set(
+            ReceiveFlags::PROMISCUOUS_MULTICAST,
+            filters.all_multicast || filters.multicast.len() > mode.max_mcast_filter_count as usize,
+        );
+        let listed =
+            !filters.multicast.is_empty() && !enable.contains(ReceiveFlags::PROMISCUOUS_MULTICAST);
+        enable.set(ReceiveFlags::MULTICAST, listed);
+        let enable = enable & supported;
+
+        let list: Vec<UefiMacAddress> = filters.multicast.iter().map(|&m| m.into()).collect();
+        self.sn.receive_filters(
+            enable,
+            supported - enable,
+            !enable.contains(ReceiveFlags::MULTICAST),
+            if enable.contains(ReceiveFlags::MULTICAST) {
+                Some(&list)
+            } else {
+                None
+            },
+        )?;
+        *self.filters.borrow_mut() = filters;
+        Ok(())
+    }
+
+    pub fn link(&self) -> Link {
+        // media presence is refreshed by a status call
+        let _ = self.sn.get_interrupt_status();
+        let mode = self.sn.mode();
+        Link {
+            initialized: mode.state == NetworkState::INITIALIZED,
+            media_present: mode.media_present_supported.then_some(mode.media_present),
+            permanent_address: mac(&mode.permanent_address),
+            mac_address_changeable: mode.mac_address_changeable,
+            max_packet_size: mode.max_packet_size,
+            max_multicast: mode.max_mcast_filter_count,
+        }
+    }
+}
+
+fn mac(address: &UefiMacAddress) -> MacAddress {
+    MacAddress(address.0[0..6].try_into().unwrap())
+}
+
+pub struct TransmitFuture<'a> {
+    network: &'a SimpleNetwork,
+    buffer: Option<Box<[u8]>>,
+    len: usize,
+    start: u64,
+}
+
+impl Future for TransmitFuture<'_> {
+    // Device errors mean retrying will not help, a timeout that the
+    // driver stayed busy
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+        let network = self.network;
+        network.recycle();
+        let timed_out = asyn::elapsed(self.start) > TRANSMIT_TIMEOUT;
+        if network.in_flight.borrow().len() >= network.max_in_flight {
+            if timed_out {
+                return Poll::Ready(Err(Error::Timeout));
+            }
+            return Poll::Pending;
+        }
+        let Some(buffer) = self.buffer.take() else {
+            return Poll::Ready(Ok(()));
+        };
+        match network
+            .sn
+            .transmit(0, &buffer[..self.len], None, None, None)
+        {
+            Ok(()) => {
+                network.in_flight.borrow_mut().push(buffer);
+                Poll::Ready(Ok(()))
+            }
+            Err(e) if e.status() == Status::NOT_READY && !timed_out => {
+                self.buffer = Some(buffer);
+                Poll::Pending
+            }
+            Err(e) if e.status() == Status::NOT_READY => Poll::Ready(Err(Error::Timeout)),
+            Err(e) => Poll::Ready(Err(Error::Device(e.status()))),
+        }
     }
 }
 
@@ -55,18 +289,57 @@ pub struct ReceiveFuture<'a> {
 }
 
 impl Future for ReceiveFuture<'_> {
-    type Output = Result<usize, Box<dyn Error>>;
+    type Output = Result<usize, Error>;
 
     fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
         match self.sn.receive(self.buffer, None, None, None, None) {
             Ok(v) => Poll::Ready(Ok(v)),
-            Err(e) => {
-                if e.status() != Status::NOT_READY {
-                    Poll::Ready(Err(Box::new(e)))
-                } else {
-                    Poll::Pending
-                }
-            }
+            Err(e) if e.status() == Status::NOT_READY => Poll::Pending,
+            Err(e) => Poll::Ready(Err(Error::Device(e.status()))),
         }
     }
 }
+
+impl Filters for SimpleNetwork {
+    fn receive_filters(&self) -> ReceiveFilters {
+        SimpleNetwork::receive_filters(self)
+    }
+
+    fn set_receive_filters(&self, filters: ReceiveFilters) -> uefi::Result {
+        SimpleNetwork::set_receive_filters(self, filters)
+    }
+}
+
+impl Device for SimpleNetwork {
+    fn mac_address(&self) -> MacAddress {
+        SimpleNetwork::mac_address(self)
+    }
+
+    fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result {
+        SimpleNetwork::set_mac_address(self, address)
+    }
+
+    fn receive<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize, Error>> + 'a {
+        SimpleNetwork::receive(self, buf)
+    }
+
+    fn transmit(
+        &self,
+        buffer: Box<[u8]>,
+        len: usize,
+    ) -> impl Future<Output = Result<(), Error>> + '_ {
+        SimpleNetwork::transmit(self, buffer, len)
+    }
+
+    fn in_flight(&self) -> usize {
+        SimpleNetwork::in_flight(self)
+    }
+
+    fn recover(&self) -> Result<(), Error> {
+        SimpleNetwork::recover(self)
+    }
+
+    fn shutdown(&self) -> uefi::Result {
+        SimpleNetwork::shutdown(self)
+    }
+}
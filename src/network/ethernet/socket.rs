@@ -4,11 +4,12 @@ use alloc::sync::Arc;
 
 use crossbeam_queue::ArrayQueue;
 
-use super::{Packet, Type};
-use crate::asyn;
+use super::{Packet, Tag, Type};
+use crate::{asyn, network::Error};
 
 pub struct Socket {
     pub(super) protocol: Type,
+    pub(super) vlan: Option<Tag>,
     pub(super) recv_queue: Arc<ArrayQueue<Packet>>,
     pub(super) send_queue: Arc<ArrayQueue<Packet>>,
 }
@@ -17,9 +18,10 @@ impl Socket {
     pub async fn receive(&self) -> Packet {
         asyn::queue_pop(self.recv_queue.clone()).await
     }
-    pub fn send(&self, mut p: Packet) {
+    // Queues the frame for the device, failing when the queue is full
+    pub fn send(&self, mut p: Packet) -> Result<(), Error> {
+        p.set_vlan(self.vlan);
         p.set_ether_type(self.protocol);
-        // TODO: push async in case queue is full
-        self.send_queue.push(p).unwrap();
+        self.send_queue.push(p).map_err(|_| Error::QueueFull)
     }
 }
@@ -0,0 +1,91 @@
+extern crate alloc;
+
+use alloc::sync::Arc;
+
+use super::{Device, Filters, Network, Service, SimpleNetwork, Socket, Type};
+use crate::metrics;
+
+// 802.1Q tag control information
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tag {
+    // 1 to 4094, 0 only marks the priority
+    pub id: u16,
+    // 802.1p priority code point, 0 to 7
+    pub priority: u8,
+    pub drop_eligible: bool,
+}
+
+impl Tag {
+    pub fn new(id: u16, priority: u8) -> Tag {
+        Tag {
+            id: id & 0x0fff,
+            priority: priority & 0x7,
+            drop_eligible: false,
+        }
+    }
+
+    pub(super) fn from_tci(tci: u16) -> Tag {
+        Tag {
+            id: tci & 0x0fff,
+            priority: (tci >> 13) as u8,
+            drop_eligible: tci & 0x1000 != 0,
+        }
+    }
+
+    pub(super) fn tci(&self) -> u16 {
+        ((self.priority as u16 & 0x7) << 13)
+            | if self.drop_eligible { 0x1000 } else { 0 }
+            | (self.id & 0x0fff)
+    }
+}
+
+// A virtual interface on one VLAN of the physical one. Sockets opened on
+// it send tagged frames and only see frames with its VLAN id, so an
+// ARP/IP stack built on it is separate from the untagged one.
+pub struct Vlan<'a, D: Device = SimpleNetwork> {
+    pub(super) service: &'a Service<D>,
+    pub(super) tag: Tag,
+}
+
+impl<D: Device + 'static> Network for Vlan<'_, D> {
+    fn open(&self, p: Type) -> Socket {
+        self.service.open_tagged(Some(self.tag), p)
+    }
+
+    fn filters(&self) -> Arc<dyn Filters> {
+        self.service.filters()
+    }
+
+    fn metrics(&self) -> Arc<metrics::Registry> {
+        self.service.metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Packet;
+    use super::*;
+
+    #[test]
+    fn tag_and_untag() {
+        let mut p = Packet::new();
+        p.set_size(4);
+        p.set_ether_type(Type::IPV4);
+        p.data_mut().copy_from_slice(&[1, 2, 3, 4]);
+
+        let tag = Tag {
+            id: 42,
+            priority: 5,
+            drop_eligible: true,
+        };
+        p.set_vlan(Some(tag));
+        assert_eq!(p.header_size(), 18);
+        assert_eq!(p.data[12..18], [0x81, 0x00, 0xb0, 42, 0x08, 0x00]);
+        assert_eq!((p.vlan(), p.ether_type()), (Some(tag), Type::IPV4));
+        assert_eq!(p.data(), [1, 2, 3, 4]);
+
+        p.set_vlan(None);
+        assert_eq!((p.vlan(), p.ether_type()), (None, Type::IPV4));
+        assert_eq!(p.data(), [1, 2, 3, 4]);
+    }
+}
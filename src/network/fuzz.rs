@@ -0,0 +1,269 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{arp, ethernet, harness::Harness, icmp, ip, mock::Mock, tcp, udp};
+use crate::asyn::Task;
+
+pub const MAC: ethernet::MacAddress = ethernet::MacAddress([2, 0, 0, 0, 0, 1]);
+pub const ADDRESS: ip::Address = ip::Address([10, 0, 0, 1]);
+pub const UDP_PORT: u16 = 7;
+pub const TCP_PORT: u16 = 80;
+
+// Virtual nanoseconds between two rounds of the executor
+const TICK: u64 = 1_000_000;
+// Rounds after the last frame, for timers and retransmissions
+const SETTLE: usize = 2000;
+
+// Every service with a receive path on a mock device, with a udp socket
+// and a tcp listener that reads what connections send
+struct Stack {
+    harness: Harness,
+    ethernet: Arc<ethernet::Service<Mock>>,
+}
+
+impl Stack {
+    fn new() -> Stack {
+        let harness = Harness::new(TICK);
+        let ethernet = Arc::new(ethernet::Service::with_device(Mock::new(MAC)));
+        let arp = Arc::new(arp::Service::new(ADDRESS, MAC, &*ethernet));
+        let ip = Arc::new(ip::Service::new(
+            &*ethernet,
+            arp.clone(),
+            ADDRESS,
+            ip::Address([255, 255, 255, 0]),
+            ip::Address([10, 0, 0, 254]),
+        ));
+        let stack = Stack { harness, ethernet };
+        let services = ip.clone();
+        let (icmp, udp, tcp) = stack.harness.block_on(async move {
+            let ip = services;
+            (
+                icmp::Service::new(ip.clone()).await,
+                udp::Service::new(ip.clone()).await,
+                tcp::Service::new(ip.clone()).await,
+            )
+        });
+        let e = stack.harness.executor();
+        stack.ethernet.clone().start(e.clone());
+        arp.start(e.clone());
+        ip.start(e.clone());
+        Arc::new(icmp).start(e.clone());
+        let udp = Arc::new(udp);
+        udp.clone().start(e.clone());
+        let tcp = Arc::new(tcp);
+        tcp.clone().start(e.clone());
+        let socket = stack
+            .harness
+            .block_on(async move { udp.open(UDP_PORT).await.unwrap() });
+        e.spawn(Task::new(async move {
+            loop {
+                let _ = socket.receive(1.0).await;
+            }
+        }));
+        let listener = stack
+            .harness
+            .block_on(async move { tcp.listen(TCP_PORT).await.unwrap() });
+        e.spawn(Task::new(async move {
+            let mut buf = [0; 512];
+            while let Ok(mut stream) = listener.accept().await {
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        }));
+        stack
+    }
+}
+
+// The fuzz target: the input is a run of frames, each preceded by its
+// length as two big endian bytes. They are received one by one, the stack
+// gets to answer each, then runs its timers. Panics are the findings.
+// fuzz/ holds the cargo fuzz target, run with `cargo fuzz run frames`.
+pub fn frames(data: &[u8]) {
+    let stack = Stack::new();
+    let mut rest = data;
+    while rest.len() >= 2 {
+        let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let frame = &rest[2..][..len.min(rest.len() - 2)];
+        rest = &rest[2 + frame.len()..];
+        stack.ethernet.interface().inject(frame);
+        for _ in 0..4 {
+            stack.harness.step();
+        }
+    }
+    for _ in 0..SETTLE {
+        stack.harness.step();
+    }
+}
+
+// Joins frames into an input for frames()
+pub fn input(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for f in frames {
+        data.extend_from_slice(&(f.len() as u16).to_be_bytes());
+        data.extend_from_slice(f);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::harness::Rng;
+
+    const PEER_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const PEER: ip::Address = ip::Address([10, 0, 0, 2]);
+
+    fn ether(t: u16, payload: &[u8]) -> Vec<u8> {
+        let mut f = Vec::new();
+        f.extend_from_slice(&MAC.0);
+        f.extend_from_slice(&PEER_MAC);
+        f.extend_from_slice(&t.to_be_bytes());
+        f.extend_from_slice(payload);
+        f
+    }
+
+    fn arp_request() -> Vec<u8> {
+        let mut p = Vec::from([0, 1, 8, 0, 6, 4, 0, 1]);
+        p.extend_from_slice(&PEER_MAC);
+        p.extend_from_slice(&PEER.0);
+        p.extend_from_slice(&[0; 6]);
+        p.extend_from_slice(&ADDRESS.0);
+        ether(0x0806, &p)
+    }
+
+    fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut p = Vec::from([0x45, 0, 0, 0, 0, 1, 0x40, 0, 64, protocol, 0, 0]);
+        p[2..4].copy_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        p.extend_from_slice(&PEER.0);
+        p.extend_from_slice(&ADDRESS.0);
+        p.extend_from_slice(payload);
+        ether(0x0800, &p)
+    }
+
+    // Recomputes the checksums a mutation broke, so the frame gets past
+    // them to the parsers behind
+    fn fix_checksums(f: &mut [u8]) {
+        if f.len() < 34 || f[12..14] != [8, 0] {
+            return;
+        }
+        let header = ((f[14] & 0xf) as usize * 4).clamp(20, f.len() - 14);
+        f[24..26].fill(0);
+        let c = ip::checksum(&f[14..14 + header]);
+        f[24..26].copy_from_slice(&c.to_be_bytes());
+        let segment = 14 + header;
+        let at = match f[23] {
+            1 => segment + 2,
+            6 => segment + 16,
+            17 => segment + 6,
+            _ => return,
+        };
+        if f.len() < at + 2 {
+            return;
+        }
+        f[at..at + 2].fill(0);
+        let c = match f[23] {
+            1 => ip::checksum(&f[segment..]),
+            p => ip::pseudo_header_checksum(&PEER, &ADDRESS, ip::Protocol(p), &f[segment..]),
+        };
+        f[at..at + 2].copy_from_slice(&c.to_be_bytes());
+    }
+
+    // Valid frames for each receive path
+    fn seeds() -> Vec<Vec<u8>> {
+        let mut echo = Vec::from([8, 0, 0, 0, 0, 1, 0, 1]);
+        echo.extend_from_slice(&[0x55; 32]);
+        let mut udp = Vec::from([0x30, 0x39, 0, UDP_PORT as u8, 0, 12, 0, 0]);
+        udp.extend_from_slice(b"ping");
+        let mut syn = Vec::from([0x30, 0x39, 0, TCP_PORT as u8]);
+        syn.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0]);
+        let mut data = syn.clone();
+        data[13] = 0x18;
+        data.extend_from_slice(b"GET / HTTP/1.0\r\n\r\n");
+        let mut seeds = Vec::from([
+            arp_request(),
+            ipv4(1, &echo),
+            ipv4(17, &udp),
+            ipv4(6, &syn),
+            ipv4(6, &data),
+            // an icmp error quoting one of our packets
+            ipv4(
+                1,
+                &[
+                    3, 3, 0, 0, 0, 0, 0, 0, 0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0,
+                ],
+            ),
+        ]);
+        // the echo request again, tagged for vlan 5
+        let mut tagged = seeds[1].clone();
+        tagged.splice(12..12, [0x81, 0, 0, 5]);
+        seeds.push(tagged);
+        for s in seeds.iter_mut() {
+            fix_checksums(s);
+        }
+        seeds
+    }
+
+    // Flips, overwrites, truncates or extends a seed
+    fn mutate(rng: &mut Rng, seed: &[u8]) -> Vec<u8> {
+        let mut f = seed.to_vec();
+        for _ in 0..1 + rng.below(4) {
+            match rng.below(5) {
+                0 => f.truncate(rng.below(f.len() + 1)),
+                1 => f.extend((0..rng.below(64)).map(|_| rng.next() as u8)),
+                2 if !f.is_empty() => {
+                    let i = rng.below(f.len());
+                    f[i] ^= 1 << rng.below(8);
+                }
+                _ if !f.is_empty() => {
+                    let i = rng.below(f.len());
+                    f[i] = [0, 0xff, 0x7f, 0x80, rng.next() as u8][rng.below(5)];
+                }
+                _ => {}
+            }
+        }
+        if rng.below(2) == 0 {
+            fix_checksums(&mut f);
+        }
+        f
+    }
+
+    #[test]
+    fn seeds_are_answered() {
+        let stack = Stack::new();
+        let mock = stack.ethernet.interface();
+        for seed in seeds() {
+            mock.inject(&seed);
+        }
+        for _ in 0..100 {
+            stack.harness.step();
+        }
+        let sent = mock.transmitted();
+        let answered = |t: u16, protocol: Option<u8>| {
+            sent.iter().any(|f| {
+                f[12..14] == t.to_be_bytes() && protocol.is_none_or(|p| f.len() > 23 && f[23] == p)
+            })
+        };
+        assert!(answered(0x0806, None), "no arp reply");
+        assert!(answered(0x0800, Some(1)), "no echo reply");
+        assert!(answered(0x0800, Some(6)), "no syn-ack");
+    }
+
+    #[test]
+    fn mutated_frames() {
+        let seeds = seeds();
+        let mut rng = Rng::new(0x9e3779b97f4a7c15);
+        for _ in 0..300 {
+            let batch: Vec<Vec<u8>> = (0..1 + rng.below(8))
+                .map(|_| {
+                    let seed = &seeds[rng.below(seeds.len())];
+                    mutate(&mut rng, seed)
+                })
+                .collect();
+            frames(&input(&batch));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+extern crate alloc;
+
+use alloc::{rc::Rc, sync::Arc};
+use core::{cell::RefCell, future::Future};
+
+use crate::asyn::{self, Executor, SimpleExecutor, Task};
+
+// Virtual seconds join waits before giving up on a task
+const MAX_RUN: f64 = 120.0;
+
+// An executor on the virtual clock, shared by the simulator, the fuzz
+// target and replays. Time only moves between its rounds, tick
+// nanoseconds at a time.
+pub struct Harness {
+    executor: Arc<SimpleExecutor>,
+    tick: u64,
+}
+
+impl Harness {
+    pub fn new(tick: u64) -> Harness {
+        Harness {
+            executor: Arc::new(SimpleExecutor::new()),
+            tick,
+        }
+    }
+
+    pub fn executor(&self) -> Arc<dyn Executor> {
+        self.executor.clone()
+    }
+
+    // One round: every task is polled once and the clock moves on
+    pub fn step(&self) {
+        self.executor.run_once();
+        asyn::advance(self.tick);
+    }
+
+    // Runs a task, its result is taken with join
+    pub fn spawn<T: 'static>(&self, f: impl Future<Output = T> + 'static) -> Handle<T> {
+        let result = Rc::new(RefCell::new(None));
+        let r = result.clone();
+        self.executor.spawn(Task::new(async move {
+            *r.borrow_mut() = Some(f.await);
+        }));
+        Handle(result)
+    }
+
+    // Runs rounds until the task is done, panicking if that takes too long
+    pub fn join<T: 'static>(&self, handle: Handle<T>) -> T {
+        let start = asyn::timestamp();
+        loop {
+            self.step();
+            if let Some(v) = handle.0.borrow_mut().take() {
+                return v;
+            }
+            assert!(
+                asyn::elapsed(start) < MAX_RUN,
+                "task did not finish within {} s",
+                MAX_RUN
+            );
+        }
+    }
+
+    pub fn block_on<T: 'static>(&self, f: impl Future<Output = T> + 'static) -> T {
+        let handle = self.spawn(f);
+        self.join(handle)
+    }
+}
+
+// The result of a spawned task, once it is done
+pub struct Handle<T>(Rc<RefCell<Option<T>>>);
+
+// xorshift64, so runs with the same seed are the same
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift never leaves 0
+        Rng(seed | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Below n, which must not be 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // From 0 up to 1
+    pub fn random(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,649 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use uefi::Status;
+
+use super::{connection::Connection, Error, Url};
+use crate::{
+    asyn,
+    network::{dns, ip, tcp, Sink},
+};
+
+const MAX_REDIRECTS: usize = 5;
+const MAX_HEADERS: usize = 100;
+const MAX_IDLE_CONNECTIONS: usize = 4;
+const USER_AGENT: &str = "rust-uefi-app";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Method> {
+        match s {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            _ => None,
+        }
+    }
+
+    // Whether sending the request twice has the effect of sending it once
+    pub fn idempotent(&self) -> bool {
+        !matches!(self, Method::Post)
+    }
+}
+
+pub struct Request<'a> {
+    pub method: Method,
+    pub url: &'a str,
+    pub headers: Vec<(&'a str, &'a str)>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    pub fn get(url: &'a str) -> Request<'a> {
+        Request {
+            method: Method::Get,
+            url,
+            headers: Vec::new(),
+            body: &[],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    // Names are lowercased
+    pub headers: Vec<(String, String)>,
+    // Final url after following redirects
+    pub url: Url,
+    // Body bytes written to the sink
+    pub length: u64,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+enum Body {
+    None,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+// Throws away the bodies of redirects
+struct Discard;
+
+impl Sink for Discard {
+    fn write(&mut self, _: &[u8]) -> Result<(), Status> {
+        Ok(())
+    }
+}
+
+pub struct Client {
+    tcp: Arc<tcp::Service>,
+    resolver: Option<Arc<dns::Resolver>>,
+    // Kept alive connections, reused for requests to the same server
+    idle: asyn::Mutex<Vec<Connection>>,
+}
+
+impl Client {
+    pub fn new(tcp: Arc<tcp::Service>, resolver: Option<Arc<dns::Resolver>>) -> Client {
+        Client {
+            tcp,
+            resolver,
+            idle: asyn::Mutex::new(Vec::new()),
+        }
+    }
+
+    // Sends the request, following redirects, and streams the final body
+    // into the sink. Progress gets the bytes received so far and the total
+    // when the server announced it.
+    pub async fn send(
+        &self,
+        request: &Request<'_>,
+        sink: &mut dyn Sink,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> Result<Response, Error> {
+        let mut url = Url::parse(request.url)?;
+        let mut method = request.method;
+        let mut body = request.body;
+        let origin = url.clone();
+
+        for _ in 0..=MAX_REDIRECTS {
+            let address = self.resolve(&url.host).await?;
+            // credentials only go to the server they were meant for
+            let credentials =
+                url.host.eq_ignore_ascii_case(&origin.host) && url.port == origin.port;
+            let head = Self::head(request, method, &url, body, credentials);
+
+            // the server may have closed an idle connection after reading
+            // the request, so only requests that can be sent again use one
+            let idle = match method.idempotent() {
+                true => self.idle_connection(address, url.port).await,
+                false => None,
+            };
+            let mut connection = match idle {
+                Some(mut c) => match Self::exchange(&mut c, &head, body, method).await {
+                    Ok(response) => Some((c, response)),
+                    Err(_) => {
+                        c.close().await;
+                        None
+                    }
+                },
+                None => None,
+            };
+            if connection.is_none() {
+                let mut c = self.connect(address, url.port).await?;
+                match Self::exchange(&mut c, &head, body, method).await {
+                    Ok(response) => connection = Some((c, response)),
+                    Err(e) => {
+                        c.close().await;
+                        return Err(e);
+                    }
+                }
+            }
+            let (mut c, (mut response, framing, keep_alive)) = connection.unwrap();
+
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 => response.header("location").map(String::from),
+                _ => None,
+            };
+            let result = match location {
+                Some(_) => Self::body(&mut c, framing, &mut Discard, &mut |_, _| {}).await,
+                None => Self::body(&mut c, framing, sink, progress).await,
+            };
+            let reusable = match &result {
+                Ok((_, complete)) => keep_alive && *complete,
+                Err(_) => false,
+            };
+            if reusable {
+                self.release(c).await;
+            } else {
+                c.close().await;
+            }
+            let (length, _) = result?;
+
+            match location {
+                Some(location) => {
+                    if response.status == 303
+                        || (matches!(response.status, 301 | 302) && method == Method::Post)
+                    {
+                        method = Method::Get;
+                        body = &[];
+                    }
+                    url = url.join(&location)?;
+                }
+                None => {
+                    response.url = url;
+                    response.length = length;
+                    return Ok(response);
+                }
+            }
+        }
+
+        Err(Error::TooManyRedirects)
+    }
+
+    async fn resolve(&self, host: &str) -> Result<ip::Address, Error> {
+        if let Ok(address) = host.parse() {
+            return Ok(address);
+        }
+        let resolver = self.resolver.as_ref().ok_or(Error::InvalidUrl)?;
+        let addresses = resolver.lookup(host).await.map_err(Error::Resolve)?;
+        addresses
+            .first()
+            .copied()
+            .ok_or(Error::Resolve(dns::Error::NoData))
+    }
+
+    async fn connect(&self, address: ip::Address, port: u16) -> Result<Connection, Error> {
+        let stream = self.tcp.connect(address, port).await.map_err(Error::Tcp)?;
+        Ok(Connection::new(stream, address, port))
+    }
+
+    async fn idle_connection(&self, address: ip::Address, port: u16) -> Option<Connection> {
+        let mut idle = self.idle.lock().await;
+        let i = idle
+            .iter()
+            .position(|c| c.address == address && c.port == port)?;
+        Some(idle.swap_remove(i))
+    }
+
+    async fn release(&self, connection: Connection) {
+        let mut idle = self.idle.lock().await;
+        idle.push(connection);
+        if idle.len() > MAX_IDLE_CONNECTIONS {
+            let oldest = idle.remove(0);
+            drop(idle);
+            oldest.close().await;
+        }
+    }
+
+    fn head(
+        request: &Request<'_>,
+        method: Method,
+        url: &Url,
+        body: &[u8],
+        credentials: bool,
+    ) -> String {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\n",
+            method.as_str(),
+            url.path,
+            url.authority(),
+            USER_AGENT,
+        );
+        if !body.is_empty() || matches!(method, Method::Post | Method::Put) {
+            head += &format!("Content-Length: {}\r\n", body.len());
+        }
+        for (name, value) in request.headers.iter() {
+            // the body and its type are dropped when a redirect turns the
+            // request into a GET
+            if body.is_empty() && name.eq_ignore_ascii_case("content-type") {
+                continue;
+            }
+            if !credentials
+                && (name.eq_ignore_ascii_case("authorization")
+                    || name.eq_ignore_ascii_case("cookie"))
+            {
+                continue;
+            }
+            head += &format!("{}: {}\r\n", name, value);
+        }
+        head += "\r\n";
+        head
+    }
+
+    // Sends the request and reads the response head, returning it with the
+    // framing of the body and whether the connection can be kept alive
+    async fn exchange(
+        c: &mut Connection,
+        head: &str,
+        body: &[u8],
+        method: Method,
+    ) -> Result<(Response, Body, bool), Error> {
+        c.write(head.as_bytes()).await?;
+        if !body.is_empty() {
+            c.write(body).await?;
+        }
+
+        loop {
+            let status_line = c.read_line().await?;
+            let mut parts = status_line.splitn(3, ' ');
+            let version = parts.next().unwrap_or("");
+            if !version.starts_with("HTTP/1.") {
+                return Err(Error::Malformed);
+            }
+            let status: u16 = parts
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or(Error::Malformed)?;
+            let reason = parts.next().unwrap_or("").into();
+
+            let mut headers = Vec::new();
+            loop {
+                let line = c.read_line().await?;
+                if line.is_empty() {
+                    break;
+                }
+                if headers.len() >= MAX_HEADERS {
+                    return Err(Error::Malformed);
+                }
+                let (name, value) = line.split_once(':').ok_or(Error::Malformed)?;
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().into()));
+            }
+
+            // interim responses like 100 Continue are skipped
+            if (100..200).contains(&status) {
+                continue;
+            }
+
+            let response = Response {
+                status,
+                reason,
+                headers,
+                url: Url {
+                    host: String::new(),
+                    port: 0,
+                    path: String::new(),
+                },
+                length: 0,
+            };
+
+            let connection = response.header("connection").unwrap_or("");
+            let mut keep_alive = if version == "HTTP/1.0" {
+                connection.eq_ignore_ascii_case("keep-alive")
+            } else {
+                !connection.eq_ignore_ascii_case("close")
+            };
+
+            let framing = if method == Method::Head || status == 204 || status == 304 {
+                Body::None
+            } else if response
+                .header("transfer-encoding")
+                .is_some_and(|t| t.to_ascii_lowercase().contains("chunked"))
+            {
+                Body::Chunked
+            } else if let Some(length) = response.header("content-length") {
+                Body::Length(length.parse().map_err(|_| Error::Malformed)?)
+            } else {
+                keep_alive = false;
+                Body::UntilClose
+            };
+
+            return Ok((response, framing, keep_alive));
+        }
+    }
+
+    // Streams the body into the sink, returning its length and whether it
+    // was read completely
+    async fn body(
+        c: &mut Connection,
+        framing: Body,
+        sink: &mut dyn Sink,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> Result<(u64, bool), Error> {
+        let mut received: u64 = 0;
+        match framing {
+            Body::None => Ok((0, true)),
+            Body::Length(length) => {
+                sink.size_hint(length);
+                while received < length {
+                    let data = c
+                        .read((length - received).min(usize::MAX as u64) as usize)
+                        .await?;
+                    if data.is_empty() {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    sink.write(data).map_err(Error::Io)?;
+                    received += data.len() as u64;
+                    progress(received, Some(length));
+                }
+                Ok((received, true))
+            }
+            Body::Chunked => {
+                loop {
+                    let line = c.read_line().await?;
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    let mut remaining =
+                        u64::from_str_radix(size, 16).map_err(|_| Error::Malformed)?;
+                    if remaining == 0 {
+                        break;
+                    }
+                    while remaining > 0 {
+                        let data = c.read(remaining.min(usize::MAX as u64) as usize).await?;
+                        if data.is_empty() {
+                            return Err(Error::ConnectionClosed);
+                        }
+                        sink.write(data).map_err(Error::Io)?;
+                        remaining -= data.len() as u64;
+                        received += data.len() as u64;
+                        progress(received, None);
+                    }
+                    if !c.read_line().await?.is_empty() {
+                        return Err(Error::Malformed);
+                    }
+                }
+                // trailers are ignored
+                while !c.read_line().await?.is_empty() {}
+                Ok((received, true))
+            }
+            Body::UntilClose => {
+                loop {
+                    let data = c.read(usize::MAX).await?;
+                    if data.is_empty() {
+                        break;
+                    }
+                    sink.write(data).map_err(Error::Io)?;
+                    received += data.len() as u64;
+                    progress(received, None);
+                }
+                Ok((received, false))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    use crate::{
+        asyn::Task,
+        network::{
+            ethernet::MacAddress,
+            sim::{Link, Simulator},
+        },
+    };
+
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 2]);
+    const LARGE: usize = 5000;
+
+    // Request lines with the number of the connection they came on
+    type Log = Rc<RefCell<Vec<(usize, String)>>>;
+
+    // The scripted response for a path
+    fn answer(path: &str) -> String {
+        if let Some(n) = path.strip_prefix("/redirect/") {
+            let n: usize = n.parse().unwrap();
+            if n == 0 {
+                return String::from("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone");
+            }
+            let status = match n % 2 {
+                0 => "302 Found",
+                _ => "301 Moved Permanently",
+            };
+            return format!(
+                "HTTP/1.1 {}\r\nLocation: /redirect/{}\r\nContent-Length: 0\r\n\r\n",
+                status,
+                n - 1
+            );
+        }
+        match path {
+            "/chunked" => String::from(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: t\r\n\r\n",
+            ),
+            "/large" => format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                LARGE,
+                "x".repeat(LARGE)
+            ),
+            _ => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"),
+        }
+    }
+
+    // Answers request heads until the client closes the connection
+    async fn serve(mut stream: tcp::Stream, connection: usize, log: Log) {
+        let mut pending = Vec::new();
+        let mut buf = [0; 512];
+        loop {
+            while let Some(end) = pending.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head: Vec<u8> = pending.drain(..end + 4).collect();
+                let line = String::from(String::from_utf8(head).unwrap().lines().next().unwrap());
+                let path = String::from(line.split(' ').nth(1).unwrap());
+                log.borrow_mut().push((connection, line));
+                if stream.write(answer(&path).as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            match stream.read(&mut buf).await {
+                Ok(n) if n > 0 => pending.extend_from_slice(&buf[..n]),
+                _ => return stream.close().await,
+            }
+        }
+    }
+
+    fn hosts() -> (Simulator, Client, Log) {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let mac = |n| MacAddress([2, 0, 0, 0, 0, n]);
+        let client = sim.host(switch, mac(1), ip::Address([10, 0, 0, 1]), Link::default());
+        let server = sim.host(switch, mac(2), SERVER, Link::default());
+        let listener = sim.block_on(async move { server.tcp.listen(80).await.unwrap() });
+        let log = Log::default();
+        let (e, l) = (sim.executor(), log.clone());
+        sim.executor().spawn(Task::new(async move {
+            let mut connection = 0;
+            while let Ok(stream) = listener.accept().await {
+                e.spawn(Task::new(serve(stream, connection, l.clone())));
+                connection += 1;
+            }
+        }));
+        (sim, Client::new(client.tcp.clone(), None), log)
+    }
+
+    // The response, the body and the progress reports of a GET
+    async fn get(
+        client: &Client,
+        url: &str,
+    ) -> (Result<Response, Error>, Vec<u8>, Vec<(u64, Option<u64>)>) {
+        let mut body = Vec::new();
+        let mut reports = Vec::new();
+        let response = client
+            .send(&Request::get(url), &mut body, &mut |n, total| {
+                reports.push((n, total))
+            })
+            .await;
+        (response, body, reports)
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let (sim, client, log) = hosts();
+        let results = sim.block_on(async move {
+            let first = get(&client, "http://10.0.0.2/chunked").await;
+            let second = get(&client, "http://10.0.0.2/chunked").await;
+            [first, second]
+        });
+        for (response, body, reports) in results {
+            assert_eq!(response.unwrap().status, 200);
+            assert_eq!(body, b"hello, world");
+            assert_eq!(reports.last(), Some(&(12, None)));
+        }
+        // the trailer was read, the second request found the connection clean
+        let connections: Vec<usize> = log.borrow().iter().map(|(c, _)| *c).collect();
+        assert_eq!(connections, [0, 0]);
+    }
+
+    #[test]
+    fn streams_bodies_of_a_given_length() {
+        let (sim, client, _) = hosts();
+        let (response, body, reports) =
+            sim.block_on(async move { get(&client, "http://10.0.0.2/large").await });
+        let response = response.unwrap();
+        assert_eq!(response.length, LARGE as u64);
+        assert_eq!(body.len(), LARGE);
+        assert!(body.iter().all(|b| *b == b'x'));
+        // reported as it arrives, in more than one piece
+        assert!(reports.len() > 1);
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(reports.last(), Some(&(LARGE as u64, Some(LARGE as u64))));
+    }
+
+    #[test]
+    fn follows_redirects_up_to_the_limit() {
+        let (sim, client, log) = hosts();
+        let (followed, refused) = sim.block_on(async move {
+            let followed = get(&client, "http://10.0.0.2/redirect/5").await;
+            let refused = get(&client, "http://10.0.0.2/redirect/6").await;
+            (followed, refused)
+        });
+        let (response, body, _) = followed;
+        let response = response.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.url.path, "/redirect/0");
+        assert_eq!(body, b"done");
+        assert!(matches!(refused.0, Err(Error::TooManyRedirects)));
+        let log = log.borrow();
+        assert_eq!(log.len(), 6 + 6);
+        assert_eq!(log[0].1, "GET /redirect/5 HTTP/1.1");
+        assert_eq!(log[5].1, "GET /redirect/0 HTTP/1.1");
+    }
+
+    #[test]
+    fn reuses_kept_alive_connections() {
+        let (sim, client, log) = hosts();
+        let statuses = sim.block_on(async move {
+            let mut statuses = Vec::new();
+            for url in ["http://10.0.0.2/large", "http://10.0.0.2/missing"] {
+                statuses.push(get(&client, url).await.0.unwrap().status);
+            }
+            statuses
+        });
+        assert_eq!(statuses, [200, 404]);
+        let log = log.borrow();
+        assert_eq!(
+            *log,
+            [
+                (0, String::from("GET /large HTTP/1.1")),
+                (0, String::from("GET /missing HTTP/1.1"))
+            ]
+        );
+    }
+
+    #[test]
+    fn credentials_stay_with_the_origin() {
+        let request = Request {
+            method: Method::Post,
+            url: "http://a/x",
+            headers: Vec::from([
+                ("Content-Type", "text/plain"),
+                ("Authorization", "Bearer t"),
+                ("Cookie", "c=1"),
+                ("Accept-Language", "en"),
+            ]),
+            body: b"hi",
+        };
+        let url = Url::parse("http://b:8080/y").unwrap();
+
+        let head = Client::head(&request, Method::Post, &url, b"hi", true);
+        assert!(head.contains("Authorization: Bearer t\r\n"));
+        assert!(head.contains("Cookie: c=1\r\n"));
+
+        // a 303 to another server
+        let head = Client::head(&request, Method::Get, &url, &[], false);
+        assert!(head.starts_with("GET /y HTTP/1.1\r\nHost: b:8080\r\n"));
+        assert!(!head.contains("Authorization"));
+        assert!(!head.contains("Cookie"));
+        assert!(!head.contains("Content-"));
+        assert!(head.ends_with("Accept-Language: en\r\n\r\n"));
+    }
+
+    #[test]
+    fn only_post_is_not_idempotent() {
+        for method in [Method::Get, Method::Head, Method::Put, Method::Delete] {
+            assert!(method.idempotent());
+        }
+        assert!(!Method::Post.idempotent());
+    }
+}
@@ -0,0 +1,90 @@
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+
+use super::Error;
+use crate::network::{ip, tcp};
+
+const BUFFER_SIZE: usize = 4096;
+const MAX_LINE: usize = 8192;
+
+// Buffered reader and writer on top of a tcp stream
+pub(super) struct Connection {
+    pub(super) stream: tcp::Stream,
+    pub(super) address: ip::Address,
+    pub(super) port: u16,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl Connection {
+    pub(super) fn new(stream: tcp::Stream, address: ip::Address, port: u16) -> Connection {
+        Connection {
+            stream,
+            address,
+            port,
+            buf: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub(super) async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.stream.write(data).await.map_err(Error::Tcp)
+    }
+
+    // Refills the buffer when it is empty, false at the end of the stream
+    async fn fill(&mut self) -> Result<bool, Error> {
+        if self.start < self.end {
+            return Ok(true);
+        }
+        self.start = 0;
+        self.end = self.stream.read(&mut self.buf).await.map_err(Error::Tcp)?;
+        Ok(self.end > 0)
+    }
+
+    // Reads a line without its terminator
+    pub(super) async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        loop {
+            if !self.fill().await? {
+                return Err(Error::ConnectionClosed);
+            }
+            let available = &self.buf[self.start..self.end];
+            match available.iter().position(|c| *c == b'\n') {
+                Some(i) => {
+                    line.extend_from_slice(&available[..i]);
+                    self.start += i + 1;
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    self.start = self.end;
+                }
+            }
+            if line.len() > MAX_LINE {
+                return Err(Error::Malformed);
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| Error::Malformed)
+    }
+
+    // Returns up to max buffered bytes, empty at the end of the stream
+    pub(super) async fn read(&mut self, max: usize) -> Result<&[u8], Error> {
+        if !self.fill().await? {
+            return Ok(&[]);
+        }
+        let n = max.min(self.end - self.start);
+        let data = &self.buf[self.start..self.start + n];
+        self.start += n;
+        Ok(data)
+    }
+
+    pub(super) async fn close(mut self) {
+        self.stream.close().await;
+    }
+}
@@ -0,0 +1,25 @@
+mod client;
+mod connection;
+mod server;
+mod url;
+
+use uefi::Status;
+
+use crate::network::{dns, tcp};
+
+pub use client::{Client, Method, Request};
+pub use server::{Handler, Server, ServerRequest, ServerResponse};
+pub use url::Url;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    InvalidUrl,
+    UnsupportedScheme,
+    UnsupportedMethod,
+    Resolve(dns::Error),
+    Tcp(tcp::Error),
+    ConnectionClosed,
+    Malformed,
+    TooManyRedirects,
+    Io(Status),
+}
@@ -0,0 +1,443 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::future::Future;
+use log::info;
+
+use super::{connection::Connection, Error, Method};
+use crate::{
+    asyn,
+    network::{ip, tcp},
+};
+
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: usize = 65536;
+// Connections served at once, each may wait a minute for its next
+// request. More are dropped as they come, the peer sees a reset.
+const MAX_CONNECTIONS: usize = 16;
+
+pub struct ServerRequest {
+    pub method: Method,
+    // Path without the query
+    pub path: String,
+    // Raw query string, without the question mark
+    pub query: String,
+    // Names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub remote_address: ip::Address,
+}
+
+impl ServerRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Value of a query parameter, percent decoded
+    pub fn query(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|p| p.split_once('=').or(Some((p, ""))))
+            .find(|(n, _)| decode_component(n).as_deref() == Some(name))
+            .and_then(|(_, v)| decode_component(v))
+    }
+}
+
+pub struct ServerResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl ServerResponse {
+    pub fn json(status: u16, body: String) -> ServerResponse {
+        ServerResponse {
+            status,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> ServerResponse {
+        Self::json(status, format!("{{\"error\":\"{}\"}}", message))
+    }
+}
+
+pub trait Handler {
+    fn handle(&self, request: ServerRequest) -> impl Future<Output = ServerResponse>;
+}
+
+// Serves a handler on a tcp listener, each connection in its own task
+pub struct Server<H> {
+    listener: tcp::Listener,
+    handler: Arc<H>,
+    // Held by each connection task, released when it ends
+    live: Arc<()>,
+}
+
+impl<H: Handler + 'static> Server<H> {
+    pub fn new(listener: tcp::Listener, handler: Arc<H>) -> Server<H> {
+        Server {
+            listener,
+            handler,
+            live: Arc::new(()),
+        }
+    }
+
+    pub fn start(self, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.task_accept(e.clone())));
+    }
+
+    async fn task_accept(self, e: Arc<dyn asyn::Executor>) {
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok(s) => s,
+                Err(err) => {
+                    info!("http accept failed: {:?}", err);
+                    continue;
+                }
+            };
+            let address = stream.remote_address();
+            let port = stream.remote_port();
+            if Arc::strong_count(&self.live) > MAX_CONNECTIONS {
+                info!(
+                    "http: {} connections open, dropping one from {}",
+                    MAX_CONNECTIONS, address
+                );
+                continue;
+            }
+            let connection = Connection::new(stream, address, port);
+            e.spawn(asyn::Task::new(Self::serve(
+                self.handler.clone(),
+                connection,
+                self.live.clone(),
+            )));
+        }
+    }
+
+    async fn serve(handler: Arc<H>, mut c: Connection, _live: Arc<()>) {
+        loop {
+            let (request, keep_alive) = match Self::read_request(&mut c).await {
+                Ok(r) => r,
+                Err(Error::Malformed) => {
+                    let response = ServerResponse::error(400, "bad request");
+                    let _ = Self::write_response(&mut c, &response, false, false).await;
+                    break;
+                }
+                // unsupported methods are answered but end the connection
+                Err(Error::UnsupportedMethod) => {
+                    let response = ServerResponse::error(501, "not implemented");
+                    let _ = Self::write_response(&mut c, &response, false, false).await;
+                    break;
+                }
+                Err(_) => break,
+            };
+            let head_only = request.method == Method::Head;
+            let response = handler.handle(request).await;
+            if Self::write_response(&mut c, &response, keep_alive, head_only)
+                .await
+                .is_err()
+                || !keep_alive
+            {
+                break;
+            }
+        }
+        c.close().await;
+    }
+
+    // Reads a request and whether the connection stays open after it
+    async fn read_request(c: &mut Connection) -> Result<(ServerRequest, bool), Error> {
+        let mut line = c.read_line().await?;
+        // empty lines before a request are allowed (RFC 9112, section 2.2)
+        while line.is_empty() {
+            line = c.read_line().await?;
+        }
+        let mut parts = line.split(' ');
+        let method = parts.next().ok_or(Error::Malformed)?;
+        let target = parts.next().ok_or(Error::Malformed)?;
+        let version = parts.next().ok_or(Error::Malformed)?;
+        if parts.next().is_some() || !version.starts_with("HTTP/1.") {
+            return Err(Error::Malformed);
+        }
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        loop {
+            let line = c.read_line().await?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADERS {
+                return Err(Error::Malformed);
+            }
+            let (name, value) = line.split_once(':').ok_or(Error::Malformed)?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().into()));
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        // chunked request bodies are not supported
+        if header("transfer-encoding").is_some() {
+            return Err(Error::Malformed);
+        }
+        let length: usize = match header("content-length") {
+            Some(l) => l.parse().map_err(|_| Error::Malformed)?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(Error::Malformed);
+        }
+        let connection = header("connection").unwrap_or("");
+        let keep_alive = if version == "HTTP/1.0" {
+            connection.eq_ignore_ascii_case("keep-alive")
+        } else {
+            !connection.eq_ignore_ascii_case("close")
+        };
+
+        // the handlers take their parameters from the query, a body is
+        // read past so the next request on the connection is found
+        let mut skipped = 0;
+        while skipped < length {
+            let data = c.read(length - skipped).await?;
+            if data.is_empty() {
+                return Err(Error::ConnectionClosed);
+            }
+            skipped += data.len();
+        }
+
+        let method = Method::parse(method).ok_or(Error::UnsupportedMethod)?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let request = ServerRequest {
+            method,
+            path: percent_decode(path).ok_or(Error::Malformed)?,
+            query: query.into(),
+            headers,
+            remote_address: c.address,
+        };
+        Ok((request, keep_alive))
+    }
+
+    async fn write_response(
+        c: &mut Connection,
+        response: &ServerResponse,
+        keep_alive: bool,
+        head_only: bool,
+    ) -> Result<(), Error> {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: {}\r\n\r\n",
+            response.status,
+            reason(response.status),
+            response.content_type,
+            response.body.len(),
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        c.write(head.as_bytes()).await?;
+        if !head_only && !response.body.is_empty() {
+            c.write(&response.body).await?;
+        }
+        Ok(())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// Query components also encode spaces as plus signs
+fn decode_component(s: &str) -> Option<String> {
+    percent_decode(&s.replace('+', " "))
+}
+
+// Decodes %XX escapes, None for invalid escapes or utf-8
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = core::str::from_utf8(&hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        ethernet::MacAddress,
+        http::{Client, Request},
+        sim::{Host, Link, Simulator},
+        Sink,
+    };
+
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 2]);
+
+    // Answers with what it understood of the request
+    struct Echo;
+
+    impl Handler for Echo {
+        async fn handle(&self, request: ServerRequest) -> ServerResponse {
+            if request.path == "/missing" {
+                return ServerResponse::error(404, "not found");
+            }
+            ServerResponse::json(
+                200,
+                format!(
+                    "{} {} q={:?} x={:?}",
+                    request.method.as_str(),
+                    request.path,
+                    request.query("q"),
+                    request.header("X-Test"),
+                ),
+            )
+        }
+    }
+
+    struct Body(Vec<u8>);
+
+    impl Sink for Body {
+        fn write(&mut self, data: &[u8]) -> Result<(), uefi::Status> {
+            self.0.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn serve() -> (Simulator, Host) {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let mac = |n| MacAddress([2, 0, 0, 0, 0, n]);
+        let client = sim.host(switch, mac(1), ip::Address([10, 0, 0, 1]), Link::default());
+        let server = sim.host(switch, mac(2), SERVER, Link::default());
+        let tcp = server.tcp.clone();
+        let listener = sim.block_on(async move { tcp.listen(80).await.unwrap() });
+        Server::new(listener, Arc::new(Echo)).start(sim.executor());
+        (sim, client)
+    }
+
+    #[test]
+    fn parses_and_routes_requests() {
+        let (sim, host) = serve();
+        let client = Client::new(host.tcp.clone(), None);
+        let (status, body) = sim.block_on(async move {
+            let mut results = Vec::new();
+            let requests = [
+                Request {
+                    headers: Vec::from([("X-Test", "yes")]),
+                    ..Request::get("http://10.0.0.2/a%20b?q=1+2&r")
+                },
+                // the body is skipped, the next request still parses
+                Request {
+                    method: Method::Post,
+                    body: b"hello",
+                    ..Request::get("http://10.0.0.2/form")
+                },
+                Request::get("http://10.0.0.2/missing"),
+            ];
+            for request in requests.iter() {
+                let mut body = Body(Vec::new());
+                let response = client
+                    .send(request, &mut body, &mut |_, _| {})
+                    .await
+                    .unwrap();
+                results.push((response.status, String::from_utf8(body.0).unwrap()));
+            }
+            results.into_iter().unzip::<_, _, Vec<_>, Vec<_>>()
+        });
+        assert_eq!(status, [200, 200, 404]);
+        assert_eq!(body[0], "GET /a b q=Some(\"1 2\") x=Some(\"yes\")");
+        assert_eq!(body[1], "POST /form q=None x=None");
+        assert_eq!(body[2], "{\"error\":\"not found\"}");
+    }
+
+    #[test]
+    fn drops_connections_past_the_limit() {
+        let (sim, host) = serve();
+        let tcp = host.tcp.clone();
+        let (refused, served) = sim.block_on(async move {
+            let mut idle = Vec::new();
+            for _ in 0..MAX_CONNECTIONS {
+                idle.push(tcp.connect(SERVER, 80).await.unwrap());
+            }
+            let request = b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+            let mut buf = [0; 512];
+            let mut extra = tcp.connect(SERVER, 80).await.unwrap();
+            let _ = extra.write(request).await;
+            let refused = !matches!(extra.read(&mut buf).await, Ok(n) if n > 0);
+            // a connection ending makes room for another
+            idle.pop().unwrap().close().await;
+            let mut next = tcp.connect(SERVER, 80).await.unwrap();
+            next.write(request).await.unwrap();
+            let n = next.read(&mut buf).await.unwrap();
+            let served = buf[..n].starts_with(b"HTTP/1.1 200");
+            (refused, served)
+        });
+        assert!(refused);
+        assert!(served);
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (sim, host) = serve();
+        let tcp = host.tcp.clone();
+        let heads = sim.block_on(async move {
+            let mut heads = Vec::new();
+            for request in [
+                "BREW /pot HTTP/1.1\r\n\r\n",
+                "GET /\r\n\r\n",
+                "GET / HTTP/1.1\r\nno colon\r\n\r\n",
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                "GET /%zz HTTP/1.1\r\n\r\n",
+            ] {
+                let mut stream = tcp.connect(SERVER, 80).await.unwrap();
+                stream.write(request.as_bytes()).await.unwrap();
+                let mut response = Vec::new();
+                let mut buf = [0; 512];
+                // the server closes after answering
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    response.extend_from_slice(&buf[..n]);
+                }
+                stream.close().await;
+                let response = String::from_utf8(response).unwrap();
+                heads.push(String::from(response.lines().next().unwrap()));
+            }
+            heads
+        });
+        assert_eq!(
+            heads,
+            [
+                "HTTP/1.1 501 Not Implemented",
+                "HTTP/1.1 400 Bad Request",
+                "HTTP/1.1 400 Bad Request",
+                "HTTP/1.1 400 Bad Request",
+                "HTTP/1.1 400 Bad Request",
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+extern crate alloc;
+
+use alloc::{format, string::String};
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    // Path including the query, always starting with a slash
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, Error> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some(_) => return Err(Error::UnsupportedScheme),
+            None => return Err(Error::InvalidUrl),
+        };
+
+        let split = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(split);
+        let path = path.split('#').next().unwrap_or("");
+        // credentials in the url are not supported, use a header instead
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| Error::InvalidUrl)?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl);
+        }
+
+        Ok(Url {
+            host: host.to_ascii_lowercase(),
+            port,
+            path: match path.chars().next() {
+                Some('/') => path.into(),
+                _ => format!("/{}", path),
+            },
+        })
+    }
+
+    // Resolves a Location header against this url
+    pub fn join(&self, location: &str) -> Result<Url, Error> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        if let Some(rest) = location.strip_prefix("//") {
+            return Url::parse(&format!("http://{}", rest));
+        }
+        let path = if location.starts_with('/') {
+            location.into()
+        } else {
+            let path = self.path.split(['?', '#']).next().unwrap_or("/");
+            let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{}{}", directory, location)
+        };
+        Ok(Url {
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        })
+    }
+
+    // Value of the Host header
+    pub fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            p => format!("{}:{}", self.host, p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Url;
+    use crate::network::http::Error;
+
+    #[test]
+    fn parse_and_join() {
+        let url = Url::parse("http://Artifacts.lab:8080/images/os.efi?v=2#top").unwrap();
+        assert_eq!(url.host, "artifacts.lab");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/images/os.efi?v=2");
+        assert_eq!(url.authority(), "artifacts.lab:8080");
+
+        assert_eq!(Url::parse("http://10.0.0.1").unwrap().path, "/");
+        assert_eq!(Url::parse("http://h?q").unwrap().path, "/?q");
+        assert_eq!(Url::parse("https://h/"), Err(Error::UnsupportedScheme));
+        assert_eq!(Url::parse("h/x"), Err(Error::InvalidUrl));
+
+        assert_eq!(url.join("initrd.img").unwrap().path, "/images/initrd.img");
+        assert_eq!(url.join("/other").unwrap().path, "/other");
+        assert_eq!(url.join("//cdn/x").unwrap().host, "cdn");
+        assert_eq!(url.join("http://h2/y").unwrap().port, 80);
+    }
+}
@@ -1,7 +1,9 @@
 mod packet;
 mod service;
 mod socket;
+mod traceroute;
 
 pub use packet::{Packet, Type};
 pub use service::Service;
 pub use socket::Socket;
+pub use traceroute::{Mode, Options, Traceroute};
@@ -71,6 +71,32 @@ impl Packet {
         self.ip.set_size(8 + data_len);
         self.ip.data_mut()[8..].clone_from_slice(data);
     }
+
+    // Error messages (TIME_EXCEEDED, DESTINATION_UNREACHABLE) quote the
+    // original ip header followed by at least 8 bytes of its payload
+    fn original_header_len(&self) -> Option<usize> {
+        let data = self.data();
+        if data.len() < 20 {
+            return None;
+        }
+        let header_len = ((data[0] & 0xf) * 4) as usize;
+        if header_len < 20 || data.len() < header_len + 8 {
+            return None;
+        }
+        Some(header_len)
+    }
+    pub fn original_protocol(&self) -> Option<ip::Protocol> {
+        self.original_header_len()?;
+        Some(ip::Protocol(self.data()[9]))
+    }
+    pub fn original_destination_address(&self) -> Option<ip::Address> {
+        self.original_header_len()?;
+        Some(ip::Address(self.data()[16..20].try_into().unwrap()))
+    }
+    pub fn original_data(&self) -> Option<&[u8]> {
+        let header_len = self.original_header_len()?;
+        Some(&self.data()[header_len..header_len + 8])
+    }
 }
 
 newtype_enum! {
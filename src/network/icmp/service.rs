@@ -1,55 +1,153 @@
 extern crate alloc;
 
+use core::sync::atomic::{AtomicU16, Ordering};
 use crossbeam_queue::ArrayQueue;
 use hashbrown::HashMap;
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use log::info;
 
-use crate::{asyn, network::ip};
+use crate::{
+    asyn, metrics,
+    network::{ip, queues},
+};
 
-use super::{Packet, Socket, Type};
+use super::{
+    traceroute::{self, Traceroute},
+    Packet, Socket, Type,
+};
+
+const DROPPED: &str = "icmp_dropped_total";
+const DROPPED_HELP: &str = "Received ICMP messages dropped, by reason.";
+
+// Receive queues by peer address and echo identifier
+type Sockets = HashMap<(ip::Address, u16), Arc<ArrayQueue<Packet>>>;
 
 pub struct Service {
-    next_request_identifier: u16,
+    next_request_identifier: AtomicU16,
+    ip_service: Arc<ip::Service>,
     ip_socket: Arc<ip::Socket>,
-    sockets: HashMap<(ip::Address, u16), Arc<ArrayQueue<Packet>>>,
+    sockets: asyn::Mutex<Sockets>,
+    bad_checksum: metrics::Counter,
+    malformed: metrics::Counter,
+    unmatched: metrics::Counter,
+    queue_full: metrics::Counter,
 }
 
 impl Service {
     pub async fn new(ip: Arc<ip::Service>) -> Service {
+        let metrics = ip.metrics();
         Service {
-            ip_socket: Arc::new(ip.open(ip::Protocol::ICMP).await),
-            sockets: HashMap::new(),
-            next_request_identifier: 0,
+            ip_socket: Arc::new(ip.clone().open(ip::Protocol::ICMP).await),
+            ip_service: ip,
+            sockets: asyn::Mutex::new(HashMap::new()),
+            next_request_identifier: AtomicU16::new(0),
+            bad_checksum: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "checksum")]),
+            malformed: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "malformed")]),
+            unmatched: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "unmatched")]),
+            queue_full: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "queue_full")]),
         }
     }
-    pub fn start(self, e: Arc<dyn asyn::Executor>) {
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
         e.spawn(asyn::Task::new(self.task_receive()));
     }
 
-    pub fn open(&mut self, ip_address: ip::Address) -> Socket {
+    pub async fn open(&self, ip_address: ip::Address) -> Socket {
         let s = Socket {
-            identifier: self.next_request_identifier,
+            identifier: self.next_request_identifier.fetch_add(1, Ordering::Relaxed),
             sequence: 0,
-            ip_address: ip_address,
+            ip_address,
             recv_queue: Arc::new(ArrayQueue::new(16)),
             ip_socket: self.ip_socket.clone(),
         };
-        self.next_request_identifier += 1;
-        self.sockets
-            .insert((ip_address, s.identifier), s.recv_queue.clone());
+        self.register((ip_address, s.identifier), s.recv_queue.clone())
+            .await;
         s
     }
 
-    async fn task_receive(self) {
+    pub async fn traceroute(
+        &self,
+        destination: ip::Address,
+        options: traceroute::Options,
+    ) -> Traceroute {
+        let t = Traceroute {
+            identifier: self.next_request_identifier.fetch_add(1, Ordering::Relaxed),
+            destination,
+            options,
+            recv_queue: Arc::new(ArrayQueue::new(16)),
+            ip_service: self.ip_service.clone(),
+        };
+        self.register((destination, t.identifier), t.recv_queue.clone())
+            .await;
+        t
+    }
+
+    // Sockets and traceroutes deregister when they are dropped
+    async fn register(&self, key: (ip::Address, u16), queue: Arc<ArrayQueue<Packet>>) {
+        let mut sockets = self.sockets.lock().await;
+        sockets.retain(|_, q| queues::is_open(q));
+        sockets.insert(key, queue);
+    }
+
+    // Remote address and identifier of every open socket
+    pub async fn sockets(&self) -> Vec<(ip::Address, u16)> {
+        let sockets = self.sockets.lock().await;
+        sockets
+            .iter()
+            .filter(|(_, q)| queues::is_open(q))
+            .map(|(k, _)| *k)
+            .collect()
+    }
+
+    fn deliver(&self, queue: Option<&Arc<ArrayQueue<Packet>>>, received: Packet) {
+        match queue.filter(|q| queues::is_open(q)) {
+            Some(q) => {
+                if q.push(received).is_err() {
+                    self.queue_full.inc();
+                }
+            }
+            None => {
+                self.unmatched.inc();
+                info!(
+                    "icmp {:?} from {:?} for unknown socket",
+                    received.typ(),
+                    received.ip.source_address(),
+                );
+            }
+        }
+    }
+
+    // Errors quote the datagram that caused them, which identifies the socket
+    fn error_key(received: &Packet) -> Option<(ip::Address, u16)> {
+        let destination = received.original_destination_address()?;
+        let data = received.original_data()?;
+        match received.original_protocol()? {
+            ip::Protocol::ICMP => Some((
+                destination,
+                u16::from_be_bytes(data[4..6].try_into().unwrap()),
+            )),
+            ip::Protocol::UDP => Some((
+                destination,
+                u16::from_be_bytes(data[0..2].try_into().unwrap())
+                    .wrapping_sub(traceroute::UDP_SOURCE_PORT_BASE),
+            )),
+            _ => None,
+        }
+    }
+
+    async fn task_receive(self: Arc<Self>) {
         loop {
             let received = Packet {
                 ip: self.ip_socket.receive().await,
             };
 
-            if ip::checksum(&received.ip.data()) != 0 {
-                log::info!("Invalid icmp checksum");
+            // shorter than an echo header
+            if received.ip.data().len() < 8 {
+                self.malformed.inc();
+                continue;
+            }
+            if ip::checksum(received.ip.data()) != 0 {
+                self.bad_checksum.inc();
                 continue;
             }
 
@@ -68,21 +166,62 @@ impl Service {
                         .ip
                         .set_destination_address(&received.ip.source_address());
 
-                    self.ip_socket.send(response.ip).await;
+                    // the requester retries if this gets lost
+                    let _ = self.ip_socket.send(response.ip).await;
+                }
+                Type::ECHO_REPLY => {
+                    let sockets = self.sockets.lock().await;
+                    let key = (received.ip.source_address(), received.identifier());
+                    self.deliver(sockets.get(&key), received);
+                }
+                Type::TIME_EXCEEDED | Type::DESTINATION_UNREACHABLE => {
+                    let sockets = self.sockets.lock().await;
+                    let queue = Self::error_key(&received).and_then(|k| sockets.get(&k));
+                    self.deliver(queue, received);
                 }
-                Type::ECHO_REPLY => match self
-                    .sockets
-                    .get(&(received.ip.source_address(), received.identifier()))
-                {
-                    Some(q) => q.push(received).expect("reply queue full"),
-                    None => info!(
-                        "icmp reply from unrequested ip and identifier {:?}, {}",
-                        received.ip.source_address(),
-                        received.identifier(),
-                    ),
-                },
                 _ => info!("unknown icmp type received {:?}", received.typ()),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESTINATION: ip::Address = ip::Address([10, 0, 0, 9]);
+
+    // A time exceeded error quoting a datagram of the protocol with the
+    // given first 8 bytes of payload
+    fn error(protocol: ip::Protocol, quoted: [u8; 8]) -> Packet {
+        let mut original = Vec::from([0x45, 0, 0, 28, 0, 0, 0, 0, 1, protocol.0, 0, 0]);
+        original.extend_from_slice(&[10, 0, 0, 1]);
+        original.extend_from_slice(&DESTINATION.0);
+        original.extend_from_slice(&quoted);
+        let mut p = Packet::new();
+        p.set_data(&original);
+        p.set_type(Type::TIME_EXCEEDED);
+        p
+    }
+
+    #[test]
+    fn error_key() {
+        let echo = error(ip::Protocol::ICMP, [8, 0, 0, 0, 0x12, 0x34, 0, 1]);
+        assert_eq!(Service::error_key(&echo), Some((DESTINATION, 0x1234)));
+
+        let port = (traceroute::UDP_SOURCE_PORT_BASE + 5).to_be_bytes();
+        let probe = error(
+            ip::Protocol::UDP,
+            [port[0], port[1], 0x82, 0x9a, 0, 40, 0, 0],
+        );
+        assert_eq!(Service::error_key(&probe), Some((DESTINATION, 5)));
+
+        let other = error(ip::Protocol::TCP, [0; 8]);
+        assert_eq!(Service::error_key(&other), None);
+
+        // the quote has to hold the header and 8 bytes
+        let mut short = Packet::new();
+        short.set_data(&other.data()[..24]);
+        assert_eq!(Service::error_key(&short), None);
+    }
+}
@@ -5,7 +5,10 @@ use alloc::sync::Arc;
 use crossbeam_queue::ArrayQueue;
 
 use super::{Packet, Type};
-use crate::{asyn, network::ip};
+use crate::{
+    asyn,
+    network::{ip, Error},
+};
 
 pub struct Socket {
     pub(super) identifier: u16,
@@ -20,11 +23,14 @@ impl Socket {
         self.ip_address
     }
 
-    pub async fn receive(&self, t: f64) -> Option<Packet> {
-        asyn::queue_pop_timeout(self.recv_queue.clone(), t).await
+    // The next reply or error for our requests, waiting at most t seconds
+    pub async fn receive(&self, t: f64) -> Result<Packet, Error> {
+        asyn::queue_pop_timeout(self.recv_queue.clone(), t)
+            .await
+            .ok_or(Error::Timeout)
     }
 
-    pub async fn send(&self, data: &[u8]) {
+    pub async fn send(&self, data: &[u8]) -> Result<(), Error> {
         let mut request = Packet::new();
 
         request.set_data(data);
@@ -37,6 +43,6 @@ impl Socket {
 
         request.ip.set_destination_address(&self.ip_address);
 
-        self.ip_socket.send(request.ip).await;
+        self.ip_socket.send(request.ip).await
     }
 }
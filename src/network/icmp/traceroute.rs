@@ -0,0 +1,274 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use crossbeam_queue::ArrayQueue;
+
+use super::{Packet, Type};
+use crate::{
+    asyn,
+    network::{ip, Error},
+};
+
+// Udp probes are sent from this port plus the traceroute identifier, so
+// that quoted errors can be routed back to the right traceroute
+pub(super) const UDP_SOURCE_PORT_BASE: u16 = 0x8000;
+
+// Code of DESTINATION_UNREACHABLE sent by hosts for closed udp ports
+const PORT_UNREACHABLE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Icmp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub mode: Mode,
+    pub max_hops: u8,
+    pub probes: u8,
+    pub timeout: f64,
+    // First udp destination port, incremented for every probe
+    pub port: u16,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            mode: Mode::Icmp,
+            max_hops: 30,
+            probes: 3,
+            timeout: 1.0,
+            port: 33434,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    pub address: ip::Address,
+    pub rtt: f64,
+    pub typ: Type,
+    pub code: u8,
+}
+
+#[derive(Debug)]
+pub struct Hop {
+    pub ttl: u8,
+    // None for probes that timed out
+    pub probes: Vec<Option<Probe>>,
+}
+
+impl Hop {
+    // True when the traced host answered at this hop
+    pub fn reached(&self) -> bool {
+        self.probes.iter().flatten().any(|p| p.is_final())
+    }
+
+    // True when no further hop can answer, either because the host was
+    // reached or because a router reported it as unreachable
+    fn last(&self) -> bool {
+        self.probes
+            .iter()
+            .flatten()
+            .any(|p| p.typ != Type::TIME_EXCEEDED)
+    }
+}
+
+pub struct Traceroute {
+    pub(super) identifier: u16,
+    pub(super) destination: ip::Address,
+    pub(super) options: Options,
+    pub(super) recv_queue: Arc<ArrayQueue<Packet>>,
+    pub(super) ip_service: Arc<ip::Service>,
+}
+
+impl Traceroute {
+    pub fn destination(&self) -> ip::Address {
+        self.destination
+    }
+
+    // Fails when probes cannot be sent at all, like without a route
+    pub async fn run(&self) -> Result<Vec<Hop>, Error> {
+        let mut hops = Vec::new();
+        let mut sequence: u16 = 0;
+
+        for ttl in 1..=self.options.max_hops {
+            let mut hop = Hop {
+                ttl,
+                probes: Vec::new(),
+            };
+            for _ in 0..self.options.probes {
+                hop.probes.push(self.probe(ttl, sequence).await?);
+                sequence = sequence.wrapping_add(1);
+            }
+
+            let done = hop.last();
+            hops.push(hop);
+            if done {
+                break;
+            }
+        }
+
+        Ok(hops)
+    }
+
+    // None when nothing answered in time
+    async fn probe(&self, ttl: u8, sequence: u16) -> Result<Option<Probe>, Error> {
+        let mut p = match self.options.mode {
+            Mode::Icmp => self.echo_probe(sequence),
+            Mode::Udp => self.udp_probe(sequence),
+        };
+        p.set_ttl(ttl);
+        p.set_destination_address(&self.destination);
+
+        let start = asyn::timestamp();
+        match self.ip_service.send(p).await {
+            // the arp request is out, the next probes go through
+            Err(Error::Unresolved(_)) | Err(Error::QueueFull) => {}
+            r => r?,
+        }
+
+        loop {
+            let remaining = self.options.timeout - asyn::elapsed(start);
+            if remaining <= 0.0 {
+                return Ok(None);
+            }
+            let Some(received) = asyn::queue_pop_timeout(self.recv_queue.clone(), remaining).await
+            else {
+                return Ok(None);
+            };
+            if self.matches(&received, sequence) {
+                return Ok(Some(Probe {
+                    address: received.ip.source_address(),
+                    rtt: asyn::elapsed(start),
+                    typ: received.typ(),
+                    code: received.code(),
+                }));
+            }
+        }
+    }
+
+    fn echo_probe(&self, sequence: u16) -> ip::Packet {
+        let mut request = Packet::new();
+        request.set_data(&[0; 32]);
+        request.set_type(Type::ECHO_REQUEST);
+        request.set_code(0);
+        request.set_identifier(self.identifier);
+        request.set_sequence_number(sequence);
+        request.set_checksum(ip::checksum(request.ip.data()));
+        request.ip.set_protocol(ip::Protocol::ICMP);
+        request.ip
+    }
+
+    fn udp_probe(&self, sequence: u16) -> ip::Packet {
+        let mut p = ip::Packet::new();
+        p.set_protocol(ip::Protocol::UDP);
+        p.set_size(8 + 32);
+
+        let source_port = UDP_SOURCE_PORT_BASE.wrapping_add(self.identifier);
+        let destination_port = self.options.port.wrapping_add(sequence);
+        let data = p.data_mut();
+        data.fill(0);
+        data[0..2].clone_from_slice(&source_port.to_be_bytes());
+        data[2..4].clone_from_slice(&destination_port.to_be_bytes());
+        data[4..6].clone_from_slice(&(8u16 + 32).to_be_bytes());
+        // udp checksum is optional over ipv4 and left as 0
+        p
+    }
+
+    fn matches(&self, received: &Packet, sequence: u16) -> bool {
+        match (self.options.mode, received.typ()) {
+            (Mode::Icmp, Type::ECHO_REPLY) => received.sequence_number() == sequence,
+            (Mode::Icmp, Type::TIME_EXCEEDED | Type::DESTINATION_UNREACHABLE) => {
+                match received.original_data() {
+                    Some(d) => u16::from_be_bytes(d[6..8].try_into().unwrap()) == sequence,
+                    None => false,
+                }
+            }
+            (Mode::Udp, Type::TIME_EXCEEDED | Type::DESTINATION_UNREACHABLE) => {
+                let destination_port = self.options.port.wrapping_add(sequence);
+                match received.original_data() {
+                    Some(d) => u16::from_be_bytes(d[2..4].try_into().unwrap()) == destination_port,
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Probe {
+    // True when the probe was answered by the traced host itself
+    pub fn is_final(&self) -> bool {
+        self.typ == Type::ECHO_REPLY
+            || (self.typ == Type::DESTINATION_UNREACHABLE && self.code == PORT_UNREACHABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        ethernet::MacAddress,
+        sim::{Link, Simulator},
+    };
+
+    fn answer(typ: Type, code: u8) -> Option<Probe> {
+        Some(Probe {
+            address: ip::Address([10, 0, 0, 254]),
+            rtt: 0.001,
+            typ,
+            code,
+        })
+    }
+
+    #[test]
+    fn hop_ends_the_trace() {
+        let hop = |probes| Hop { ttl: 1, probes };
+        let router = hop(Vec::from([answer(Type::TIME_EXCEEDED, 0), None]));
+        assert!(!router.reached() && !router.last());
+        let silent = hop(Vec::from([None, None]));
+        assert!(!silent.reached() && !silent.last());
+        let host = hop(Vec::from([None, answer(Type::ECHO_REPLY, 0)]));
+        assert!(host.reached() && host.last());
+        let closed_port = hop(Vec::from([answer(
+            Type::DESTINATION_UNREACHABLE,
+            PORT_UNREACHABLE,
+        )]));
+        assert!(closed_port.reached() && closed_port.last());
+        // network unreachable from a router
+        let unreachable = hop(Vec::from([answer(Type::DESTINATION_UNREACHABLE, 0)]));
+        assert!(!unreachable.reached() && unreachable.last());
+    }
+
+    #[test]
+    fn trace_a_neighbor() {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let mac = |n| MacAddress([2, 0, 0, 0, 0, n]);
+        let target = ip::Address([10, 0, 0, 2]);
+        let a = sim.host(switch, mac(1), ip::Address([10, 0, 0, 1]), Link::default());
+        let _b = sim.host(switch, mac(2), target, Link::default());
+
+        let icmp = a.icmp.clone();
+        let hops = sim
+            .block_on(async move {
+                let options = Options {
+                    timeout: 0.5,
+                    ..Options::default()
+                };
+                icmp.traceroute(target, options).await.run().await
+            })
+            .unwrap();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].ttl, 1);
+        assert_eq!(hops[0].probes.len(), 3);
+        assert!(hops[0].reached());
+        // the first probe waits for arp, the others are answered
+        assert!(hops[0].probes[0].is_none());
+        for p in hops[0].probes[1..].iter().flatten() {
+            assert_eq!(p.address, target);
+        }
+    }
+}
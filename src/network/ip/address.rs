@@ -1,9 +1,16 @@
-use core::{fmt, ops::BitAnd};
+use core::{fmt, ops::BitAnd, str::FromStr};
 
 #[repr(C)]
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Address(pub [u8; 4]);
 
+impl Address {
+    // 224.0.0.0/4
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 0xe0
+    }
+}
+
 impl fmt::Debug for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
@@ -13,6 +20,27 @@ impl fmt::Debug for Address {
     }
 }
 
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{}.{}.{}.{}",
+            self.0[0], self.0[1], self.0[2], self.0[3],
+        ))
+    }
+}
+
+impl From<Address> for u32 {
+    fn from(a: Address) -> u32 {
+        u32::from_be_bytes(a.0)
+    }
+}
+
+impl From<u32> for Address {
+    fn from(a: u32) -> Address {
+        Address(a.to_be_bytes())
+    }
+}
+
 impl BitAnd for Address {
     type Output = Address;
 
@@ -24,3 +52,24 @@ impl BitAnd for Address {
         result
     }
 }
+
+impl FromStr for Address {
+    type Err = ();
+
+    // Dotted decimal notation, e.g. 192.168.0.1
+    fn from_str(s: &str) -> Result<Address, ()> {
+        let mut result = Address([0; 4]);
+        let mut parts = s.split('.');
+        for b in result.0.iter_mut() {
+            let part = parts.next().ok_or(())?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(());
+            }
+            *b = part.parse().map_err(|_| ())?;
+        }
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(result),
+        }
+    }
+}
@@ -1,11 +1,34 @@
+use super::{Address, Protocol};
+
 pub fn checksum(data: &[u8]) -> u16 {
-    let mut sum: u32 = 0;
+    finish(sum(data, 0))
+}
+
+// Checksum of a transport segment preceded by the ipv4 pseudo header
+pub fn pseudo_header_checksum(
+    source: &Address,
+    destination: &Address,
+    protocol: Protocol,
+    segment: &[u8],
+) -> u16 {
+    let mut s = sum(&source.0, 0);
+    s = sum(&destination.0, s);
+    s += protocol.0 as u32;
+    s += segment.len() as u32;
+    finish(sum(segment, s))
+}
+
+fn sum(data: &[u8], mut sum: u32) -> u32 {
     for i in 0..data.len() / 2 {
         sum += u16::from_be_bytes(data[2 * i..2 * i + 2].try_into().unwrap()) as u32;
     }
     if data.len() % 2 == 1 {
         sum += (data[data.len() - 1] as u32) << 8;
     }
+    sum
+}
+
+fn finish(mut sum: u32) -> u16 {
     while sum >> 16 != 0 {
         sum = (sum >> 16) + (sum & 0xffff);
     }
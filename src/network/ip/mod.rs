@@ -5,7 +5,7 @@ mod service;
 mod socket;
 
 pub use address::Address;
-pub use checksum::checksum;
+pub use checksum::{checksum, pseudo_header_checksum};
 pub use packet::{Packet, Protocol};
 pub use service::Service;
 pub use socket::Socket;
@@ -6,6 +6,7 @@ use crate::network::ethernet;
 
 use super::Address;
 
+#[derive(Clone)]
 pub struct Packet {
     pub eth: ethernet::Packet,
 }
@@ -43,16 +44,16 @@ impl Packet {
     }
 
     pub fn version(&self) -> u8 {
-        return (self.eth.data()[0] & 0xf0) >> 4;
+        (self.eth.data()[0] & 0xf0) >> 4
     }
     pub fn set_version(&mut self, v: u8) {
         self.eth.data_mut()[0] = (self.eth.data()[0] & 0xf) | (v << 4);
     }
     pub fn header_len(&self) -> u8 {
-        return (self.eth.data()[0] & 0xf) * 4;
+        (self.eth.data()[0] & 0xf) * 4
     }
     pub fn set_header_len(&mut self, l: u8) {
-        let lsize: usize = l.try_into().unwrap();
+        let lsize = usize::from(l);
         if self.eth.size() < lsize {
             self.eth.set_size(lsize);
         }
@@ -62,7 +63,7 @@ impl Packet {
         u16::from_be_bytes(self.eth.data()[2..4].try_into().unwrap())
     }
     pub fn set_total_len(&mut self, l: u16) {
-        self.eth.set_size(l.try_into().unwrap());
+        self.eth.set_size(usize::from(l));
         self.eth.data_mut()[2..4].clone_from_slice(&l.to_be_bytes());
     }
     pub fn identification(&self) -> u16 {
@@ -72,13 +73,13 @@ impl Packet {
         self.eth.data_mut()[4..6].clone_from_slice(&l.to_be_bytes());
     }
     pub fn ttl(&self) -> u8 {
-        return self.eth.data()[8];
+        self.eth.data()[8]
     }
     pub fn set_ttl(&mut self, ttl: u8) {
         self.eth.data_mut()[8] = ttl
     }
     pub fn protocol(&self) -> Protocol {
-        return Protocol(self.eth.data()[9]);
+        Protocol(self.eth.data()[9])
     }
     pub fn set_protocol(&mut self, p: Protocol) {
         self.eth.data_mut()[9] = p.0
@@ -102,13 +103,8 @@ impl Packet {
         self.eth.data_mut()[16..20].clone_from_slice(&a.0);
     }
 
-    pub fn size(&self) -> u16 {
-        let header_len: u16 = self.header_len().try_into().unwrap();
-        self.total_len() - header_len
-    }
     pub fn set_size(&mut self, size: u16) {
-        let header_len: u16 = self.header_len().try_into().unwrap();
-        self.set_total_len(size + header_len);
+        self.set_total_len(size + u16::from(self.header_len()));
     }
     pub fn header(&self) -> &[u8] {
         let header_len = self.header_len() as usize;
@@ -125,6 +121,19 @@ impl Packet {
         }
         &self.eth.data()[header_len..total_len]
     }
+    // An ipv4 header that fits the frame, and a total length covering
+    // the header but not more than the frame
+    pub fn valid(&self) -> bool {
+        if self.eth.size() < 20 {
+            return false;
+        }
+        let header_len = self.header_len() as usize;
+        let total_len = self.total_len() as usize;
+        self.version() == 4
+            && header_len >= 20
+            && header_len <= total_len
+            && total_len <= self.eth.size()
+    }
     pub fn data_mut(&mut self) -> &mut [u8] {
         let header_len = self.header_len() as usize;
 
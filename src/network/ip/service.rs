@@ -1,86 +1,221 @@
 extern crate alloc;
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
 use crossbeam_queue::ArrayQueue;
 use hashbrown::HashMap;
-use log::info;
 
 use super::{checksum, Address, Packet, Protocol, Socket};
-use crate::{asyn, network::arp, network::ethernet};
+use crate::{
+    asyn, metrics,
+    network::{arp, ethernet, queues, Error},
+};
+
+struct Counters {
+    received: metrics::Counter,
+    sent: metrics::Counter,
+    bad_checksum: metrics::Counter,
+    malformed: metrics::Counter,
+    no_socket: metrics::Counter,
+    queue_full: metrics::Counter,
+    // Destination mac unknown, the arp request is still out
+    unresolved: metrics::Counter,
+    // Off the subnet without a gateway
+    no_route: metrics::Counter,
+}
+
+impl Counters {
+    fn new(registry: &metrics::Registry) -> Counters {
+        let packets = "IPv4 packets received or sent.";
+        let dropped = "IPv4 packets dropped, by reason.";
+        Counters {
+            received: registry.counter("ip_packets_total", packets, &[("direction", "received")]),
+            sent: registry.counter("ip_packets_total", packets, &[("direction", "sent")]),
+            bad_checksum: registry.counter("ip_dropped_total", dropped, &[("reason", "checksum")]),
+            malformed: registry.counter("ip_dropped_total", dropped, &[("reason", "malformed")]),
+            no_socket: registry.counter("ip_dropped_total", dropped, &[("reason", "no_socket")]),
+            queue_full: registry.counter("ip_dropped_total", dropped, &[("reason", "queue_full")]),
+            unresolved: registry.counter("ip_dropped_total", dropped, &[("reason", "unresolved")]),
+            no_route: registry.counter("ip_dropped_total", dropped, &[("reason", "no_route")]),
+        }
+    }
+}
 
 pub struct Service {
     ethernet: Arc<ethernet::Socket>,
     arp_service: Arc<arp::Service>,
 
-    sockets: asyn::Mutex<HashMap<Protocol, Arc<ArrayQueue<Packet>>>>,
+    sockets: asyn::Mutex<HashMap<Protocol, Vec<Arc<ArrayQueue<Packet>>>>>,
+
+    address: AtomicU32,
+    netmask: AtomicU32,
+    gateway: AtomicU32,
 
-    address: Address,
-    netmask: Address,
-    gateway: Address,
+    // Joined multicast groups, their macs are in the receive filter
+    filters: Arc<dyn ethernet::Filters>,
+    groups: asyn::Mutex<Vec<Address>>,
+
+    counters: Counters,
+    metrics: Arc<metrics::Registry>,
 }
 
 impl Service {
     pub fn new(
-        eth: &mut ethernet::Service,
+        eth: &impl ethernet::Network,
         arp: Arc<arp::Service>,
         address: Address,
         netmask: Address,
         gateway: Address,
     ) -> Service {
+        let metrics = eth.metrics();
         Service {
             ethernet: Arc::new(eth.open(ethernet::Type::IPV4)),
             arp_service: arp,
 
             sockets: asyn::Mutex::new(HashMap::new()),
 
-            address: address,
-            netmask: netmask,
-            gateway: gateway,
+            address: AtomicU32::new(address.into()),
+            netmask: AtomicU32::new(netmask.into()),
+            gateway: AtomicU32::new(gateway.into()),
+
+            filters: eth.filters(),
+            groups: asyn::Mutex::new(Vec::new()),
+
+            counters: Counters::new(&metrics),
+            metrics,
         }
     }
 
+    // The registry of the stack, for the services on top
+    pub fn metrics(&self) -> Arc<metrics::Registry> {
+        self.metrics.clone()
+    }
+
     pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
         e.spawn(asyn::Task::new(self.task_receive()));
     }
 
+    pub fn address(&self) -> Address {
+        self.address.load(Ordering::Relaxed).into()
+    }
+    pub fn netmask(&self) -> Address {
+        self.netmask.load(Ordering::Relaxed).into()
+    }
+    pub fn gateway(&self) -> Address {
+        self.gateway.load(Ordering::Relaxed).into()
+    }
+
+    pub fn set_config(&self, address: Address, netmask: Address, gateway: Address) {
+        self.address.store(address.into(), Ordering::Relaxed);
+        self.netmask.store(netmask.into(), Ordering::Relaxed);
+        self.gateway.store(gateway.into(), Ordering::Relaxed);
+        self.arp_service.set_address(address);
+    }
+
+    // Protocols with an open socket
+    pub async fn protocols(&self) -> Vec<Protocol> {
+        let sockets = self.sockets.lock().await;
+        sockets
+            .iter()
+            .filter(|(_, queues)| queues.iter().any(queues::is_open))
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    pub async fn groups(&self) -> Vec<Address> {
+        self.groups.lock().await.clone()
+    }
+
+    // Accepts frames for a multicast group
+    pub async fn join(&self, group: Address) -> uefi::Result {
+        let mut groups = self.groups.lock().await;
+        if !group.is_multicast() {
+            return Err(uefi::Status::INVALID_PARAMETER.into());
+        }
+        if groups.contains(&group) {
+            return Ok(());
+        }
+        groups.push(group);
+        let r = self.apply_groups(&groups);
+        if r.is_err() {
+            groups.pop();
+        }
+        r
+    }
+
+    pub async fn leave(&self, group: Address) -> uefi::Result {
+        let mut groups = self.groups.lock().await;
+        let Some(i) = groups.iter().position(|g| *g == group) else {
+            return Ok(());
+        };
+        groups.remove(i);
+        self.apply_groups(&groups)
+    }
+
+    fn apply_groups(&self, groups: &[Address]) -> uefi::Result {
+        let mut filters = self.filters.receive_filters();
+        // other protocols' groups, like lldp's, stay
+        filters.multicast.retain(|m| m.0[..3] != [0x01, 0x00, 0x5e]);
+        // groups can share a mac
+        for mac in groups.iter().map(|g| multicast_mac(*g)) {
+            if !filters.multicast.contains(&mac) {
+                filters.multicast.push(mac);
+            }
+        }
+        self.filters.set_receive_filters(filters)
+    }
+
     pub async fn open(self: Arc<Self>, p: Protocol) -> Socket {
         let s = Socket {
             protocol: p,
             recv_queue: Arc::new(ArrayQueue::new(16)),
             service: self.clone(),
         };
+        // every socket of a protocol gets its own copy of a packet
         let mut sockets = self.sockets.lock().await;
-        sockets.insert(p, s.recv_queue.clone());
+        let queues = sockets.entry(p).or_default();
+        queues.retain(queues::is_open);
+        queues.push(s.recv_queue.clone());
         s
     }
 
-    pub(super) async fn send(&self, mut p: Packet) {
+    // Fails when the next hop is unknown or not resolved yet; in the
+    // latter case an arp request is out and a later send may work
+    pub async fn send(&self, mut p: Packet) -> Result<(), Error> {
         if p.source_address() == Address([0; 4]) {
-            p.set_source_address(&self.address);
+            p.set_source_address(&self.address());
         }
 
         p.set_header_checksum(0);
         p.set_header_checksum(checksum(p.header()));
 
         if p.eth.mac_destination() == ethernet::MacAddress([0; 6]) {
-            if p.destination_address() & self.netmask == self.address & self.netmask {
-                match self.arp_service.lookup(&p.destination_address()).await {
-                    Some(a) => p.eth.set_mac_destination(a),
-                    None => (),
-                };
+            let netmask = self.netmask();
+            let destination = p.destination_address();
+            let subnet_broadcast = Address::from(u32::from(self.address()) | !u32::from(netmask));
+            let mac = if destination == Address([255; 4]) || destination == subnet_broadcast {
+                ethernet::MAC_BROADCAST
+            } else if destination.is_multicast() {
+                multicast_mac(destination)
             } else {
-                match self.arp_service.lookup(&self.gateway).await {
-                    Some(a) => p.eth.set_mac_destination(a),
-                    None => (),
+                let next_hop = if destination & netmask == self.address() & netmask {
+                    destination
+                } else if self.gateway() != Address([0; 4]) {
+                    self.gateway()
+                } else {
+                    self.counters.no_route.inc();
+                    return Err(Error::NoRoute);
                 };
-            }
+                self.arp_service.lookup(&next_hop).await.inspect_err(|_| {
+                    self.counters.unresolved.inc();
+                })?
+            };
+            p.eth.set_mac_destination(mac);
         }
 
-        if p.eth.mac_destination() != ethernet::MacAddress([0; 6]) {
-            self.ethernet.send(p.eth);
-        } else {
-            info!("dropping packet without destination mac");
-        }
+        self.ethernet.send(p.eth)?;
+        self.counters.sent.inc();
+        Ok(())
     }
 
     async fn task_receive(self: Arc<Self>) {
@@ -88,15 +223,29 @@ impl Service {
             let ip_packet = Packet {
                 eth: self.ethernet.receive().await,
             };
+            self.counters.received.inc();
+            if !ip_packet.valid() {
+                self.counters.malformed.inc();
+                continue;
+            }
             if checksum(ip_packet.header()) != 0 {
-                log::info!("Invalid ip checksum");
+                self.counters.bad_checksum.inc();
                 continue;
             }
 
             let sockets = self.sockets.lock().await;
-            if let Some(s) = sockets.get(&ip_packet.protocol()) {
-                s.push(ip_packet).unwrap();
+            match sockets.get(&ip_packet.protocol()) {
+                Some(queues) => match queues::deliver(queues, ip_packet) {
+                    (0, 0) => self.counters.no_socket.inc(),
+                    (_, full) => self.counters.queue_full.add(full as u64),
+                },
+                None => self.counters.no_socket.inc(),
             }
         }
     }
 }
+
+// RFC 1112, the low 23 bits of the group under 01:00:5e
+fn multicast_mac(group: Address) -> ethernet::MacAddress {
+    ethernet::MacAddress([0x01, 0x00, 0x5e, group.0[1] & 0x7f, group.0[2], group.0[3]])
+}
@@ -3,8 +3,8 @@ extern crate alloc;
 use alloc::sync::Arc;
 use crossbeam_queue::ArrayQueue;
 
-use super::{Packet, Protocol, Service};
-use crate::asyn;
+use super::{Address, Packet, Protocol, Service};
+use crate::{asyn, network::Error};
 
 pub struct Socket {
     pub(super) service: Arc<Service>,
@@ -13,12 +13,16 @@ pub struct Socket {
 }
 
 impl Socket {
+    pub fn address(&self) -> Address {
+        self.service.address()
+    }
+
     pub async fn receive(&self) -> Packet {
         asyn::queue_pop(self.recv_queue.clone()).await
     }
 
-    pub async fn send(&self, mut p: Packet) {
+    pub async fn send(&self, mut p: Packet) -> Result<(), Error> {
         p.set_protocol(self.protocol);
-        self.service.send(p).await;
+        self.service.send(p).await
     }
 }
@@ -0,0 +1,455 @@
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+use hashbrown::HashMap;
+use log::info;
+
+use super::{ethernet, ip, Error};
+use crate::{asyn, metrics};
+
+// Nearest bridge group address, switches do not forward it
+pub const MULTICAST: ethernet::MacAddress =
+    ethernet::MacAddress([0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e]);
+
+// Neighbors kept at most, a segment full of hosts should not grow the table
+const MAX_NEIGHBORS: usize = 64;
+
+// TLV types
+const END: u8 = 0;
+const CHASSIS_ID: u8 = 1;
+const PORT_ID: u8 = 2;
+const TTL: u8 = 3;
+const PORT_DESCRIPTION: u8 = 4;
+const SYSTEM_NAME: u8 = 5;
+const SYSTEM_DESCRIPTION: u8 = 6;
+const MANAGEMENT_ADDRESS: u8 = 8;
+
+// Chassis and port id subtypes carrying a mac or network address
+const CHASSIS_MAC: u8 = 4;
+const CHASSIS_ADDRESS: u8 = 5;
+const PORT_MAC: u8 = 3;
+const PORT_ADDRESS: u8 = 4;
+// Subtypes holding text: components, interface aliases and names, and
+// locally assigned ids
+const CHASSIS_NAMES: [u8; 5] = [1, 2, 3, 6, 7];
+const PORT_NAMES: [u8; 4] = [1, 2, 5, 7];
+// IANA address family of ipv4
+const FAMILY_IPV4: u8 = 1;
+
+pub struct Options {
+    pub system_name: String,
+    // Seconds between advertisements
+    pub interval: f64,
+    // Seconds neighbors keep our advertisement
+    pub ttl: u16,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            system_name: String::from("rust-uefi-app"),
+            interval: 30.0,
+            ttl: 120,
+        }
+    }
+}
+
+// Chassis or port id, by what its subtype says it holds
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Id {
+    Mac(ethernet::MacAddress),
+    Address(ip::Address),
+    Name(String),
+    Other(u8, Vec<u8>),
+}
+
+impl Id {
+    fn parse(subtype: u8, value: &[u8], mac: u8, address: u8, names: &[u8]) -> Id {
+        if subtype == mac {
+            if let Ok(mac) = value.try_into() {
+                return Id::Mac(ethernet::MacAddress(mac));
+            }
+        }
+        if subtype == address {
+            if let Some(address) = ipv4(value) {
+                return Id::Address(address);
+            }
+        }
+        if names.contains(&subtype) {
+            if let Ok(name) = core::str::from_utf8(value) {
+                return Id::Name(name.to_string());
+            }
+        }
+        Id::Other(subtype, value.to_vec())
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Id::Mac(mac) => write!(f, "{}", mac),
+            Id::Address(address) => write!(f, "{}", address),
+            Id::Name(name) => f.write_str(name),
+            Id::Other(subtype, value) => {
+                write!(f, "{}:", subtype)?;
+                for b in value {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// What a neighbor advertised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lldpdu {
+    pub chassis_id: Id,
+    pub port_id: Id,
+    // Seconds the information is valid, 0 when the neighbor shuts down
+    pub ttl: u16,
+    pub port_description: Option<String>,
+    pub system_name: Option<String>,
+    pub system_description: Option<String>,
+    pub management_address: Option<ip::Address>,
+}
+
+impl Lldpdu {
+    // The mandatory chassis id, port id and ttl followed by the optional
+    // TLVs and the end
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let (subtype, value) = encode_id(&self.chassis_id, CHASSIS_MAC, CHASSIS_ADDRESS);
+        tlv(&mut data, CHASSIS_ID, &[&[subtype], &value]);
+        let (subtype, value) = encode_id(&self.port_id, PORT_MAC, PORT_ADDRESS);
+        tlv(&mut data, PORT_ID, &[&[subtype], &value]);
+        tlv(&mut data, TTL, &[&self.ttl.to_be_bytes()]);
+        if let Some(d) = self.port_description.as_ref() {
+            tlv(&mut data, PORT_DESCRIPTION, &[d.as_bytes()]);
+        }
+        if let Some(name) = self.system_name.as_ref() {
+            tlv(&mut data, SYSTEM_NAME, &[name.as_bytes()]);
+        }
+        if let Some(d) = self.system_description.as_ref() {
+            tlv(&mut data, SYSTEM_DESCRIPTION, &[d.as_bytes()]);
+        }
+        if let Some(address) = self.management_address {
+            // length and family, the address, unknown interface numbering
+            // with interface 0 and no oid
+            tlv(
+                &mut data,
+                MANAGEMENT_ADDRESS,
+                &[&[5, FAMILY_IPV4], &address.0, &[1, 0, 0, 0, 0, 0]],
+            );
+        }
+        tlv(&mut data, END, &[]);
+        data
+    }
+
+    // Malformed unless the chassis id, port id and ttl are all there
+    pub fn parse(data: &[u8]) -> Result<Lldpdu, Error> {
+        Self::parse_tlvs(data).ok_or(Error::Malformed)
+    }
+
+    fn parse_tlvs(mut data: &[u8]) -> Option<Lldpdu> {
+        let mut chassis_id = None;
+        let mut port_id = None;
+        let mut ttl = None;
+        let mut port_description = None;
+        let mut system_name = None;
+        let mut system_description = None;
+        let mut management_address = None;
+        while data.len() >= 2 {
+            let header = u16::from_be_bytes([data[0], data[1]]);
+            let (kind, length) = ((header >> 9) as u8, (header & 0x1ff) as usize);
+            let value = data.get(2..2 + length)?;
+            data = &data[2 + length..];
+            match kind {
+                END => break,
+                CHASSIS_ID => {
+                    let (&subtype, id) = value.split_first()?;
+                    chassis_id = Some(Id::parse(
+                        subtype,
+                        id,
+                        CHASSIS_MAC,
+                        CHASSIS_ADDRESS,
+                        &CHASSIS_NAMES,
+                    ));
+                }
+                PORT_ID => {
+                    let (&subtype, id) = value.split_first()?;
+                    port_id = Some(Id::parse(subtype, id, PORT_MAC, PORT_ADDRESS, &PORT_NAMES));
+                }
+                TTL => ttl = Some(u16::from_be_bytes(value.try_into().ok()?)),
+                PORT_DESCRIPTION => port_description = Some(text(value)),
+                SYSTEM_NAME => system_name = Some(text(value)),
+                SYSTEM_DESCRIPTION => system_description = Some(text(value)),
+                // the first ipv4 one, there may be several
+                MANAGEMENT_ADDRESS if management_address.is_none() => {
+                    let (&length, rest) = value.split_first()?;
+                    management_address = rest.get(..length as usize).and_then(ipv4);
+                }
+                _ => {}
+            }
+        }
+        Some(Lldpdu {
+            chassis_id: chassis_id?,
+            port_id: port_id?,
+            ttl: ttl?,
+            port_description,
+            system_name,
+            system_description,
+            management_address,
+        })
+    }
+}
+
+// Values longer than the 9 bit length are cut
+fn tlv(data: &mut Vec<u8>, kind: u8, parts: &[&[u8]]) {
+    let mut value = parts.concat();
+    value.truncate(0x1ff);
+    let header = (kind as u16) << 9 | value.len() as u16;
+    data.extend_from_slice(&header.to_be_bytes());
+    data.extend_from_slice(&value);
+}
+
+fn encode_id(id: &Id, mac_subtype: u8, address_subtype: u8) -> (u8, Vec<u8>) {
+    match id {
+        Id::Mac(mac) => (mac_subtype, mac.0.to_vec()),
+        Id::Address(address) => {
+            let mut value = alloc::vec![FAMILY_IPV4];
+            value.extend_from_slice(&address.0);
+            (address_subtype, value)
+        }
+        // locally assigned
+        Id::Name(name) => (7, name.as_bytes().to_vec()),
+        Id::Other(subtype, value) => (*subtype, value.clone()),
+    }
+}
+
+// An IANA family number followed by the address
+fn ipv4(value: &[u8]) -> Option<ip::Address> {
+    match value {
+        [FAMILY_IPV4, a, b, c, d] => Some(ip::Address([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).trim_end_matches('\0').into()
+}
+
+#[derive(Debug, Clone)]
+pub struct Neighbor {
+    // Where the advertisement came from
+    pub source: ethernet::MacAddress,
+    pub lldpdu: Lldpdu,
+    expires: u64,
+}
+
+impl Neighbor {
+    // Seconds until the neighbor is forgotten
+    pub fn remaining(&self) -> f64 {
+        self.expires.saturating_sub(asyn::timestamp()) as f64 / 1_000_000_000.0
+    }
+}
+
+struct Counters {
+    sent: metrics::Counter,
+    received: metrics::Counter,
+    malformed: metrics::Counter,
+    table_full: metrics::Counter,
+    neighbors: metrics::Gauge,
+}
+
+impl Counters {
+    fn new(registry: &metrics::Registry) -> Counters {
+        let frames = |direction| {
+            registry.counter("lldp_frames_total", "LLDPDUs sent and received.", direction)
+        };
+        let dropped = |reason| registry.counter("lldp_dropped_total", "LLDPDUs not used.", reason);
+        Counters {
+            sent: frames(&[("direction", "sent")]),
+            received: frames(&[("direction", "received")]),
+            malformed: dropped(&[("reason", "malformed")]),
+            table_full: dropped(&[("reason", "table_full")]),
+            neighbors: registry.gauge("lldp_neighbors", "Neighbors in the table.", &[]),
+        }
+    }
+}
+
+// Advertises us with LLDP (ether type 0x88cc) and keeps a table of what
+// the neighbors advertise, each until its ttl runs out
+pub struct Service {
+    mac: ethernet::MacAddress,
+    socket: ethernet::Socket,
+    ip: Arc<ip::Service>,
+    options: Options,
+    neighbors: asyn::Mutex<HashMap<(Id, Id), Neighbor>>,
+    counters: Counters,
+}
+
+impl Service {
+    // The management address is the current address of the ip service
+    pub fn new(
+        mac: ethernet::MacAddress,
+        network: &impl ethernet::Network,
+        ip: Arc<ip::Service>,
+        options: Options,
+    ) -> Service {
+        let interface = network.filters();
+        let mut filters = interface.receive_filters();
+        if !filters.multicast.contains(&MULTICAST) {
+            filters.multicast.push(MULTICAST);
+            if let Err(e) = interface.set_receive_filters(filters) {
+                info!("lldp: failed to receive {}: {:?}", MULTICAST, e);
+            }
+        }
+        Service {
+            mac,
+            socket: network.open(ethernet::Type::LLDP),
+            ip,
+            options,
+            neighbors: asyn::Mutex::new(HashMap::new()),
+            counters: Counters::new(&network.metrics()),
+        }
+    }
+
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.clone().task_send()));
+        e.spawn(asyn::Task::new(self.task_receive()));
+    }
+
+    // What we advertise
+    pub fn lldpdu(&self) -> Lldpdu {
+        let address = self.ip.address();
+        Lldpdu {
+            chassis_id: Id::Mac(self.mac),
+            port_id: Id::Mac(self.mac),
+            ttl: self.options.ttl,
+            port_description: None,
+            system_name: Some(self.options.system_name.clone()),
+            system_description: None,
+            management_address: (address != ip::Address([0, 0, 0, 0])).then_some(address),
+        }
+    }
+
+    // Neighbors whose ttl has not run out, ordered by chassis and port
+    pub async fn neighbors(&self) -> Vec<Neighbor> {
+        let mut neighbors = self.neighbors.lock().await;
+        expire(&mut neighbors);
+        self.counters.neighbors.set(neighbors.len() as u64);
+        let mut list: Vec<Neighbor> = neighbors.values().cloned().collect();
+        list.sort_by_key(|n| {
+            (
+                n.lldpdu.chassis_id.to_string(),
+                n.lldpdu.port_id.to_string(),
+            )
+        });
+        list
+    }
+
+    // Advertises a ttl of 0, neighbors drop us from their tables right away
+    pub fn withdraw(&self) {
+        self.send(0);
+    }
+
+    fn send(&self, ttl: u16) {
+        let data = Lldpdu {
+            ttl,
+            ..self.lldpdu()
+        }
+        .encode();
+        let mut p = ethernet::Packet::new();
+        p.set_mac_destination(MULTICAST);
+        p.set_size(data.len());
+        p.data_mut().copy_from_slice(&data);
+        // a missed advertisement is made up for by the next one
+        if self.socket.send(p).is_ok() {
+            self.counters.sent.inc();
+        }
+    }
+
+    async fn task_send(self: Arc<Self>) {
+        loop {
+            self.send(self.options.ttl);
+            asyn::sleep(self.options.interval).await;
+        }
+    }
+
+    async fn task_receive(self: Arc<Self>) {
+        loop {
+            let p = self.socket.receive().await;
+            self.counters.received.inc();
+            let Ok(lldpdu) = Lldpdu::parse(p.data()) else {
+                self.counters.malformed.inc();
+                continue;
+            };
+            let mut neighbors = self.neighbors.lock().await;
+            expire(&mut neighbors);
+            let key = (lldpdu.chassis_id.clone(), lldpdu.port_id.clone());
+            if lldpdu.ttl == 0 {
+                neighbors.remove(&key);
+            } else if neighbors.len() < MAX_NEIGHBORS || neighbors.contains_key(&key) {
+                if !neighbors.contains_key(&key) {
+                    info!(
+                        "lldp: new neighbor {} port {}",
+                        lldpdu.chassis_id, lldpdu.port_id
+                    );
+                }
+                let expires = asyn::timestamp() + lldpdu.ttl as u64 * 1_000_000_000;
+                neighbors.insert(
+                    key,
+                    Neighbor {
+                        source: p.mac_source(),
+                        lldpdu,
+                        expires,
+                    },
+                );
+            } else {
+                self.counters.table_full.inc();
+            }
+            self.counters.neighbors.set(neighbors.len() as u64);
+        }
+    }
+}
+
+fn expire(neighbors: &mut HashMap<(Id, Id), Neighbor>) {
+    let now = asyn::timestamp();
+    neighbors.retain(|_, n| n.expires > now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lldpdus() {
+        let lldpdu = Lldpdu {
+            chassis_id: Id::Mac(ethernet::MacAddress([0x02, 0, 0, 0, 0, 1])),
+            port_id: Id::Name(String::from("Gi1/0/7")),
+            ttl: 120,
+            port_description: None,
+            system_name: Some(String::from("lab-host")),
+            system_description: None,
+            management_address: Some(ip::Address([10, 0, 0, 2])),
+        };
+        let data = lldpdu.encode();
+        assert_eq!(&data[..3], &[0x02, 0x07, CHASSIS_MAC]);
+        assert_eq!(Lldpdu::parse(&data), Ok(lldpdu.clone()));
+        // frames are padded after the end
+        let mut padded = data.clone();
+        padded.resize(data.len() + 10, 0);
+        assert_eq!(Lldpdu::parse(&padded), Ok(lldpdu));
+
+        // ttl missing
+        assert_eq!(Lldpdu::parse(&data[..19]), Err(Error::Malformed));
+        // value running past the end
+        assert_eq!(Lldpdu::parse(&data[..5]), Err(Error::Malformed));
+    }
+}
@@ -0,0 +1,107 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    future::{self, Future},
+    task::Poll,
+};
+
+use super::{
+    ethernet::{Device, Filters, MacAddress, ReceiveFilters},
+    Error,
+};
+
+// A device driven by the test: injected frames are received in order,
+// whatever the filters say, and transmitted ones are kept
+pub struct Mock {
+    permanent: MacAddress,
+    mac: Cell<MacAddress>,
+    received: RefCell<VecDeque<Vec<u8>>>,
+    transmitted: RefCell<Vec<Vec<u8>>>,
+    // What the device claims is still going out
+    in_flight: Cell<usize>,
+    filters: RefCell<ReceiveFilters>,
+}
+
+impl Mock {
+    pub fn new(mac: MacAddress) -> Mock {
+        Mock {
+            permanent: mac,
+            mac: Cell::new(mac),
+            received: RefCell::new(VecDeque::new()),
+            transmitted: RefCell::new(Vec::new()),
+            in_flight: Cell::new(0),
+            filters: RefCell::new(ReceiveFilters::default()),
+        }
+    }
+
+    pub fn inject(&self, frame: &[u8]) {
+        self.received.borrow_mut().push_back(frame.to_vec());
+    }
+
+    // Frames sent since the last call
+    pub fn transmitted(&self) -> Vec<Vec<u8>> {
+        self.transmitted.take()
+    }
+
+    pub fn set_in_flight(&self, n: usize) {
+        self.in_flight.set(n);
+    }
+}
+
+impl Filters for Mock {
+    fn receive_filters(&self) -> ReceiveFilters {
+        self.filters.borrow().clone()
+    }
+
+    fn set_receive_filters(&self, filters: ReceiveFilters) -> uefi::Result {
+        *self.filters.borrow_mut() = filters;
+        Ok(())
+    }
+}
+
+impl Device for Mock {
+    fn mac_address(&self) -> MacAddress {
+        self.mac.get()
+    }
+
+    fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result {
+        self.mac.set(address.unwrap_or(self.permanent));
+        Ok(())
+    }
+
+    fn receive<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize, Error>> + 'a {
+        future::poll_fn(move |_| {
+            let Some(frame) = self.received.borrow_mut().pop_front() else {
+                return Poll::Pending;
+            };
+            if frame.len() > buf.len() {
+                return Poll::Ready(Err(Error::Device(uefi::Status::BUFFER_TOO_SMALL)));
+            }
+            buf[..frame.len()].copy_from_slice(&frame);
+            Poll::Ready(Ok(frame.len()))
+        })
+    }
+
+    fn transmit(
+        &self,
+        buffer: Box<[u8]>,
+        len: usize,
+    ) -> impl Future<Output = Result<(), Error>> + '_ {
+        self.transmitted.borrow_mut().push(buffer[..len].to_vec());
+        future::ready(Ok(()))
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.get()
+    }
+
+    fn recover(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> uefi::Result {
+        Ok(())
+    }
+}
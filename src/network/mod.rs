@@ -1,4 +1,30 @@
 pub mod arp;
+pub mod capture;
+pub mod dhcp;
+pub mod dns;
+mod error;
 pub mod ethernet;
+#[cfg(any(test, fuzzing))]
+pub mod fuzz;
+#[cfg(any(test, fuzzing))]
+mod harness;
+pub mod http;
 pub mod icmp;
 pub mod ip;
+pub mod lldp;
+#[cfg(any(test, fuzzing))]
+mod mock;
+mod queues;
+#[cfg(test)]
+mod replay;
+#[cfg(test)]
+pub(crate) mod sim;
+mod sink;
+pub mod stack;
+pub mod tcp;
+pub mod tftp;
+pub mod udp;
+pub mod wol;
+
+pub use error::Error;
+pub use sink::{Sink, Source};
@@ -0,0 +1,52 @@
+extern crate alloc;
+
+use alloc::sync::Arc;
+use crossbeam_queue::ArrayQueue;
+
+// Services keep the receive queue of every socket they hand out and the
+// socket holds the only other reference to it. A queue nobody else holds
+// belongs to a dropped socket; services skip it and forget it when they
+// next change their tables.
+pub(crate) fn is_open<T>(queue: &Arc<ArrayQueue<T>>) -> bool {
+    Arc::strong_count(queue) > 1
+}
+
+// Gives every open queue its own copy of a packet. Returns how many took
+// it and how many were full.
+pub(crate) fn deliver<T: Clone>(queues: &[Arc<ArrayQueue<T>>], p: T) -> (usize, usize) {
+    let mut open = queues.iter().filter(|q| is_open(q)).peekable();
+    let (mut delivered, mut full) = (0, 0);
+    let mut p = Some(p);
+    while let Some(q) = open.next() {
+        let copy = match open.peek() {
+            Some(_) => p.clone(),
+            None => p.take(),
+        };
+        match copy.map(|c| q.push(c)) {
+            Some(Ok(())) => delivered += 1,
+            _ => full += 1,
+        }
+    }
+    (delivered, full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_sockets_are_skipped() {
+        let table = [Arc::new(ArrayQueue::new(1)), Arc::new(ArrayQueue::new(1))];
+        let first = table[0].clone();
+        assert_eq!(deliver(&table, 1), (1, 0));
+        assert_eq!(deliver(&table, 2), (0, 1));
+
+        let second = table[1].clone();
+        assert_eq!(deliver(&table, 3), (1, 1));
+        assert_eq!((first.pop(), second.pop()), (Some(1), Some(3)));
+
+        drop(first);
+        assert!(!is_open(&table[0]));
+        assert_eq!(deliver(&table, 4), (1, 0));
+    }
+}
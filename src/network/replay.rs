@@ -0,0 +1,378 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::future::Future;
+
+use super::{capture::Direction, ethernet, harness::Harness, mock::Mock, Error};
+use crate::asyn::{self, Executor};
+
+const PCAP_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_NANOS: u32 = 0xa1b23c4d;
+const SECTION_HEADER: u32 = 0x0a0d0d0a;
+const BYTE_ORDER: u32 = 0x1a2b3c4d;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+const LINKTYPE_ETHERNET: u16 = 1;
+// Virtual nanoseconds between two rounds of the executor
+const TICK: u64 = 100_000;
+
+// A frame of a capture file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    // Nanoseconds since the first record
+    pub time: u64,
+    // From the pcapng flags, None when the file does not say
+    pub direction: Option<Direction>,
+    pub frame: Vec<u8>,
+}
+
+impl Record {
+    // The file's direction, or ours when the frame is from our mac
+    fn outbound(&self, mac: ethernet::MacAddress) -> bool {
+        match self.direction {
+            Some(d) => d == Direction::Outbound,
+            None => self.frame.get(6..12) == Some(&mac.0[..]),
+        }
+    }
+}
+
+// Little or big endian reads, as the file was written
+#[derive(Clone, Copy)]
+struct Reader(bool);
+
+impl Reader {
+    fn u16(&self, data: &[u8], at: usize) -> Result<u16, Error> {
+        let b: [u8; 2] = data
+            .get(at..at + 2)
+            .ok_or(Error::Malformed)?
+            .try_into()
+            .unwrap();
+        Ok(if self.0 {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, data: &[u8], at: usize) -> Result<u32, Error> {
+        let b: [u8; 4] = data
+            .get(at..at + 4)
+            .ok_or(Error::Malformed)?
+            .try_into()
+            .unwrap();
+        Ok(if self.0 {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+}
+
+// Reads a pcap or pcapng file of ethernet frames
+pub fn read(data: &[u8]) -> Result<Vec<Record>, Error> {
+    let magic = Reader(false).u32(data, 0)?;
+    let mut records = match magic {
+        SECTION_HEADER => read_pcapng(data)?,
+        _ => read_pcap(data)?,
+    };
+    if let Some(start) = records.iter().map(|r| r.time).min() {
+        for r in records.iter_mut() {
+            r.time -= start;
+        }
+    }
+    Ok(records)
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<Record>, Error> {
+    let (r, nanos) = match Reader(false).u32(data, 0)? {
+        PCAP_MICROS => (Reader(false), false),
+        PCAP_NANOS => (Reader(false), true),
+        m if m.swap_bytes() == PCAP_MICROS => (Reader(true), false),
+        m if m.swap_bytes() == PCAP_NANOS => (Reader(true), true),
+        _ => return Err(Error::Malformed),
+    };
+    if r.u32(data, 20)? != LINKTYPE_ETHERNET as u32 {
+        return Err(Error::Malformed);
+    }
+    let mut records = Vec::new();
+    let mut at = 24;
+    while at < data.len() {
+        let seconds = r.u32(data, at)? as u64;
+        let fraction = r.u32(data, at + 4)? as u64;
+        let len = r.u32(data, at + 8)? as usize;
+        let frame = data.get(at + 16..at + 16 + len).ok_or(Error::Malformed)?;
+        records.push(Record {
+            time: seconds * 1_000_000_000 + if nanos { fraction } else { fraction * 1000 },
+            direction: None,
+            frame: frame.to_vec(),
+        });
+        at += 16 + len;
+    }
+    Ok(records)
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Record>, Error> {
+    let mut r = Reader(false);
+    // nanoseconds per timestamp unit, for each interface of the section
+    let mut units: Vec<u64> = Vec::new();
+    let mut records = Vec::new();
+    let mut at = 0;
+    while at < data.len() {
+        if Reader(false).u32(data, at)? == SECTION_HEADER {
+            r = match Reader(false).u32(data, at + 8)? {
+                BYTE_ORDER => Reader(false),
+                m if m.swap_bytes() == BYTE_ORDER => Reader(true),
+                _ => return Err(Error::Malformed),
+            };
+            units.clear();
+        }
+        let typ = r.u32(data, at)?;
+        let len = r.u32(data, at + 4)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(Error::Malformed);
+        }
+        let body = data.get(at + 8..at + len - 4).ok_or(Error::Malformed)?;
+        match typ {
+            INTERFACE_DESCRIPTION => {
+                if r.u16(body, 0)? != LINKTYPE_ETHERNET {
+                    return Err(Error::Malformed);
+                }
+                let mut unit = 1000;
+                for (code, value) in options(r, body.get(8..).unwrap_or(&[]))? {
+                    // if_tsresol, a power of ten or of two
+                    if code == 9 && value.len() == 1 {
+                        unit = match value[0] {
+                            v if v & 0x80 == 0 => {
+                                1_000_000_000 / 10u64.pow(v as u32).min(1_000_000_000)
+                            }
+                            v => (1_000_000_000u64 >> (v & 0x7f).min(30)).max(1),
+                        };
+                    }
+                }
+                units.push(unit);
+            }
+            ENHANCED_PACKET => {
+                let interface = r.u32(body, 0)? as usize;
+                let unit = *units.get(interface).ok_or(Error::Malformed)?;
+                let time = ((r.u32(body, 4)? as u64) << 32) | r.u32(body, 8)? as u64;
+                let captured = r.u32(body, 12)? as usize;
+                let frame = body.get(20..20 + captured).ok_or(Error::Malformed)?;
+                let mut direction = None;
+                let padded = 20 + captured.next_multiple_of(4);
+                for (code, value) in options(r, body.get(padded..).unwrap_or(&[]))? {
+                    // epb_flags, the low two bits
+                    if code == 2 && value.len() == 4 {
+                        direction = match r.u32(value, 0)? & 3 {
+                            1 => Some(Direction::Inbound),
+                            2 => Some(Direction::Outbound),
+                            _ => None,
+                        };
+                    }
+                }
+                records.push(Record {
+                    time: time * unit,
+                    direction,
+                    frame: frame.to_vec(),
+                });
+            }
+            SIMPLE_PACKET => {
+                if body.len() < 4 {
+                    return Err(Error::Malformed);
+                }
+                let captured = (r.u32(body, 0)? as usize).min(body.len() - 4);
+                records.push(Record {
+                    // no timestamp, the frame follows the one before
+                    time: records.last().map_or(0, |p: &Record| p.time),
+                    direction: None,
+                    frame: body[4..4 + captured].to_vec(),
+                });
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(records)
+}
+
+// Code and value of each option up to the end marker
+fn options(r: Reader, mut data: &[u8]) -> Result<Vec<(u16, &[u8])>, Error> {
+    let mut options = Vec::new();
+    while data.len() >= 4 {
+        let code = r.u16(data, 0)?;
+        let len = r.u16(data, 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        let value = data.get(4..4 + len).ok_or(Error::Malformed)?;
+        options.push((code, value));
+        data = data.get(4 + len.next_multiple_of(4)..).unwrap_or(&[]);
+    }
+    Ok(options)
+}
+
+// An ethernet service on a mock device, fed the inbound frames of a
+// capture on a virtual clock. What it transmits is recorded for comparing
+// with the outbound frames of the capture.
+pub struct Replay {
+    harness: Harness,
+    ethernet: Arc<ethernet::Service<Mock>>,
+}
+
+impl Replay {
+    pub fn new(mac: ethernet::MacAddress) -> Replay {
+        Replay {
+            harness: Harness::new(TICK),
+            ethernet: Arc::new(ethernet::Service::with_device(Mock::new(mac))),
+        }
+    }
+
+    pub fn executor(&self) -> Arc<dyn Executor> {
+        self.harness.executor()
+    }
+
+    // Where the services under test open their sockets
+    pub fn ethernet(&self) -> &Arc<ethernet::Service<Mock>> {
+        &self.ethernet
+    }
+
+    // Runs the executor until the future is done, for async constructors
+    pub fn block_on<T: 'static>(&self, f: impl Future<Output = T> + 'static) -> T {
+        self.harness.block_on(f)
+    }
+
+    // Starts the ethernet service and injects the inbound records, each at
+    // its time in the capture. Runs settle seconds past the last one and
+    // returns what was transmitted, timed from the first.
+    pub fn play(&self, records: &[Record], settle: f64) -> Vec<Record> {
+        let mac = self.ethernet.mac_address();
+        let mock = self.ethernet.interface();
+        self.ethernet.clone().start(self.executor());
+        let start = asyn::timestamp();
+        let mut inbound = records.iter().filter(|r| !r.outbound(mac)).peekable();
+        let end =
+            records.iter().map(|r| r.time).max().unwrap_or(0) + (settle * 1_000_000_000.0) as u64;
+        let mut transmitted = Vec::new();
+        while asyn::timestamp() - start <= end {
+            let now = asyn::timestamp() - start;
+            while let Some(r) = inbound.next_if(|r| r.time <= now) {
+                mock.inject(&r.frame);
+            }
+            self.harness.step();
+            transmitted.extend(mock.transmitted().into_iter().map(|frame| Record {
+                time: now,
+                direction: Some(Direction::Outbound),
+                frame,
+            }));
+        }
+        transmitted
+    }
+}
+
+// The outbound frames of a capture against the ones transmitted, in
+// order, ignoring times. The error tells the first difference.
+pub fn compare(
+    mac: ethernet::MacAddress,
+    expected: &[Record],
+    transmitted: &[Record],
+) -> Result<(), String> {
+    let expected: Vec<&Record> = expected.iter().filter(|r| r.outbound(mac)).collect();
+    for (i, (e, t)) in expected.iter().zip(transmitted.iter()).enumerate() {
+        if e.frame != t.frame {
+            let at = e
+                .frame
+                .iter()
+                .zip(t.frame.iter())
+                .position(|(a, b)| a != b)
+                .unwrap_or(e.frame.len().min(t.frame.len()));
+            return Err(format!(
+                "frame {} differs at byte {}:\nexpected    {:02x?}\ntransmitted {:02x?}",
+                i, at, e.frame, t.frame
+            ));
+        }
+    }
+    if expected.len() != transmitted.len() {
+        return Err(format!(
+            "expected {} frames, {} transmitted",
+            expected.len(),
+            transmitted.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{arp, icmp, ip};
+
+    const MAC: ethernet::MacAddress = ethernet::MacAddress([2, 0, 0, 0, 0, 1]);
+    const ADDRESS: ip::Address = ip::Address([10, 0, 0, 1]);
+
+    // ARP, IP and ICMP as 10.0.0.1/24
+    fn replay() -> Replay {
+        let replay = Replay::new(MAC);
+        let arp = Arc::new(arp::Service::new(ADDRESS, MAC, &**replay.ethernet()));
+        let ip = Arc::new(ip::Service::new(
+            &**replay.ethernet(),
+            arp.clone(),
+            ADDRESS,
+            ip::Address([255, 255, 255, 0]),
+            ip::Address([0, 0, 0, 0]),
+        ));
+        let icmp = Arc::new(replay.block_on(icmp::Service::new(ip.clone())));
+        arp.start(replay.executor());
+        ip.start(replay.executor());
+        icmp.start(replay.executor());
+        replay
+    }
+
+    fn check(capture: &[u8]) {
+        let records = read(capture).unwrap();
+        let replay = replay();
+        let transmitted = replay.play(&records, 1.0);
+        if let Err(e) = compare(MAC, &records, &transmitted) {
+            panic!("{}", e);
+        }
+    }
+
+    // A peer resolves us and pings twice, in pcapng with directions
+    #[test]
+    fn arp_and_ping() {
+        check(include_bytes!("testdata/arp_and_ping.pcapng"));
+    }
+
+    // A request for another address goes unanswered, ours does not. In
+    // classic big endian pcap, with directions told by the mac.
+    #[test]
+    fn arp() {
+        check(include_bytes!("testdata/arp.pcap"));
+    }
+
+    // A simple packet block too short for its length field
+    #[test]
+    fn short_simple_packet() {
+        let mut data = Vec::new();
+        for word in [SECTION_HEADER, 28, BYTE_ORDER, 1, u32::MAX, u32::MAX, 28] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [SIMPLE_PACKET, 12, 12] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        assert!(matches!(read(&data), Err(Error::Malformed)));
+    }
+
+    #[test]
+    fn differences() {
+        let frame = |b: u8| Record {
+            time: 0,
+            direction: Some(Direction::Outbound),
+            frame: Vec::from([b; 14]),
+        };
+        assert!(compare(MAC, &[frame(1)], &[frame(1)]).is_ok());
+        assert!(compare(MAC, &[frame(1)], &[frame(2)])
+            .unwrap_err()
+            .starts_with("frame 0 differs at byte 0"));
+        assert!(compare(MAC, &[frame(1)], &[]).is_err());
+    }
+}
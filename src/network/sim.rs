@@ -0,0 +1,474 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::VecDeque, rc::Rc, sync::Arc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    future::{self, poll_fn, Future},
+    task::Poll,
+};
+use hashbrown::HashMap;
+
+use super::{
+    arp,
+    ethernet::{self, Device, Filters, MacAddress, ReceiveFilters},
+    harness::{Handle, Harness, Rng},
+    icmp, ip, tcp, udp, Error,
+};
+use crate::asyn::{self, Executor, Task};
+
+// Virtual nanoseconds between two rounds of the executor
+const TICK: u64 = 100_000;
+
+// How a link treats the frames crossing it, the same both ways
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    // Seconds
+    pub latency: f64,
+    // Up to this many seconds more, at random, which reorders frames
+    pub jitter: f64,
+    // Chances from 0 to 1
+    pub loss: f64,
+    pub duplicate: f64,
+}
+
+impl Default for Link {
+    fn default() -> Link {
+        Link {
+            latency: 0.0005,
+            jitter: 0.0,
+            loss: 0.0,
+            duplicate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Switch(usize);
+
+// What is at the other end of a switch port
+enum Peer {
+    // Frames the nic has not received yet
+    Host(VecDeque<Vec<u8>>),
+    // The port of another switch
+    Uplink(usize),
+}
+
+struct Port {
+    switch: usize,
+    link: Link,
+    peer: Peer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    // Into the switch the port belongs to
+    Switch(usize),
+    // Out of the port to its host
+    Host(usize),
+}
+
+struct Frame {
+    at: u64,
+    // Frames due at the same time keep their order
+    sequence: u64,
+    target: Target,
+    data: Vec<u8>,
+}
+
+// Switches with learning tables, the ports between them and the frames
+// on the links
+struct Fabric {
+    ports: Vec<Port>,
+    tables: Vec<HashMap<MacAddress, usize>>,
+    in_transit: Vec<Frame>,
+    sequence: u64,
+    rng: Rng,
+}
+
+impl Fabric {
+    fn random(&mut self) -> f64 {
+        self.rng.random()
+    }
+
+    // Puts a frame on the link of a port
+    fn transmit(&mut self, port: usize, target: Target, data: &[u8]) {
+        let link = self.ports[port].link;
+        if self.random() < link.loss {
+            return;
+        }
+        let copies = if self.random() < link.duplicate { 2 } else { 1 };
+        for _ in 0..copies {
+            let delay = link.latency + link.jitter * self.random();
+            self.sequence += 1;
+            self.in_transit.push(Frame {
+                at: asyn::timestamp() + (delay * 1_000_000_000.0) as u64,
+                sequence: self.sequence,
+                target,
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    // A frame entering a switch: the source is learned, known unicast
+    // destinations get it, everything else is flooded
+    fn forward(&mut self, ingress: usize, data: &[u8]) {
+        if data.len() < 14 {
+            return;
+        }
+        let switch = self.ports[ingress].switch;
+        let destination = MacAddress(data[0..6].try_into().unwrap());
+        let source = MacAddress(data[6..12].try_into().unwrap());
+        self.tables[switch].insert(source, ingress);
+        let learned = match destination.0[0] & 1 {
+            0 => self.tables[switch].get(&destination).copied(),
+            _ => None,
+        };
+        let egress: Vec<usize> = match learned {
+            Some(port) => Vec::from([port]),
+            None => (0..self.ports.len())
+                .filter(|p| self.ports[*p].switch == switch)
+                .collect(),
+        };
+        for port in egress.into_iter().filter(|p| *p != ingress) {
+            let target = match self.ports[port].peer {
+                Peer::Host(_) => Target::Host(port),
+                Peer::Uplink(other) => Target::Switch(other),
+            };
+            self.transmit(port, target, data);
+        }
+    }
+
+    // Hands over the frames whose time has come, earliest first
+    fn deliver(&mut self) {
+        let now = asyn::timestamp();
+        while let Some(i) = self
+            .in_transit
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.at <= now)
+            .min_by_key(|(_, f)| (f.at, f.sequence))
+            .map(|(i, _)| i)
+        {
+            let frame = self.in_transit.swap_remove(i);
+            match frame.target {
+                Target::Switch(port) => self.forward(port, &frame.data),
+                Target::Host(port) => {
+                    if let Peer::Host(received) = &mut self.ports[port].peer {
+                        received.push_back(frame.data);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A simulated interface plugged into a switch port
+pub struct Nic {
+    fabric: Rc<RefCell<Fabric>>,
+    port: usize,
+    permanent: MacAddress,
+    mac: Cell<MacAddress>,
+    filters: RefCell<ReceiveFilters>,
+}
+
+impl Nic {
+    fn accepts(&self, destination: MacAddress) -> bool {
+        let filters = self.filters.borrow();
+        match destination {
+            d if d == self.mac.get() => filters.unicast,
+            ethernet::MAC_BROADCAST => filters.broadcast,
+            d if d.0[0] & 1 == 1 => filters.all_multicast || filters.multicast.contains(&d),
+            _ => false,
+        }
+    }
+}
+
+impl Filters for Nic {
+    fn receive_filters(&self) -> ReceiveFilters {
+        self.filters.borrow().clone()
+    }
+
+    fn set_receive_filters(&self, filters: ReceiveFilters) -> uefi::Result {
+        *self.filters.borrow_mut() = filters;
+        Ok(())
+    }
+}
+
+impl Device for Nic {
+    fn mac_address(&self) -> MacAddress {
+        self.mac.get()
+    }
+
+    fn set_mac_address(&self, address: Option<MacAddress>) -> uefi::Result {
+        self.mac.set(address.unwrap_or(self.permanent));
+        Ok(())
+    }
+
+    fn receive<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize, Error>> + 'a {
+        future::poll_fn(move |_| loop {
+            let mut fabric = self.fabric.borrow_mut();
+            let Peer::Host(received) = &mut fabric.ports[self.port].peer else {
+                unreachable!("nic on an uplink port");
+            };
+            let Some(frame) = received.pop_front() else {
+                return Poll::Pending;
+            };
+            drop(fabric);
+            let destination = MacAddress(frame[0..6].try_into().unwrap());
+            if !self.filters.borrow().promiscuous && !self.accepts(destination) {
+                continue;
+            }
+            if frame.len() > buf.len() {
+                return Poll::Ready(Err(Error::Device(uefi::Status::BUFFER_TOO_SMALL)));
+            }
+            buf[..frame.len()].copy_from_slice(&frame);
+            return Poll::Ready(Ok(frame.len()));
+        })
+    }
+
+    fn transmit(
+        &self,
+        buffer: Box<[u8]>,
+        len: usize,
+    ) -> impl Future<Output = Result<(), Error>> + '_ {
+        self.fabric
+            .borrow_mut()
+            .transmit(self.port, Target::Switch(self.port), &buffer[..len]);
+        future::ready(Ok(()))
+    }
+
+    // frames are copied onto the link at once
+    fn in_flight(&self) -> usize {
+        0
+    }
+
+    fn recover(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> uefi::Result {
+        Ok(())
+    }
+}
+
+// ARP, IP, ICMP, UDP and TCP on a simulated interface, all started
+pub struct Host {
+    pub arp: Arc<arp::Service>,
+    pub ip: Arc<ip::Service>,
+    pub icmp: Arc<icmp::Service>,
+    pub udp: Arc<udp::Service>,
+    pub tcp: Arc<tcp::Service>,
+}
+
+// Switches, links and hosts in one test thread. Time only moves while the
+// simulator runs, in steps of TICK, so every run with the same seed sees
+// the same frames in the same order.
+pub struct Simulator {
+    fabric: Rc<RefCell<Fabric>>,
+    harness: Harness,
+    switches: RefCell<usize>,
+}
+
+impl Simulator {
+    pub fn new(seed: u64) -> Simulator {
+        let fabric = Rc::new(RefCell::new(Fabric {
+            ports: Vec::new(),
+            tables: Vec::new(),
+            in_transit: Vec::new(),
+            sequence: 0,
+            rng: Rng::new(seed),
+        }));
+        let harness = Harness::new(TICK);
+        // The first task, so each round starts with the frames due
+        let f = fabric.clone();
+        harness.executor().spawn(Task::new(poll_fn(move |_| {
+            f.borrow_mut().deliver();
+            Poll::<()>::Pending
+        })));
+        Simulator {
+            fabric,
+            harness,
+            switches: RefCell::new(0),
+        }
+    }
+
+    pub fn executor(&self) -> Arc<dyn Executor> {
+        self.harness.executor()
+    }
+
+    pub fn switch(&self) -> Switch {
+        let mut switches = self.switches.borrow_mut();
+        self.fabric.borrow_mut().tables.push(HashMap::new());
+        *switches += 1;
+        Switch(*switches - 1)
+    }
+
+    // Wires a port of each switch to the other
+    pub fn connect(&self, a: Switch, b: Switch, link: Link) {
+        let mut fabric = self.fabric.borrow_mut();
+        let first = fabric.ports.len();
+        for (switch, peer) in [(a, first + 1), (b, first)] {
+            fabric.ports.push(Port {
+                switch: switch.0,
+                link,
+                peer: Peer::Uplink(peer),
+            });
+        }
+    }
+
+    pub fn nic(&self, switch: Switch, mac: MacAddress, link: Link) -> Nic {
+        let mut fabric = self.fabric.borrow_mut();
+        fabric.ports.push(Port {
+            switch: switch.0,
+            link,
+            peer: Peer::Host(VecDeque::new()),
+        });
+        Nic {
+            fabric: self.fabric.clone(),
+            port: fabric.ports.len() - 1,
+            permanent: mac,
+            mac: Cell::new(mac),
+            filters: RefCell::new(ReceiveFilters::default()),
+        }
+    }
+
+    // A host on a /24 without a gateway
+    pub fn host(&self, switch: Switch, mac: MacAddress, address: ip::Address, link: Link) -> Host {
+        let ethernet = Arc::new(ethernet::Service::with_device(self.nic(switch, mac, link)));
+        let arp = Arc::new(arp::Service::new(address, mac, &*ethernet));
+        let ip = Arc::new(ip::Service::new(
+            &*ethernet,
+            arp.clone(),
+            address,
+            ip::Address([255, 255, 255, 0]),
+            ip::Address([0, 0, 0, 0]),
+        ));
+        let icmp = Arc::new(self.block_on(icmp::Service::new(ip.clone())));
+        let udp = Arc::new(self.block_on(udp::Service::new(ip.clone())));
+        let tcp = Arc::new(self.block_on(tcp::Service::new(ip.clone())));
+        ethernet.clone().start(self.executor());
+        arp.clone().start(self.executor());
+        ip.clone().start(self.executor());
+        icmp.clone().start(self.executor());
+        udp.clone().start(self.executor());
+        tcp.clone().start(self.executor());
+        Host {
+            arp,
+            ip,
+            icmp,
+            udp,
+            tcp,
+        }
+    }
+
+    // Runs a task beside the hosts, its result is taken with join
+    pub fn spawn<T: 'static>(&self, f: impl Future<Output = T> + 'static) -> Handle<T> {
+        self.harness.spawn(f)
+    }
+
+    // Runs the hosts until the task is done, failing the test if that
+    // takes too long
+    pub fn join<T: 'static>(&self, handle: Handle<T>) -> T {
+        self.harness.join(handle)
+    }
+
+    pub fn block_on<T: 'static>(&self, f: impl Future<Output = T> + 'static) -> T {
+        self.harness.block_on(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(n: u8) -> MacAddress {
+        MacAddress([2, 0, 0, 0, 0, n])
+    }
+
+    fn address(n: u8) -> ip::Address {
+        ip::Address([10, 0, 0, n])
+    }
+
+    // Pings until count replies came back, resending after timeouts and
+    // while arp is unresolved. Returns the requests sent.
+    async fn ping(icmp: Arc<icmp::Service>, to: ip::Address, count: usize) -> usize {
+        let socket = icmp.open(to).await;
+        let (mut sent, mut replies) = (0, 0);
+        while replies < count {
+            sent += 1;
+            match socket.send(&[7; 32]).await {
+                Ok(()) => {}
+                Err(Error::Unresolved(_)) => {
+                    asyn::sleep(0.1).await;
+                    continue;
+                }
+                Err(e) => panic!("ping {}: {}", to, e),
+            }
+            if let Ok(reply) = socket.receive(0.5).await {
+                assert_eq!(reply.ip.source_address(), to);
+                replies += 1;
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn ping_across_a_switch() {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let a = sim.host(switch, mac(1), address(1), Link::default());
+        let b = sim.host(switch, mac(2), address(2), Link::default());
+
+        let sent = sim.block_on(ping(a.icmp.clone(), address(2), 3));
+        // the first request waits for arp
+        assert_eq!(sent, 4);
+        let a_table = sim.block_on(async move { a.arp.table.lock().await.clone() });
+        assert_eq!(a_table.get(&address(2)), Some(&mac(2)));
+        // b learned a from its request
+        let b_table = sim.block_on(async move { b.arp.table.lock().await.clone() });
+        assert_eq!(b_table.get(&address(1)), Some(&mac(1)));
+    }
+
+    #[test]
+    fn ping_over_a_lossy_reordering_uplink() {
+        let sim = Simulator::new(7);
+        let (left, right) = (sim.switch(), sim.switch());
+        sim.connect(
+            left,
+            right,
+            Link {
+                latency: 0.002,
+                jitter: 0.01,
+                loss: 0.2,
+                duplicate: 0.2,
+            },
+        );
+        let a = sim.host(left, mac(1), address(1), Link::default());
+        let _b = sim.host(right, mac(2), address(2), Link::default());
+        let c = sim.host(right, mac(3), address(3), Link::default());
+
+        let sent = sim.block_on(ping(a.icmp.clone(), address(2), 10));
+        assert!(sent > 10);
+        // hosts on the same switch are not affected by the uplink
+        let sent = sim.block_on(ping(c.icmp.clone(), address(2), 10));
+        assert_eq!(sent, 11);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let run = |seed| {
+            let sim = Simulator::new(seed);
+            let (left, right) = (sim.switch(), sim.switch());
+            let lossy = Link {
+                loss: 0.3,
+                jitter: 0.005,
+                ..Link::default()
+            };
+            sim.connect(left, right, lossy);
+            let a = sim.host(left, mac(1), address(1), Link::default());
+            let _b = sim.host(right, mac(2), address(2), Link::default());
+            sim.block_on(ping(a.icmp.clone(), address(2), 5))
+        };
+        assert_eq!(run(3), run(3));
+    }
+}
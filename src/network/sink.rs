@@ -0,0 +1,71 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use uefi::{proto::media::file::RegularFile, Status};
+
+use crate::fs;
+
+// Largest announced size reserved up front, the peer may announce anything
+const MAX_RESERVE: u64 = 4 * 1024 * 1024;
+
+// Destination of a download
+pub trait Sink {
+    // Size announced by the server, when known up front
+    fn size_hint(&mut self, _size: u64) {}
+    fn write(&mut self, data: &[u8]) -> Result<(), Status>;
+}
+
+// Origin of an upload
+pub trait Source {
+    fn size(&mut self) -> Option<u64> {
+        None
+    }
+    // Fills as much of buf as possible, returning less only at the end
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Status>;
+}
+
+impl Sink for Vec<u8> {
+    fn size_hint(&mut self, size: u64) {
+        if size <= MAX_RESERVE {
+            self.reserve(size as usize);
+        }
+    }
+    fn write(&mut self, data: &[u8]) -> Result<(), Status> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl Sink for RegularFile {
+    fn write(&mut self, data: &[u8]) -> Result<(), Status> {
+        RegularFile::write(self, data).map_err(|e| e.status())
+    }
+}
+
+impl Source for &[u8] {
+    fn size(&mut self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Status> {
+        let n = buf.len().min(self.len());
+        buf[..n].clone_from_slice(&self[..n]);
+        *self = &self[n..];
+        Ok(n)
+    }
+}
+
+impl Source for RegularFile {
+    fn size(&mut self) -> Option<u64> {
+        fs::size(self).ok()
+    }
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Status> {
+        let mut total = 0;
+        while total < buf.len() {
+            match RegularFile::read(self, &mut buf[total..]).map_err(|e| e.status())? {
+                0 => break,
+                n => total += n,
+            }
+        }
+        Ok(total)
+    }
+}
@@ -0,0 +1,349 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use log::info;
+
+use super::{arp, capture::Capture, dhcp, dns, ethernet, icmp, ip, lldp, tcp, udp, wol, Error};
+use crate::{asyn, metrics};
+
+// How long shutdown waits for queued frames to reach the device
+const FLUSH_TIMEOUT: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressing {
+    Static {
+        address: ip::Address,
+        netmask: ip::Address,
+        gateway: ip::Address,
+    },
+    // 0.0.0.0 until a DHCP server hands out a lease, needs udp
+    Dhcp,
+    // 0.0.0.0 until ip().set_config is called
+    Unconfigured,
+}
+
+// Protocols above ip, each can be left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protocols {
+    pub icmp: bool,
+    // Also needed for dns, dhcp and wake-on-lan over udp
+    pub udp: bool,
+    pub tcp: bool,
+    pub wol: bool,
+    pub lldp: bool,
+}
+
+impl Default for Protocols {
+    fn default() -> Protocols {
+        Protocols {
+            icmp: true,
+            udp: true,
+            tcp: true,
+            wol: false,
+            lldp: false,
+        }
+    }
+}
+
+pub struct Builder {
+    vlan: Option<ethernet::Tag>,
+    addressing: Addressing,
+    dns: Vec<ip::Address>,
+    protocols: Protocols,
+    lldp: lldp::Options,
+}
+
+impl Builder {
+    // Runs arp and ip on a VLAN instead of untagged frames
+    pub fn vlan(mut self, tag: Option<ethernet::Tag>) -> Builder {
+        self.vlan = tag;
+        self
+    }
+
+    pub fn addressing(mut self, addressing: Addressing) -> Builder {
+        self.addressing = addressing;
+        self
+    }
+
+    pub fn dns(mut self, servers: Vec<ip::Address>) -> Builder {
+        self.dns = servers;
+        self
+    }
+
+    pub fn protocols(mut self, protocols: Protocols) -> Builder {
+        self.protocols = protocols;
+        self
+    }
+
+    pub fn lldp(mut self, options: lldp::Options) -> Builder {
+        self.lldp = options;
+        self
+    }
+
+    // Creates the services on the first network device, they run once the
+    // stack is started
+    pub async fn build(self) -> Result<NetworkStack, Error> {
+        let ethernet = Arc::new(ethernet::Service::new());
+        let mac_address = ethernet.mac_address();
+        let (address, netmask, gateway) = match self.addressing {
+            Addressing::Static {
+                address,
+                netmask,
+                gateway,
+            } => (address, netmask, gateway),
+            Addressing::Dhcp | Addressing::Unconfigured => (
+                ip::Address([0; 4]),
+                ip::Address([0; 4]),
+                ip::Address([0; 4]),
+            ),
+        };
+
+        let (arp, ip) = match self.vlan {
+            Some(tag) => {
+                let vlan = ethernet.vlan(tag.id, tag.priority);
+                let arp = Arc::new(arp::Service::new(address, mac_address, &vlan));
+                let ip = Arc::new(ip::Service::new(
+                    &vlan,
+                    arp.clone(),
+                    address,
+                    netmask,
+                    gateway,
+                ));
+                (arp, ip)
+            }
+            None => {
+                let arp = Arc::new(arp::Service::new(address, mac_address, &*ethernet));
+                let ip = Arc::new(ip::Service::new(
+                    &*ethernet,
+                    arp.clone(),
+                    address,
+                    netmask,
+                    gateway,
+                ));
+                (arp, ip)
+            }
+        };
+
+        let protocols = self.protocols;
+        let icmp = match protocols.icmp {
+            true => Some(Arc::new(icmp::Service::new(ip.clone()).await)),
+            false => None,
+        };
+        let udp = match protocols.udp {
+            true => Some(Arc::new(udp::Service::new(ip.clone()).await)),
+            false => None,
+        };
+        let tcp = match protocols.tcp {
+            true => Some(Arc::new(tcp::Service::new(ip.clone()).await)),
+            false => None,
+        };
+
+        let mut resolver = None;
+        let mut dhcp = None;
+        let mut wol_udp = None;
+        if let Some(udp) = udp.as_ref() {
+            let dns = Arc::new(dns::Resolver::new(udp.open(0).await?, self.dns));
+            dhcp = Some(Arc::new(dhcp::Client::new(
+                udp.open(dhcp::CLIENT_PORT).await?,
+                mac_address,
+                ip.clone(),
+                Some(dns.clone()),
+            )));
+            resolver = Some(dns);
+            if protocols.wol {
+                wol_udp = match udp.open(wol::PORT).await {
+                    Ok(socket) => Some(socket),
+                    Err(e) => {
+                        info!(
+                            "udp port {}: {}, only raw wake-on-lan packets are heard",
+                            wol::PORT,
+                            e
+                        );
+                        None
+                    }
+                };
+            }
+        }
+        let wol = protocols.wol.then(|| {
+            Arc::new(match self.vlan {
+                Some(tag) => {
+                    wol::Service::new(mac_address, &ethernet.vlan(tag.id, tag.priority), wol_udp)
+                }
+                None => wol::Service::new(mac_address, &*ethernet, wol_udp),
+            })
+        });
+        // lldp talks to the switch port, outside of any vlan
+        let lldp = protocols.lldp.then(|| {
+            Arc::new(lldp::Service::new(
+                mac_address,
+                &*ethernet,
+                ip.clone(),
+                self.lldp,
+            ))
+        });
+
+        Ok(NetworkStack {
+            ethernet,
+            vlan: self.vlan,
+            addressing: self.addressing,
+            arp,
+            ip,
+            icmp,
+            udp,
+            tcp,
+            resolver,
+            dhcp,
+            wol,
+            lldp,
+            started: AtomicBool::new(false),
+        })
+    }
+}
+
+// An interface with its arp/ip stack and the protocols above, wired
+// together. Sockets can be opened before and after start.
+pub struct NetworkStack {
+    ethernet: Arc<ethernet::Service>,
+    vlan: Option<ethernet::Tag>,
+    addressing: Addressing,
+    arp: Arc<arp::Service>,
+    ip: Arc<ip::Service>,
+    icmp: Option<Arc<icmp::Service>>,
+    udp: Option<Arc<udp::Service>>,
+    tcp: Option<Arc<tcp::Service>>,
+    resolver: Option<Arc<dns::Resolver>>,
+    dhcp: Option<Arc<dhcp::Client>>,
+    wol: Option<Arc<wol::Service>>,
+    lldp: Option<Arc<lldp::Service>>,
+    started: AtomicBool,
+}
+
+impl NetworkStack {
+    pub fn builder() -> Builder {
+        Builder {
+            vlan: None,
+            addressing: Addressing::Unconfigured,
+            dns: Vec::new(),
+            protocols: Protocols::default(),
+            lldp: lldp::Options::default(),
+        }
+    }
+
+    // Spawns the tasks of every service, only the first call does
+    pub fn start(&self, e: Arc<dyn asyn::Executor>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.ethernet.clone().start(e.clone());
+        self.arp.clone().start(e.clone());
+        self.ip.clone().start(e.clone());
+        if let Some(icmp) = self.icmp.as_ref() {
+            icmp.clone().start(e.clone());
+        }
+        if let Some(udp) = self.udp.as_ref() {
+            udp.clone().start(e.clone());
+        }
+        if let Some(dhcp) = self.dhcp.as_ref() {
+            if self.addressing == Addressing::Dhcp {
+                dhcp.clone().start(e.clone());
+            }
+        }
+        if let Some(tcp) = self.tcp.as_ref() {
+            tcp.clone().start(e.clone());
+        }
+        if let Some(wol) = self.wol.as_ref() {
+            wol.clone().start(e.clone());
+        }
+        if let Some(lldp) = self.lldp.as_ref() {
+            lldp.clone().start(e);
+        }
+    }
+
+    // Tells the neighbors we are leaving, lets queued frames go out and
+    // stops the interface. Sockets stay silent afterwards.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        if let Some(lldp) = self.lldp.as_ref() {
+            lldp.withdraw();
+        }
+        self.ethernet.flush(FLUSH_TIMEOUT).await;
+        self.ethernet.shutdown()?;
+        Ok(())
+    }
+
+    pub fn mac_address(&self) -> ethernet::MacAddress {
+        self.ethernet.mac_address()
+    }
+
+    // The permanent address for None. Wake-on-lan, lldp and dhcp keep
+    // the address they were started with.
+    pub fn set_mac_address(&self, address: Option<ethernet::MacAddress>) -> uefi::Result {
+        self.ethernet.set_mac_address(address)?;
+        self.arp.set_mac(self.ethernet.mac_address());
+        Ok(())
+    }
+
+    pub fn ethernet(&self) -> &Arc<ethernet::Service> {
+        &self.ethernet
+    }
+
+    pub fn interface(&self) -> Arc<ethernet::SimpleNetwork> {
+        self.ethernet.interface()
+    }
+
+    pub fn counters(&self) -> Arc<ethernet::Counters> {
+        self.ethernet.counters()
+    }
+
+    pub fn metrics(&self) -> Arc<metrics::Registry> {
+        self.ethernet.metrics()
+    }
+
+    pub fn capture(&self) -> Arc<Capture> {
+        self.ethernet.capture()
+    }
+
+    // The VLAN arp and ip run on, None for untagged
+    pub fn vlan(&self) -> Option<ethernet::Tag> {
+        self.vlan
+    }
+
+    pub fn arp(&self) -> &Arc<arp::Service> {
+        &self.arp
+    }
+
+    pub fn ip(&self) -> &Arc<ip::Service> {
+        &self.ip
+    }
+
+    pub fn icmp(&self) -> Option<&Arc<icmp::Service>> {
+        self.icmp.as_ref()
+    }
+
+    pub fn udp(&self) -> Option<&Arc<udp::Service>> {
+        self.udp.as_ref()
+    }
+
+    pub fn tcp(&self) -> Option<&Arc<tcp::Service>> {
+        self.tcp.as_ref()
+    }
+
+    // Present with udp
+    pub fn resolver(&self) -> Option<&Arc<dns::Resolver>> {
+        self.resolver.as_ref()
+    }
+
+    // Present with udp, keeps renewing a lease with Addressing::Dhcp or
+    // once started from the shell
+    pub fn dhcp(&self) -> Option<&Arc<dhcp::Client>> {
+        self.dhcp.as_ref()
+    }
+
+    pub fn wol(&self) -> Option<&Arc<wol::Service>> {
+        self.wol.as_ref()
+    }
+
+    pub fn lldp(&self) -> Option<&Arc<lldp::Service>> {
+        self.lldp.as_ref()
+    }
+}
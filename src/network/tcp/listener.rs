@@ -0,0 +1,43 @@
+extern crate alloc;
+
+use alloc::sync::Arc;
+use crossbeam_queue::ArrayQueue;
+
+use super::{Error, Packet, Service, Stream};
+use crate::asyn;
+
+// Accepts connections on a local port. Incoming SYNs wait in the queue
+// until accept is called.
+pub struct Listener {
+    port: u16,
+    queue: Arc<ArrayQueue<Packet>>,
+    service: Arc<Service>,
+}
+
+impl Listener {
+    pub(super) fn new(
+        port: u16,
+        queue: Arc<ArrayQueue<Packet>>,
+        service: Arc<Service>,
+    ) -> Listener {
+        Listener {
+            port,
+            queue,
+            service,
+        }
+    }
+
+    // Waits for the next connection and completes its handshake
+    pub async fn accept(&self) -> Result<Stream, Error> {
+        loop {
+            let syn = asyn::queue_pop(self.queue.clone()).await;
+            let key = (self.port, syn.ip.source_address(), syn.source_port());
+            // retransmitted SYNs of an accepted connection are skipped
+            let recv_queue = match self.service.register(key).await {
+                Some(q) => q,
+                None => continue,
+            };
+            return Stream::accept(self.service.clone(), key, recv_queue, &syn).await;
+        }
+    }
+}
@@ -0,0 +1,12 @@
+mod listener;
+mod packet;
+mod service;
+mod stream;
+
+pub use listener::Listener;
+pub use packet::{Flags, Packet};
+pub use service::Service;
+pub use stream::Stream;
+
+// Connections fail with the errors of the whole stack
+pub use super::Error;
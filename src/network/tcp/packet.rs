@@ -0,0 +1,187 @@
+use core::{fmt, ops::BitOr};
+
+use crate::network::ip;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Flags(pub u8);
+
+impl Flags {
+    pub const FIN: Flags = Flags(0x01);
+    pub const SYN: Flags = Flags(0x02);
+    pub const RST: Flags = Flags(0x04);
+    pub const PSH: Flags = Flags(0x08);
+    pub const ACK: Flags = Flags(0x10);
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Flags::FIN, "FIN"),
+            (Flags::SYN, "SYN"),
+            (Flags::RST, "RST"),
+            (Flags::PSH, "PSH"),
+            (Flags::ACK, "ACK"),
+        ];
+        let mut list = f.debug_set();
+        for (flag, name) in names.iter() {
+            if self.contains(*flag) {
+                list.entry(&format_args!("{}", name));
+            }
+        }
+        list.finish()
+    }
+}
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+pub struct Packet {
+    pub ip: ip::Packet,
+}
+
+impl fmt::Debug for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TCPPacket")
+            .field("source_port", &self.source_port())
+            .field("destination_port", &self.destination_port())
+            .field("sequence_number", &self.sequence_number())
+            .field("acknowledgment_number", &self.acknowledgment_number())
+            .field("flags", &self.flags())
+            .field("window", &self.window())
+            .field("data_len", &self.data().len())
+            .finish()
+    }
+}
+
+impl Packet {
+    pub fn new() -> Packet {
+        let mut p = Packet {
+            ip: ip::Packet::new(),
+        };
+        p.ip.set_protocol(ip::Protocol::TCP);
+        p.ip.set_size(20);
+        p.ip.data_mut().fill(0);
+        p.set_header_len(20);
+        p
+    }
+
+    pub fn source_port(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[0..2].try_into().unwrap())
+    }
+    pub fn set_source_port(&mut self, p: u16) {
+        self.ip.data_mut()[0..2].clone_from_slice(&p.to_be_bytes());
+    }
+    pub fn destination_port(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[2..4].try_into().unwrap())
+    }
+    pub fn set_destination_port(&mut self, p: u16) {
+        self.ip.data_mut()[2..4].clone_from_slice(&p.to_be_bytes());
+    }
+    pub fn sequence_number(&self) -> u32 {
+        u32::from_be_bytes(self.ip.data()[4..8].try_into().unwrap())
+    }
+    pub fn set_sequence_number(&mut self, n: u32) {
+        self.ip.data_mut()[4..8].clone_from_slice(&n.to_be_bytes());
+    }
+    pub fn acknowledgment_number(&self) -> u32 {
+        u32::from_be_bytes(self.ip.data()[8..12].try_into().unwrap())
+    }
+    pub fn set_acknowledgment_number(&mut self, n: u32) {
+        self.ip.data_mut()[8..12].clone_from_slice(&n.to_be_bytes());
+    }
+    pub fn header_len(&self) -> usize {
+        ((self.ip.data()[12] >> 4) * 4) as usize
+    }
+    pub fn set_header_len(&mut self, l: usize) {
+        self.ip.data_mut()[12] = ((l / 4) as u8) << 4;
+    }
+    pub fn flags(&self) -> Flags {
+        Flags(self.ip.data()[13] & 0x3f)
+    }
+    pub fn set_flags(&mut self, f: Flags) {
+        self.ip.data_mut()[13] = f.0;
+    }
+    pub fn window(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[14..16].try_into().unwrap())
+    }
+    pub fn set_window(&mut self, w: u16) {
+        self.ip.data_mut()[14..16].clone_from_slice(&w.to_be_bytes());
+    }
+    pub fn set_checksum(&mut self, s: u16) {
+        self.ip.data_mut()[16..18].clone_from_slice(&s.to_be_bytes());
+    }
+
+    // Maximum segment size announced in the options of a SYN
+    pub fn mss(&self) -> Option<u16> {
+        let options = &self.ip.data()[20..self.header_len()];
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                OPTION_END => break,
+                OPTION_NOP => i += 1,
+                kind => {
+                    let len = *options.get(i + 1)? as usize;
+                    if len < 2 {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        return Some(u16::from_be_bytes(
+                            options.get(i + 2..i + 4)?.try_into().unwrap(),
+                        ));
+                    }
+                    i += len;
+                }
+            }
+        }
+        None
+    }
+    // Has to be set before the data
+    pub fn set_mss(&mut self, mss: u16) {
+        self.ip.set_size(24);
+        self.set_header_len(24);
+        self.ip.data_mut()[20..22].clone_from_slice(&[OPTION_MSS, 4]);
+        self.ip.data_mut()[22..24].clone_from_slice(&mss.to_be_bytes());
+    }
+
+    pub fn data(&self) -> &[u8] {
+        let header_len = self.header_len().min(self.ip.data().len());
+        &self.ip.data()[header_len..]
+    }
+    pub fn set_data(&mut self, data: &[u8]) {
+        let header_len = self.header_len();
+        let size: u16 = (header_len + data.len()).try_into().unwrap();
+        self.ip.set_size(size);
+        self.ip.data_mut()[header_len..].clone_from_slice(data);
+    }
+
+    // Both addresses have to be set before calling this
+    pub fn compute_checksum(&self) -> u16 {
+        ip::pseudo_header_checksum(
+            &self.ip.source_address(),
+            &self.ip.destination_address(),
+            ip::Protocol::TCP,
+            self.ip.data(),
+        )
+    }
+
+    pub fn valid(&self) -> bool {
+        let len = self.ip.data().len();
+        len >= 20
+            && self.header_len() >= 20
+            && self.header_len() <= len
+            && self.compute_checksum() == 0
+    }
+}
@@ -0,0 +1,217 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
+use crossbeam_queue::ArrayQueue;
+use hashbrown::HashMap;
+use log::info;
+
+use super::{Error, Flags, Listener, Packet, Stream};
+use crate::{
+    asyn, metrics,
+    network::{ip, queues},
+};
+
+// Start of the dynamic port range used for outgoing connections
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+// Pending connections per listener
+const BACKLOG: usize = 8;
+
+const DROPPED: &str = "tcp_dropped_total";
+const DROPPED_HELP: &str = "Received TCP segments dropped, by reason.";
+
+// Local port, remote address and remote port of a connection
+pub type Key = (u16, ip::Address, u16);
+
+pub struct Service {
+    ip_socket: ip::Socket,
+    connections: asyn::Mutex<HashMap<Key, Arc<ArrayQueue<Packet>>>>,
+    listeners: asyn::Mutex<HashMap<u16, Arc<ArrayQueue<Packet>>>>,
+    next_ephemeral_port: AtomicU16,
+    invalid: metrics::Counter,
+    queue_full: metrics::Counter,
+    backlog_full: metrics::Counter,
+}
+
+impl Service {
+    pub async fn new(ip: Arc<ip::Service>) -> Service {
+        let metrics = ip.metrics();
+        Service {
+            ip_socket: ip.open(ip::Protocol::TCP).await,
+            connections: asyn::Mutex::new(HashMap::new()),
+            listeners: asyn::Mutex::new(HashMap::new()),
+            next_ephemeral_port: AtomicU16::new(EPHEMERAL_PORT_START),
+            invalid: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "invalid")]),
+            queue_full: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "queue_full")]),
+            backlog_full: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "backlog_full")]),
+        }
+    }
+
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.task_receive()));
+    }
+
+    pub async fn connect(
+        self: &Arc<Self>,
+        address: ip::Address,
+        port: u16,
+    ) -> Result<Stream, Error> {
+        let (key, recv_queue) = {
+            let mut connections = self.connections.lock().await;
+            connections.retain(|_, q| queues::is_open(q));
+            let mut local_port;
+            loop {
+                local_port = self.next_ephemeral_port.fetch_add(1, Ordering::Relaxed);
+                if local_port == 0 {
+                    self.next_ephemeral_port
+                        .store(EPHEMERAL_PORT_START, Ordering::Relaxed);
+                    continue;
+                }
+                if !connections.contains_key(&(local_port, address, port)) {
+                    break;
+                }
+            }
+            let key = (local_port, address, port);
+            let recv_queue = Arc::new(ArrayQueue::new(64));
+            connections.insert(key, recv_queue.clone());
+            (key, recv_queue)
+        };
+
+        Stream::connect(self.clone(), key, recv_queue).await
+    }
+
+    // Listens on a local port, failing when it already has a listener
+    pub async fn listen(self: &Arc<Self>, port: u16) -> Result<Listener, Error> {
+        let mut listeners = self.listeners.lock().await;
+        // a dropped listener frees its port
+        listeners.retain(|_, q| queues::is_open(q));
+        if listeners.contains_key(&port) {
+            return Err(Error::AddressInUse);
+        }
+        let queue = Arc::new(ArrayQueue::new(BACKLOG));
+        listeners.insert(port, queue.clone());
+        Ok(Listener::new(port, queue, self.clone()))
+    }
+
+    pub async fn connections(&self) -> Vec<Key> {
+        let connections = self.connections.lock().await;
+        connections
+            .iter()
+            .filter(|(_, q)| queues::is_open(q))
+            .map(|(k, _)| *k)
+            .collect()
+    }
+
+    pub async fn listeners(&self) -> Vec<u16> {
+        let listeners = self.listeners.lock().await;
+        listeners
+            .iter()
+            .filter(|(_, q)| queues::is_open(q))
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    // Registers an accepted connection, None when it already exists
+    pub(super) async fn register(&self, key: Key) -> Option<Arc<ArrayQueue<Packet>>> {
+        let mut connections = self.connections.lock().await;
+        connections.retain(|_, q| queues::is_open(q));
+        if connections.contains_key(&key) {
+            return None;
+        }
+        let recv_queue = Arc::new(ArrayQueue::new(64));
+        connections.insert(key, recv_queue.clone());
+        Some(recv_queue)
+    }
+
+    pub(super) async fn unregister(&self, key: &Key) {
+        self.connections.lock().await.remove(key);
+    }
+
+    pub(super) fn address(&self) -> ip::Address {
+        self.ip_socket.address()
+    }
+
+    // Segments that do not go out are retransmitted like lost ones, only
+    // a missing route is worth failing for
+    pub(super) async fn send(&self, mut p: Packet) -> Result<(), Error> {
+        p.ip.set_source_address(&self.address());
+        p.set_checksum(0);
+        p.set_checksum(p.compute_checksum());
+        match self.ip_socket.send(p.ip).await {
+            Err(Error::NoRoute) => Err(Error::NoRoute),
+            _ => Ok(()),
+        }
+    }
+
+    // Answers segments that belong to no connection (RFC 793, page 36)
+    async fn reset(&self, received: &Packet) {
+        let mut p = Packet::new();
+        p.set_source_port(received.destination_port());
+        p.set_destination_port(received.source_port());
+        if received.flags().contains(Flags::ACK) {
+            p.set_sequence_number(received.acknowledgment_number());
+            p.set_flags(Flags::RST);
+        } else {
+            let mut len = received.data().len() as u32;
+            if received.flags().contains(Flags::SYN) {
+                len += 1;
+            }
+            if received.flags().contains(Flags::FIN) {
+                len += 1;
+            }
+            p.set_acknowledgment_number(received.sequence_number().wrapping_add(len));
+            p.set_flags(Flags::RST | Flags::ACK);
+        }
+        p.ip.set_destination_address(&received.ip.source_address());
+        let _ = self.send(p).await;
+    }
+
+    async fn task_receive(self: Arc<Self>) {
+        loop {
+            let received = Packet {
+                ip: self.ip_socket.receive().await,
+            };
+
+            if !received.valid() {
+                self.invalid.inc();
+                continue;
+            }
+
+            let key = (
+                received.destination_port(),
+                received.ip.source_address(),
+                received.source_port(),
+            );
+            let connections = self.connections.lock().await;
+            match connections.get(&key).filter(|q| queues::is_open(q)) {
+                Some(q) => {
+                    if q.push(received).is_err() {
+                        self.queue_full.inc();
+                        info!("tcp receive queue full, dropping segment");
+                    }
+                }
+                None => {
+                    drop(connections);
+                    let flags = received.flags();
+                    if flags.contains(Flags::SYN) && !flags.contains(Flags::ACK) {
+                        let listeners = self.listeners.lock().await;
+                        let listener = listeners
+                            .get(&received.destination_port())
+                            .filter(|q| queues::is_open(q));
+                        if let Some(q) = listener {
+                            // a full backlog drops the SYN, the peer retries
+                            if q.push(received).is_err() {
+                                self.backlog_full.inc();
+                            }
+                            continue;
+                        }
+                    }
+                    if !flags.contains(Flags::RST) {
+                        self.reset(&received).await;
+                    }
+                }
+            }
+        }
+    }
+}
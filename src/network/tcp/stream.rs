@@ -0,0 +1,501 @@
+extern crate alloc;
+
+use alloc::{collections::VecDeque, sync::Arc};
+use crossbeam_queue::ArrayQueue;
+
+use super::{service::Key, Error, Flags, Packet, Service};
+use crate::{asyn, network::ip};
+
+// Largest segment that fits a single 1500 byte frame
+const MSS: usize = 1460;
+// Segment size assumed when the peer does not announce one
+const DEFAULT_MSS: usize = 536;
+const RECEIVE_BUFFER: usize = 16384;
+// Upper bound on unacknowledged data regardless of the peer window
+const MAX_IN_FLIGHT: usize = 8 * MSS;
+
+const INITIAL_RTO: f64 = 1.0;
+const MAX_RTO: f64 = 16.0;
+const RETRIES: usize = 6;
+// How long a read waits for the peer before giving up
+const IDLE_TIMEOUT: f64 = 60.0;
+// How long close waits for the peer to finish its side
+const CLOSE_TIMEOUT: f64 = 2.0;
+
+// A connected stream, used by a single task at a time. Segments are
+// processed while reading or writing, there is no background task per
+// connection.
+pub struct Stream {
+    service: Arc<Service>,
+    key: Key,
+    recv_queue: Arc<ArrayQueue<Packet>>,
+
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    mss: usize,
+    rcv_nxt: u32,
+
+    // Sent but not yet acknowledged bytes, starting at snd_una
+    unacked: VecDeque<u8>,
+    received: VecDeque<u8>,
+    fin_sent: bool,
+    fin_received: bool,
+    closed: bool,
+}
+
+impl Stream {
+    pub(super) async fn connect(
+        service: Arc<Service>,
+        key: Key,
+        recv_queue: Arc<ArrayQueue<Packet>>,
+    ) -> Result<Stream, Error> {
+        let iss = Self::initial_sequence_number();
+        let mut s = Stream::new(service, key, recv_queue, iss);
+
+        let mut rto = INITIAL_RTO;
+        for _ in 0..RETRIES {
+            let mut syn = s.segment(Flags::SYN, iss);
+            syn.set_mss(MSS as u16);
+            syn.set_data(&[]);
+            if let Err(e) = s.service.send(syn).await {
+                s.service.unregister(&s.key).await;
+                return Err(e);
+            }
+
+            let start = asyn::timestamp();
+            loop {
+                let remaining = rto - asyn::elapsed(start);
+                if remaining <= 0.0 {
+                    break;
+                }
+                let p = match asyn::queue_pop_timeout(s.recv_queue.clone(), remaining).await {
+                    Some(p) => p,
+                    None => break,
+                };
+                if !p.flags().contains(Flags::ACK) || p.acknowledgment_number() != s.snd_nxt {
+                    continue;
+                }
+                if p.flags().contains(Flags::RST) {
+                    s.service.unregister(&s.key).await;
+                    return Err(Error::Refused);
+                }
+                if p.flags().contains(Flags::SYN) {
+                    s.snd_una = s.snd_nxt;
+                    s.snd_wnd = p.window() as usize;
+                    s.mss = p.mss().map_or(DEFAULT_MSS, |m| m as usize).min(MSS);
+                    s.rcv_nxt = p.sequence_number().wrapping_add(1);
+                    s.send_ack().await;
+                    return Ok(s);
+                }
+            }
+            rto = (rto * 2.0).min(MAX_RTO);
+        }
+
+        s.service.unregister(&s.key).await;
+        Err(Error::Timeout)
+    }
+
+    // Completes the handshake for a SYN received by a listener
+    pub(super) async fn accept(
+        service: Arc<Service>,
+        key: Key,
+        recv_queue: Arc<ArrayQueue<Packet>>,
+        syn: &Packet,
+    ) -> Result<Stream, Error> {
+        let iss = Self::initial_sequence_number();
+        let mut s = Stream::new(service, key, recv_queue, iss);
+        s.snd_wnd = syn.window() as usize;
+        s.mss = syn.mss().map_or(DEFAULT_MSS, |m| m as usize).min(MSS);
+        s.rcv_nxt = syn.sequence_number().wrapping_add(1);
+
+        let mut rto = INITIAL_RTO;
+        for _ in 0..RETRIES {
+            let mut syn_ack = s.segment(Flags::SYN | Flags::ACK, iss);
+            syn_ack.set_mss(MSS as u16);
+            syn_ack.set_data(&[]);
+            if let Err(e) = s.service.send(syn_ack).await {
+                s.service.unregister(&s.key).await;
+                return Err(e);
+            }
+
+            let start = asyn::timestamp();
+            loop {
+                let remaining = rto - asyn::elapsed(start);
+                if remaining <= 0.0 {
+                    break;
+                }
+                let p = match asyn::queue_pop_timeout(s.recv_queue.clone(), remaining).await {
+                    Some(p) => p,
+                    None => break,
+                };
+                if p.flags().contains(Flags::RST) {
+                    s.service.unregister(&s.key).await;
+                    return Err(Error::Reset);
+                }
+                // the SYN was retransmitted, answer it again
+                if p.flags().contains(Flags::SYN) {
+                    break;
+                }
+                if !p.flags().contains(Flags::ACK) || p.acknowledgment_number() != s.snd_nxt {
+                    continue;
+                }
+                s.snd_una = s.snd_nxt;
+                // the ack may already carry data
+                s.handle(p).await?;
+                return Ok(s);
+            }
+            rto = (rto * 2.0).min(MAX_RTO);
+        }
+
+        s.service.unregister(&s.key).await;
+        Err(Error::Timeout)
+    }
+
+    fn new(
+        service: Arc<Service>,
+        key: Key,
+        recv_queue: Arc<ArrayQueue<Packet>>,
+        iss: u32,
+    ) -> Stream {
+        Stream {
+            service,
+            key,
+            recv_queue,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            rcv_nxt: 0,
+            unacked: VecDeque::new(),
+            received: VecDeque::new(),
+            fin_sent: false,
+            fin_received: false,
+            closed: false,
+        }
+    }
+
+    fn initial_sequence_number() -> u32 {
+        (asyn::timestamp() / 4000) as u32
+    }
+
+    pub fn remote_address(&self) -> ip::Address {
+        self.key.1
+    }
+    pub fn remote_port(&self) -> u16 {
+        self.key.2
+    }
+
+    // Reads at least one byte, 0 means the peer closed its side
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            if !self.received.is_empty() {
+                let window_before = self.window();
+                let n = buf.len().min(self.received.len());
+                for (i, b) in self.received.drain(..n).enumerate() {
+                    buf[i] = b;
+                }
+                // let the peer know once a closed window opens again
+                if window_before < self.mss && self.window() >= self.mss {
+                    self.send_ack().await;
+                }
+                return Ok(n);
+            }
+            if self.fin_received {
+                return Ok(0);
+            }
+            if self.closed {
+                return Err(Error::Closed);
+            }
+            if !self.process(IDLE_TIMEOUT).await? {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    // Writes all of data, returning once the peer acknowledged it
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.fin_sent || self.closed {
+            return Err(Error::Closed);
+        }
+
+        let mut offset = 0;
+        let mut rto = INITIAL_RTO;
+        let mut retries = 0;
+        loop {
+            let window = self.snd_wnd.min(MAX_IN_FLIGHT);
+            while offset < data.len() && self.unacked.len() < window {
+                let n = self
+                    .mss
+                    .min(window - self.unacked.len())
+                    .min(data.len() - offset);
+                let chunk = &data[offset..offset + n];
+                let mut p = self.segment(Flags::ACK | Flags::PSH, self.snd_nxt);
+                p.set_data(chunk);
+                self.service.send(p).await?;
+
+                self.unacked.extend(chunk.iter());
+                self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+                offset += n;
+            }
+
+            if offset == data.len() && self.unacked.is_empty() {
+                return Ok(());
+            }
+
+            if self.process(rto).await? {
+                rto = INITIAL_RTO;
+                retries = 0;
+                continue;
+            }
+
+            retries += 1;
+            if retries > RETRIES {
+                return Err(Error::Timeout);
+            }
+            rto = (rto * 2.0).min(MAX_RTO);
+            if self.unacked.is_empty() {
+                // zero window probe
+                self.snd_wnd = 1;
+            } else {
+                self.retransmit().await;
+            }
+        }
+    }
+
+    // Sends FIN and waits for the peer to acknowledge it and to close its
+    // own side, then releases the connection
+    pub async fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        if !self.fin_sent {
+            let p = self.segment(Flags::FIN | Flags::ACK, self.snd_nxt);
+            let _ = self.service.send(p).await;
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+
+        let start = asyn::timestamp();
+        while self.snd_una != self.snd_nxt || !self.fin_received {
+            let remaining = CLOSE_TIMEOUT - asyn::elapsed(start);
+            if remaining <= 0.0 {
+                break;
+            }
+            match self.process(remaining.min(INITIAL_RTO)).await {
+                Ok(true) => {}
+                Ok(false) => self.retransmit().await,
+                Err(_) => break,
+            }
+        }
+
+        self.closed = true;
+        self.service.unregister(&self.key).await;
+    }
+
+    fn window(&self) -> usize {
+        RECEIVE_BUFFER - self.received.len()
+    }
+
+    fn segment(&self, flags: Flags, sequence: u32) -> Packet {
+        let mut p = Packet::new();
+        p.set_source_port(self.key.0);
+        p.set_destination_port(self.key.2);
+        p.set_sequence_number(sequence);
+        if flags.contains(Flags::ACK) {
+            p.set_acknowledgment_number(self.rcv_nxt);
+        }
+        p.set_flags(flags);
+        p.set_window(self.window().min(u16::MAX as usize) as u16);
+        p.ip.set_destination_address(&self.key.1);
+        p
+    }
+
+    async fn send_ack(&self) {
+        let p = self.segment(Flags::ACK, self.snd_nxt);
+        let _ = self.service.send(p).await;
+    }
+
+    // Resends the oldest unacknowledged segment, or the FIN
+    async fn retransmit(&self) {
+        if self.unacked.is_empty() {
+            if self.fin_sent && self.snd_una != self.snd_nxt {
+                let p = self.segment(Flags::FIN | Flags::ACK, self.snd_una);
+                let _ = self.service.send(p).await;
+            }
+            return;
+        }
+        let n = self.mss.min(self.unacked.len());
+        let mut chunk = [0; MSS];
+        for (i, b) in self.unacked.range(..n).enumerate() {
+            chunk[i] = *b;
+        }
+        let mut p = self.segment(Flags::ACK | Flags::PSH, self.snd_una);
+        p.set_data(&chunk[..n]);
+        let _ = self.service.send(p).await;
+    }
+
+    // Handles one incoming segment, false when none arrived in time
+    async fn process(&mut self, timeout: f64) -> Result<bool, Error> {
+        let p = match asyn::queue_pop_timeout(self.recv_queue.clone(), timeout).await {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        self.handle(p).await?;
+        Ok(true)
+    }
+
+    async fn handle(&mut self, p: Packet) -> Result<(), Error> {
+        let flags = p.flags();
+
+        if flags.contains(Flags::RST) {
+            if p.sequence_number() == self.rcv_nxt {
+                self.closed = true;
+                self.service.unregister(&self.key).await;
+                return Err(Error::Reset);
+            }
+            return Ok(());
+        }
+
+        if flags.contains(Flags::ACK) {
+            let acked = p.acknowledgment_number().wrapping_sub(self.snd_una) as usize;
+            let outstanding = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            if acked > 0 && acked <= outstanding {
+                let n = acked.min(self.unacked.len());
+                self.unacked.drain(..n);
+                self.snd_una = p.acknowledgment_number();
+            }
+            self.snd_wnd = p.window() as usize;
+        }
+
+        let data = p.data();
+        // a retransmitted SYN means our ack of it got lost
+        let mut ack = flags.contains(Flags::SYN);
+        if !data.is_empty() || flags.contains(Flags::FIN) {
+            if p.sequence_number() == self.rcv_nxt && !self.fin_received {
+                let n = data.len().min(self.window());
+                self.received.extend(data[..n].iter());
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(n as u32);
+                if flags.contains(Flags::FIN) && n == data.len() {
+                    self.fin_received = true;
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                }
+            }
+            // out of order segments are dropped and the expected sequence
+            // number acknowledged again
+            ack = true;
+        }
+        if ack {
+            self.send_ack().await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    use crate::network::{
+        ethernet::MacAddress,
+        sim::{Host, Link, Simulator},
+    };
+
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 2]);
+    const PORT: u16 = 7;
+
+    // A host on each of two switches, joined by the given uplink
+    fn hosts(seed: u64, uplink: Link) -> (Simulator, Host, Host) {
+        let sim = Simulator::new(seed);
+        let (left, right) = (sim.switch(), sim.switch());
+        sim.connect(left, right, uplink);
+        let a = MacAddress([2, 0, 0, 0, 0, 1]);
+        let b = MacAddress([2, 0, 0, 0, 0, 2]);
+        let client = sim.host(left, a, ip::Address([10, 0, 0, 1]), Link::default());
+        let server = sim.host(right, b, SERVER, Link::default());
+        (sim, client, server)
+    }
+
+    // Sends back everything of one connection until the peer closes
+    async fn echo(service: Arc<Service>) -> usize {
+        let listener = service.listen(PORT).await.unwrap();
+        let mut stream = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        let mut total = 0;
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                stream.close().await;
+                return total;
+            }
+            stream.write(&buf[..n]).await.unwrap();
+            total += n;
+        }
+    }
+
+    // Writes data a piece at a time, reading each piece back so neither
+    // receive buffer fills up, and closes
+    async fn round_trip(service: Arc<Service>, data: Vec<u8>) -> Vec<u8> {
+        let mut stream = service.connect(SERVER, PORT).await.unwrap();
+        let mut echoed = Vec::new();
+        let mut buf = [0; 4096];
+        for piece in data.chunks(buf.len()) {
+            stream.write(piece).await.unwrap();
+            let end = echoed.len() + piece.len();
+            while echoed.len() < end {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0, "closed after {} bytes", echoed.len());
+                echoed.extend_from_slice(&buf[..n]);
+            }
+        }
+        stream.close().await;
+        echoed
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn echo_over_a_clean_link() {
+        let (sim, client, server) = hosts(1, Link::default());
+        let echoed = sim.spawn(echo(server.tcp.clone()));
+        let received = sim.block_on(round_trip(client.tcp.clone(), data(64 * 1024)));
+        assert!(received == data(64 * 1024));
+        assert_eq!(sim.join(echoed), 64 * 1024);
+        // both ends released the connection
+        let service = server.tcp.clone();
+        assert!(sim
+            .block_on(async move { service.connections().await })
+            .is_empty());
+        let service = client.tcp.clone();
+        assert!(sim
+            .block_on(async move { service.connections().await })
+            .is_empty());
+    }
+
+    #[test]
+    fn retransmits_over_a_lossy_reordering_link() {
+        let lossy = Link {
+            latency: 0.002,
+            jitter: 0.003,
+            loss: 0.05,
+            duplicate: 0.05,
+        };
+        let (sim, client, server) = hosts(5, lossy);
+        let echoed = sim.spawn(echo(server.tcp.clone()));
+        let received = sim.block_on(round_trip(client.tcp.clone(), data(16 * 1024)));
+        assert!(received == data(16 * 1024));
+        assert_eq!(sim.join(echoed), 16 * 1024);
+    }
+
+    #[test]
+    fn closed_port_refuses() {
+        let (sim, client, _server) = hosts(1, Link::default());
+        let service = client.tcp.clone();
+        let connected = sim.block_on(async move { service.connect(SERVER, PORT).await });
+        assert!(matches!(connected, Err(Error::Refused)));
+    }
+}
@@ -0,0 +1,554 @@
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+use uefi::Status;
+
+use super::message::{self, ErrorCode, Message, Opcode};
+use crate::{
+    asyn,
+    network::{self, ip, udp, Sink, Source},
+};
+
+pub const PORT: u16 = 69;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+// Largest block that fits a single 1500 byte frame, since ip fragments
+// are not reassembled
+const MAX_BLOCK_SIZE: u16 = 1468;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    // Requested blksize, 512 avoids negotiating it
+    pub block_size: u16,
+    // Retransmission timeout in seconds, also requested from the server
+    pub timeout: u8,
+    pub retries: usize,
+    // Ask for (or announce) the transfer size
+    pub transfer_size: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            block_size: 1428,
+            timeout: 1,
+            retries: 5,
+            transfer_size: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Timeout,
+    Remote(ErrorCode, String),
+    Io(Status),
+    Protocol,
+    Network(network::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => f.write_str("timed out"),
+            Error::Remote(code, message) => write!(f, "server error {:?}: {}", code, message),
+            Error::Io(status) => write!(f, "i/o error {:?}", status),
+            Error::Protocol => f.write_str("unexpected reply"),
+            Error::Network(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Remote end of a transfer, the port (transfer id) is learned from the
+// first reply
+struct Peer {
+    address: ip::Address,
+    port: Option<u16>,
+    block_size: usize,
+    timeout: f64,
+}
+
+pub struct Client {
+    socket: udp::Socket,
+    options: Options,
+}
+
+impl Client {
+    pub fn new(socket: udp::Socket, options: Options) -> Client {
+        Client { socket, options }
+    }
+
+    // Reads a file from the server into the sink, returning its size
+    pub async fn read(
+        &self,
+        server: ip::Address,
+        filename: &str,
+        sink: &mut dyn Sink,
+    ) -> Result<u64, Error> {
+        let mut peer = self.peer(server);
+        let mut last = message::request(
+            Opcode::READ_REQUEST,
+            filename,
+            &self.request_options(Some(0)),
+        );
+        let mut expected: u16 = 1;
+        let mut total: u64 = 0;
+        let mut retries = 0;
+
+        loop {
+            self.send(&peer, &last).await?;
+
+            let received = match self.receive(&mut peer).await {
+                Some(p) => p,
+                None => {
+                    retries += 1;
+                    if retries > self.options.retries {
+                        return Err(Error::Timeout);
+                    }
+                    continue;
+                }
+            };
+
+            match Message::parse(received.data()) {
+                Some(Message::Error { code, message }) => {
+                    return Err(Error::Remote(code, message.into()))
+                }
+                Some(Message::OptionAck { options }) if expected == 1 => {
+                    for (name, value) in message::options(options) {
+                        match (name.to_ascii_lowercase().as_str(), value.parse::<u64>()) {
+                            ("blksize", Ok(v)) if v >= 8 && v <= self.block_size() as u64 => {
+                                peer.block_size = v as usize
+                            }
+                            ("tsize", Ok(v)) => sink.size_hint(v),
+                            ("timeout", Ok(v)) if v > 0 => peer.timeout = v as f64,
+                            _ => return Err(self.abort(&peer, Error::Protocol).await),
+                        }
+                    }
+                    last = message::ack(0);
+                    retries = 0;
+                }
+                Some(Message::Data { block, data }) if block == expected => {
+                    if let Err(s) = sink.write(data) {
+                        return Err(self.abort(&peer, Error::Io(s)).await);
+                    }
+                    total += data.len() as u64;
+                    last = message::ack(block);
+                    expected = expected.wrapping_add(1);
+                    retries = 0;
+
+                    if data.len() < peer.block_size {
+                        // the server resends its last block if this is lost
+                        let _ = self.send(&peer, &last).await;
+                        return Ok(total);
+                    }
+                }
+                // duplicates are answered by resending the last ack
+                _ => {}
+            }
+        }
+    }
+
+    // Writes the whole source to a file on the server, returning its size
+    pub async fn write(
+        &self,
+        server: ip::Address,
+        filename: &str,
+        source: &mut dyn Source,
+    ) -> Result<u64, Error> {
+        let mut peer = self.peer(server);
+        let size = if self.options.transfer_size {
+            source.size()
+        } else {
+            None
+        };
+        let mut last =
+            message::request(Opcode::WRITE_REQUEST, filename, &self.request_options(size));
+        let mut buf = vec![0; MAX_BLOCK_SIZE as usize];
+        let mut started = false;
+        let mut finished = false;
+        let mut block: u16 = 0;
+        let mut total: u64 = 0;
+        let mut retries = 0;
+        let mut resend = true;
+
+        loop {
+            if resend {
+                self.send(&peer, &last).await?;
+            }
+            resend = true;
+
+            let received = match self.receive(&mut peer).await {
+                Some(p) => p,
+                None => {
+                    retries += 1;
+                    if retries > self.options.retries {
+                        return Err(Error::Timeout);
+                    }
+                    continue;
+                }
+            };
+
+            match Message::parse(received.data()) {
+                Some(Message::Error { code, message }) => {
+                    return Err(Error::Remote(code, message.into()))
+                }
+                Some(Message::OptionAck { options }) if !started => {
+                    for (name, value) in message::options(options) {
+                        match (name.to_ascii_lowercase().as_str(), value.parse::<u64>()) {
+                            ("blksize", Ok(v)) if v >= 8 && v <= self.block_size() as u64 => {
+                                peer.block_size = v as usize
+                            }
+                            ("tsize", Ok(_)) => {}
+                            ("timeout", Ok(v)) if v > 0 => peer.timeout = v as f64,
+                            _ => return Err(self.abort(&peer, Error::Protocol).await),
+                        }
+                    }
+                    started = true;
+                }
+                Some(Message::Ack { block: 0 }) if !started => started = true,
+                Some(Message::Ack { block: b }) if started && b == block => {}
+                // a duplicate ack must not trigger a retransmission
+                // (sorcerer's apprentice syndrome)
+                _ => {
+                    resend = false;
+                    continue;
+                }
+            }
+
+            if finished {
+                return Ok(total);
+            }
+
+            let n = match source.read(&mut buf[..peer.block_size]) {
+                Ok(n) => n,
+                Err(s) => return Err(self.abort(&peer, Error::Io(s)).await),
+            };
+            block = block.wrapping_add(1);
+            total += n as u64;
+            finished = n < peer.block_size;
+            last = message::data(block, &buf[..n]);
+            retries = 0;
+        }
+    }
+
+    fn peer(&self, address: ip::Address) -> Peer {
+        Peer {
+            address,
+            port: None,
+            block_size: DEFAULT_BLOCK_SIZE,
+            timeout: self.options.timeout as f64,
+        }
+    }
+
+    // The block size asked for, what fits in a packet at most
+    fn block_size(&self) -> u16 {
+        self.options.block_size.min(MAX_BLOCK_SIZE)
+    }
+
+    fn request_options(&self, size: Option<u64>) -> Vec<(&'static str, u64)> {
+        let mut options = Vec::new();
+        let block_size = self.block_size();
+        if block_size as usize != DEFAULT_BLOCK_SIZE {
+            options.push(("blksize", block_size as u64));
+        }
+        options.push(("timeout", self.options.timeout as u64));
+        if let (true, Some(s)) = (self.options.transfer_size, size) {
+            options.push(("tsize", s));
+        }
+        options
+    }
+
+    // A packet that does not go out is retried like a lost one
+    async fn send(&self, peer: &Peer, data: &[u8]) -> Result<(), Error> {
+        match self
+            .socket
+            .send(peer.address, peer.port.unwrap_or(PORT), data)
+            .await
+        {
+            Err(e) if !e.is_transient() => Err(Error::Network(e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Tells the server the transfer is over because of a local error
+    async fn abort(&self, peer: &Peer, e: Error) -> Error {
+        let (code, text) = match e {
+            Error::Io(_) => (ErrorCode::DISK_FULL, "local i/o error"),
+            _ => (ErrorCode::OPTION_NEGOTIATION, "unexpected option"),
+        };
+        let _ = self.send(peer, &message::error(code, text)).await;
+        e
+    }
+
+    // Waits for the next packet of this transfer, rejecting strays
+    async fn receive(&self, peer: &mut Peer) -> Option<udp::Packet> {
+        let start = asyn::timestamp();
+        loop {
+            let remaining = peer.timeout - asyn::elapsed(start);
+            if remaining <= 0.0 {
+                return None;
+            }
+            let received = self.socket.receive(remaining).await.ok()?;
+            if received.ip.source_address() != peer.address {
+                continue;
+            }
+            match peer.port {
+                None => peer.port = Some(received.source_port()),
+                Some(p) if p != received.source_port() => {
+                    let e = message::error(ErrorCode::UNKNOWN_TRANSFER_ID, "unknown transfer id");
+                    let _ = self
+                        .socket
+                        .send(peer.address, received.source_port(), &e)
+                        .await;
+                    continue;
+                }
+                Some(_) => {}
+            }
+            return Some(received);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{
+        ethernet::MacAddress,
+        sim::{Host, Link, Simulator},
+    };
+
+    const CLIENT: ip::Address = ip::Address([10, 0, 0, 1]);
+    const SERVER: ip::Address = ip::Address([10, 0, 0, 2]);
+    // Frames arrive on the next round, long transfers stay quick
+    const LINK: Link = Link {
+        latency: 0.0,
+        jitter: 0.0,
+        loss: 0.0,
+        duplicate: 0.0,
+    };
+
+    fn hosts() -> (Simulator, Host, Host) {
+        let sim = Simulator::new(1);
+        let switch = sim.switch();
+        let client = sim.host(switch, MacAddress([2, 0, 0, 0, 0, 1]), CLIENT, LINK);
+        let server = sim.host(switch, MacAddress([2, 0, 0, 0, 0, 2]), SERVER, LINK);
+        (sim, client, server)
+    }
+
+    fn options(block_size: u16) -> Options {
+        Options {
+            block_size,
+            ..Options::default()
+        }
+    }
+
+    fn option_ack(options: &[(&str, u64)]) -> Vec<u8> {
+        let mut r = message::request(Opcode::OPTION_ACK, "", options);
+        // request() puts a filename and mode first
+        r.drain(2..2 + "\0octet\0".len());
+        r
+    }
+
+    // Sends a packet until the peer answers with something else than a
+    // copy of what it sent before, returning the answer
+    async fn exchange(socket: &udp::Socket, port: u16, packet: &[u8]) -> udp::Packet {
+        loop {
+            socket.send(CLIENT, port, packet).await.unwrap();
+            if let Ok(p) = socket.receive(0.5).await {
+                return p;
+            }
+        }
+    }
+
+    // A server for one read request: the request, answered with the
+    // option ack if any, then the file in blocks
+    async fn serve_read(
+        socket: udp::Socket,
+        file: Vec<u8>,
+        ack: Option<Vec<u8>>,
+        block_size: usize,
+    ) -> (Vec<u8>, Option<udp::Packet>) {
+        let request = socket.receive(10.0).await.unwrap();
+        let port = request.source_port();
+        if let Some(ack) = ack {
+            let reply = exchange(&socket, port, &ack).await;
+            if !matches!(
+                Message::parse(reply.data()),
+                Some(Message::Ack { block: 0 })
+            ) {
+                return (request.data().to_vec(), Some(reply));
+            }
+        }
+        let mut blocks: Vec<&[u8]> = file.chunks(block_size).collect();
+        if file.len().is_multiple_of(block_size) {
+            blocks.push(&[]);
+        }
+        let mut block: u16 = 0;
+        for data in blocks {
+            block = block.wrapping_add(1);
+            loop {
+                let reply = exchange(&socket, port, &message::data(block, data)).await;
+                if let Some(Message::Ack { block: b }) = Message::parse(reply.data()) {
+                    if b == block {
+                        break;
+                    }
+                }
+            }
+        }
+        (request.data().to_vec(), None)
+    }
+
+    #[test]
+    fn read_negotiates_and_rolls_over_the_block_number() {
+        let (sim, client, server) = hosts();
+        // more than 65535 blocks of 8 bytes
+        let file: Vec<u8> = (0..65536 * 8 + 3).map(|i| (i % 251) as u8).collect();
+        let socket = sim.block_on(async move { server.udp.open(PORT).await.unwrap() });
+        let ack = option_ack(&[("blksize", 8), ("tsize", file.len() as u64)]);
+        let server = sim.spawn(serve_read(socket, file.clone(), Some(ack), 8));
+
+        let received = sim.block_on(async move {
+            let client = Client::new(client.udp.open(0).await.unwrap(), options(16));
+            let mut data = Vec::new();
+            client
+                .read(SERVER, "boot/kernel", &mut data)
+                .await
+                .map(|n| (n, data))
+        });
+        let (size, data) = received.unwrap();
+        assert_eq!(size, file.len() as u64);
+        assert!(data == file);
+        let (request, _) = sim.join(server);
+        let fields: Vec<(&str, &str)> = message::options(&request[2..]).collect();
+        assert_eq!(
+            fields,
+            [
+                ("boot/kernel", "octet"),
+                ("blksize", "16"),
+                ("timeout", "1"),
+                ("tsize", "0")
+            ]
+        );
+    }
+
+    #[test]
+    fn read_rejects_a_larger_block_size() {
+        let (sim, client, server) = hosts();
+        let socket = sim.block_on(async move { server.udp.open(PORT).await.unwrap() });
+        let ack = option_ack(&[("blksize", 32)]);
+        let server = sim.spawn(serve_read(socket, Vec::new(), Some(ack), 32));
+
+        let received = sim.block_on(async move {
+            let client = Client::new(client.udp.open(0).await.unwrap(), options(16));
+            client.read(SERVER, "kernel", &mut Vec::new()).await
+        });
+        assert!(matches!(received, Err(Error::Protocol)));
+        let (_, reply) = sim.join(server);
+        let reply = reply.unwrap();
+        assert!(matches!(
+            Message::parse(reply.data()),
+            Some(Message::Error {
+                code: ErrorCode::OPTION_NEGOTIATION,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn read_does_not_reserve_a_huge_transfer_size() {
+        let (sim, client, server) = hosts();
+        let socket = sim.block_on(async move { server.udp.open(PORT).await.unwrap() });
+        let ack = option_ack(&[("tsize", 1 << 40)]);
+        let server = sim.spawn(serve_read(socket, Vec::from([1, 2, 3]), Some(ack), 512));
+
+        let received = sim.block_on(async move {
+            let client = Client::new(client.udp.open(0).await.unwrap(), Options::default());
+            let mut data = Vec::new();
+            client.read(SERVER, "kernel", &mut data).await.map(|_| data)
+        });
+        let data = received.unwrap();
+        assert_eq!(data, [1, 2, 3]);
+        assert!(data.capacity() < 1 << 20);
+        sim.join(server);
+    }
+
+    // A block size past what fits in a packet is asked for as the largest
+    // that does, a server agreeing to more is refused
+    #[test]
+    fn write_caps_the_block_size() {
+        let (sim, client, server) = hosts();
+        let socket = sim.block_on(async move { server.udp.open(PORT).await.unwrap() });
+        let replied = sim.spawn(async move {
+            let request = socket.receive(10.0).await.unwrap();
+            let port = request.source_port();
+            let reply = exchange(&socket, port, &option_ack(&[("blksize", 4096)])).await;
+            (request.data().to_vec(), reply)
+        });
+
+        let sent = sim.block_on(async move {
+            let client = Client::new(client.udp.open(0).await.unwrap(), options(4096));
+            client
+                .write(SERVER, "log.txt", &mut [0u8; 8192].as_slice())
+                .await
+        });
+        assert!(matches!(sent, Err(Error::Protocol)));
+        let (request, reply) = sim.join(replied);
+        assert!(message::options(&request[2..]).any(|o| o == ("blksize", "1468")));
+        assert!(matches!(
+            Message::parse(reply.data()),
+            Some(Message::Error {
+                code: ErrorCode::OPTION_NEGOTIATION,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn write_sends_blocks_of_the_negotiated_size() {
+        let (sim, client, server) = hosts();
+        let socket = sim.block_on(async move { server.udp.open(PORT).await.unwrap() });
+        let uploaded = sim.spawn(async move {
+            let request = socket.receive(10.0).await.unwrap();
+            let port = request.source_port();
+            let mut reply = exchange(&socket, port, &option_ack(&[("blksize", 8)])).await;
+            let mut file = Vec::new();
+            let mut sizes = Vec::new();
+            loop {
+                let Some(Message::Data { block, data }) = Message::parse(reply.data()) else {
+                    panic!("not a data block");
+                };
+                file.extend_from_slice(data);
+                sizes.push(data.len());
+                let done = data.len() < 8;
+                if done {
+                    socket
+                        .send(CLIENT, port, &message::ack(block))
+                        .await
+                        .unwrap();
+                    break;
+                }
+                reply = exchange(&socket, port, &message::ack(block)).await;
+            }
+            (request.data().to_vec(), file, sizes)
+        });
+
+        let content: Vec<u8> = (0..20).collect();
+        let source = content.clone();
+        let sent = sim.block_on(async move {
+            let client = Client::new(client.udp.open(0).await.unwrap(), options(16));
+            client
+                .write(SERVER, "log.txt", &mut source.as_slice())
+                .await
+        });
+        assert_eq!(sent.unwrap(), 20);
+        let (request, file, sizes) = sim.join(uploaded);
+        assert_eq!(
+            Opcode(u16::from_be_bytes([request[0], request[1]])),
+            Opcode::WRITE_REQUEST
+        );
+        assert!(message::options(&request[2..]).any(|o| o == ("tsize", "20")));
+        assert_eq!(file, content);
+        assert_eq!(sizes, [8, 8, 4]);
+    }
+}
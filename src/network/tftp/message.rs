@@ -0,0 +1,116 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use uefi_raw::newtype_enum;
+
+newtype_enum! {
+    pub enum Opcode: u16 => {
+        READ_REQUEST = 1,
+        WRITE_REQUEST = 2,
+        DATA = 3,
+        ACK = 4,
+        ERROR = 5,
+        OPTION_ACK = 6,
+    }
+}
+
+newtype_enum! {
+    pub enum ErrorCode: u16 => {
+        NOT_DEFINED = 0,
+        FILE_NOT_FOUND = 1,
+        ACCESS_VIOLATION = 2,
+        DISK_FULL = 3,
+        ILLEGAL_OPERATION = 4,
+        UNKNOWN_TRANSFER_ID = 5,
+        FILE_EXISTS = 6,
+        NO_SUCH_USER = 7,
+        OPTION_NEGOTIATION = 8,
+    }
+}
+
+#[derive(Debug)]
+pub enum Message<'a> {
+    Data { block: u16, data: &'a [u8] },
+    Ack { block: u16 },
+    Error { code: ErrorCode, message: &'a str },
+    // Raw option/value pairs, see options()
+    OptionAck { options: &'a [u8] },
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Message<'a>> {
+        let opcode = Opcode(u16::from_be_bytes(data.get(0..2)?.try_into().unwrap()));
+        let field = || Some(u16::from_be_bytes(data.get(2..4)?.try_into().unwrap()));
+        match opcode {
+            Opcode::DATA => Some(Message::Data {
+                block: field()?,
+                data: &data[4..],
+            }),
+            Opcode::ACK => Some(Message::Ack { block: field()? }),
+            Opcode::ERROR => {
+                let message = data[4..].split(|c| *c == 0).next().unwrap_or(&[]);
+                Some(Message::Error {
+                    code: ErrorCode(field()?),
+                    message: core::str::from_utf8(message).unwrap_or(""),
+                })
+            }
+            Opcode::OPTION_ACK => Some(Message::OptionAck {
+                options: &data[2..],
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Iterates over the null terminated option/value pairs of a request or
+// option acknowledgement
+pub fn options(data: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    let mut fields = data
+        .split(|c| *c == 0)
+        .map(|f| core::str::from_utf8(f).unwrap_or(""));
+    core::iter::from_fn(move || {
+        let name = fields.next().filter(|n| !n.is_empty())?;
+        Some((name, fields.next()?))
+    })
+}
+
+pub fn request(opcode: Opcode, filename: &str, options: &[(&str, u64)]) -> Vec<u8> {
+    let mut r = Vec::new();
+    r.extend_from_slice(&opcode.0.to_be_bytes());
+    r.extend_from_slice(filename.as_bytes());
+    r.push(0);
+    r.extend_from_slice(b"octet");
+    r.push(0);
+    for (name, value) in options.iter() {
+        r.extend_from_slice(name.as_bytes());
+        r.push(0);
+        r.extend_from_slice(alloc::format!("{}", value).as_bytes());
+        r.push(0);
+    }
+    r
+}
+
+pub fn data(block: u16, data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(4 + data.len());
+    r.extend_from_slice(&Opcode::DATA.0.to_be_bytes());
+    r.extend_from_slice(&block.to_be_bytes());
+    r.extend_from_slice(data);
+    r
+}
+
+pub fn ack(block: u16) -> Vec<u8> {
+    let mut r = Vec::with_capacity(4);
+    r.extend_from_slice(&Opcode::ACK.0.to_be_bytes());
+    r.extend_from_slice(&block.to_be_bytes());
+    r
+}
+
+pub fn error(code: ErrorCode, message: &str) -> Vec<u8> {
+    let mut r = Vec::with_capacity(5 + message.len());
+    r.extend_from_slice(&Opcode::ERROR.0.to_be_bytes());
+    r.extend_from_slice(&code.0.to_be_bytes());
+    r.extend_from_slice(message.as_bytes());
+    r.push(0);
+    r
+}
@@ -0,0 +1,4 @@
+mod client;
+mod message;
+
+pub use client::{Client, Options};
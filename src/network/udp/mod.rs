@@ -0,0 +1,7 @@
+mod packet;
+mod service;
+mod socket;
+
+pub use packet::Packet;
+pub use service::Service;
+pub use socket::Socket;
@@ -0,0 +1,88 @@
+use core::fmt;
+
+use crate::network::ip;
+
+pub struct Packet {
+    pub ip: ip::Packet,
+}
+
+impl fmt::Debug for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UDPPacket")
+            .field("source_port", &self.source_port())
+            .field("destination_port", &self.destination_port())
+            .field("length", &self.length())
+            .field("checksum", &self.checksum())
+            .finish()
+    }
+}
+
+impl Packet {
+    pub fn new() -> Packet {
+        let mut p = Packet {
+            ip: ip::Packet::new(),
+        };
+        p.ip.set_protocol(ip::Protocol::UDP);
+        p.set_data(&[]);
+        p
+    }
+
+    pub fn source_port(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[0..2].try_into().unwrap())
+    }
+    pub fn set_source_port(&mut self, p: u16) {
+        self.ip.data_mut()[0..2].clone_from_slice(&p.to_be_bytes());
+    }
+    pub fn destination_port(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[2..4].try_into().unwrap())
+    }
+    pub fn set_destination_port(&mut self, p: u16) {
+        self.ip.data_mut()[2..4].clone_from_slice(&p.to_be_bytes());
+    }
+    pub fn length(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[4..6].try_into().unwrap())
+    }
+    pub fn set_length(&mut self, l: u16) {
+        self.ip.data_mut()[4..6].clone_from_slice(&l.to_be_bytes());
+    }
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(self.ip.data()[6..8].try_into().unwrap())
+    }
+    pub fn set_checksum(&mut self, s: u16) {
+        self.ip.data_mut()[6..8].clone_from_slice(&s.to_be_bytes());
+    }
+
+    pub fn data(&self) -> &[u8] {
+        let length = (self.length() as usize).clamp(8, self.ip.data().len());
+        &self.ip.data()[8..length]
+    }
+    pub fn set_data(&mut self, data: &[u8]) {
+        let data_len: u16 = data.len().try_into().unwrap();
+        self.ip.set_size(8 + data_len);
+        self.set_length(8 + data_len);
+        self.ip.data_mut()[8..].clone_from_slice(data);
+    }
+
+    // Checksum over the ipv4 pseudo header and the udp datagram, both
+    // addresses have to be set before calling this
+    pub fn compute_checksum(&self) -> u16 {
+        ip::pseudo_header_checksum(
+            &self.ip.source_address(),
+            &self.ip.destination_address(),
+            ip::Protocol::UDP,
+            &self.ip.data()[..self.length() as usize],
+        )
+    }
+
+    pub fn valid(&self) -> bool {
+        if self.ip.data().len() < 8 {
+            return false;
+        }
+        let length = self.length() as usize;
+        if length < 8 || length > self.ip.data().len() {
+            return false;
+        }
+        // zero checksum means the sender did not compute one
+        self.checksum() == 0 || self.compute_checksum() == 0
+    }
+}
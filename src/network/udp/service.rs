@@ -0,0 +1,124 @@
+extern crate alloc;
+
+use core::sync::atomic::{AtomicU16, Ordering};
+use crossbeam_queue::ArrayQueue;
+use hashbrown::HashMap;
+
+use alloc::{sync::Arc, vec::Vec};
+use log::info;
+
+use crate::{
+    asyn, metrics,
+    network::{ip, queues, Error},
+};
+
+use super::{Packet, Socket};
+
+// Start of the dynamic port range used for sockets opened without a port
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+const DROPPED: &str = "udp_dropped_total";
+const DROPPED_HELP: &str = "Received UDP datagrams dropped, by reason.";
+
+pub struct Service {
+    next_ephemeral_port: AtomicU16,
+    ip_socket: Arc<ip::Socket>,
+    sockets: asyn::Mutex<HashMap<u16, Arc<ArrayQueue<Packet>>>>,
+    invalid: metrics::Counter,
+    queue_full: metrics::Counter,
+    closed_port: metrics::Counter,
+}
+
+impl Service {
+    pub async fn new(ip: Arc<ip::Service>) -> Service {
+        let metrics = ip.metrics();
+        Service {
+            ip_socket: Arc::new(ip.open(ip::Protocol::UDP).await),
+            sockets: asyn::Mutex::new(HashMap::new()),
+            next_ephemeral_port: AtomicU16::new(EPHEMERAL_PORT_START),
+            invalid: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "invalid")]),
+            queue_full: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "queue_full")]),
+            closed_port: metrics.counter(DROPPED, DROPPED_HELP, &[("reason", "closed_port")]),
+        }
+    }
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.task_receive()));
+    }
+
+    // Port 0 picks a free ephemeral port. Fails when the port is taken or
+    // no ephemeral port is left; dropping the socket frees its port.
+    pub async fn open(&self, mut port: u16) -> Result<Socket, Error> {
+        let mut sockets = self.sockets.lock().await;
+        sockets.retain(|_, q| queues::is_open(q));
+        if sockets.contains_key(&port) {
+            return Err(Error::AddressInUse);
+        }
+        let mut tries = u16::MAX - EPHEMERAL_PORT_START + 1;
+        while port == 0 {
+            if tries == 0 {
+                return Err(Error::AddressInUse);
+            }
+            tries -= 1;
+            port = self.next_ephemeral_port.fetch_add(1, Ordering::Relaxed);
+            if port == u16::MAX {
+                self.next_ephemeral_port
+                    .store(EPHEMERAL_PORT_START, Ordering::Relaxed);
+            }
+            if port < EPHEMERAL_PORT_START || sockets.contains_key(&port) {
+                port = 0;
+            }
+        }
+
+        let s = Socket {
+            port,
+            recv_queue: Arc::new(ArrayQueue::new(16)),
+            ip_socket: self.ip_socket.clone(),
+        };
+        sockets.insert(port, s.recv_queue.clone());
+        Ok(s)
+    }
+
+    // Local ports of every open socket
+    pub async fn ports(&self) -> Vec<u16> {
+        let sockets = self.sockets.lock().await;
+        sockets
+            .iter()
+            .filter(|(_, q)| queues::is_open(q))
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    async fn task_receive(self: Arc<Self>) {
+        loop {
+            let received = Packet {
+                ip: self.ip_socket.receive().await,
+            };
+
+            if !received.valid() {
+                self.invalid.inc();
+                continue;
+            }
+
+            let sockets = self.sockets.lock().await;
+            match sockets
+                .get(&received.destination_port())
+                .filter(|q| queues::is_open(q))
+            {
+                Some(q) => {
+                    if q.push(received).is_err() {
+                        self.queue_full.inc();
+                        info!("udp receive queue full, dropping datagram");
+                    }
+                }
+                None => {
+                    self.closed_port.inc();
+                    info!(
+                        "udp datagram for closed port {} from {:?}",
+                        received.destination_port(),
+                        received.ip.source_address(),
+                    )
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+extern crate alloc;
+
+use alloc::sync::Arc;
+
+use crossbeam_queue::ArrayQueue;
+
+use super::Packet;
+use crate::{
+    asyn,
+    network::{ip, Error},
+};
+
+pub struct Socket {
+    pub(super) port: u16,
+    pub(super) recv_queue: Arc<ArrayQueue<Packet>>,
+    pub(super) ip_socket: Arc<ip::Socket>,
+}
+
+impl Socket {
+    pub fn address(&self) -> ip::Address {
+        self.ip_socket.address()
+    }
+
+    // The next datagram, waiting at most t seconds
+    pub async fn receive(&self, t: f64) -> Result<Packet, Error> {
+        asyn::queue_pop_timeout(self.recv_queue.clone(), t)
+            .await
+            .ok_or(Error::Timeout)
+    }
+
+    pub async fn send(
+        &self,
+        destination: ip::Address,
+        port: u16,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let mut request = Packet::new();
+
+        request.set_data(data);
+        request.set_source_port(self.port);
+        request.set_destination_port(port);
+
+        request.ip.set_source_address(&self.ip_socket.address());
+        request.ip.set_destination_address(&destination);
+        request.set_checksum(0);
+        request.set_checksum(match request.compute_checksum() {
+            0 => 0xffff,
+            c => c,
+        });
+
+        self.ip_socket.send(request.ip).await
+    }
+}
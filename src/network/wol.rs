@@ -0,0 +1,156 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt;
+use log::info;
+
+use super::{ethernet, ip, udp, Error};
+use crate::asyn;
+
+// Discard port, where magic packets over udp usually go
+pub const PORT: u16 = 9;
+
+const SYNC: [u8; 6] = [0xff; 6];
+const REPETITIONS: usize = 16;
+
+// Sync stream, the target mac 16 times and an optional password
+pub fn magic_packet(target: ethernet::MacAddress, password: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SYNC.len() + REPETITIONS * 6 + password.len());
+    data.extend_from_slice(&SYNC);
+    for _ in 0..REPETITIONS {
+        data.extend_from_slice(&target.0);
+    }
+    data.extend_from_slice(password);
+    data
+}
+
+// The password of a magic packet for the mac, None for anything else
+pub fn parse(data: &[u8], mac: ethernet::MacAddress) -> Option<Vec<u8>> {
+    // some senders put a header in front, look for the sync stream
+    let start = data.windows(SYNC.len()).position(|w| w == SYNC)?;
+    let mut rest = &data[start + SYNC.len()..];
+    // the target mac may itself start with ff bytes
+    while rest.len() > REPETITIONS * 6 && rest[..6] != mac.0 && rest[0] == 0xff {
+        rest = &rest[1..];
+    }
+    for _ in 0..REPETITIONS {
+        let (target, tail) = rest.split_at_checked(6)?;
+        if target != mac.0 {
+            return None;
+        }
+        rest = tail;
+    }
+    match rest.len() {
+        4 | 6 => Some(rest.to_vec()),
+        _ => Some(Vec::new()),
+    }
+}
+
+// Sends magic packets and reports the ones for us, arriving raw as ether
+// type 0x0842 or over udp to the discard port
+pub struct Service {
+    mac: ethernet::MacAddress,
+    socket: ethernet::Socket,
+    udp: Option<udp::Socket>,
+}
+
+impl Service {
+    // Without a udp socket only raw magic packets are heard
+    pub fn new(
+        mac: ethernet::MacAddress,
+        network: &impl ethernet::Network,
+        udp: Option<udp::Socket>,
+    ) -> Service {
+        Service {
+            mac,
+            socket: network.open(ethernet::Type::WOL),
+            udp,
+        }
+    }
+
+    pub fn start(self: Arc<Self>, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.clone().task_receive()));
+        if self.udp.is_some() {
+            e.spawn(asyn::Task::new(self.task_receive_udp()));
+        }
+    }
+
+    // Broadcasts a raw magic packet on the local segment
+    pub fn wake(&self, target: ethernet::MacAddress, password: &[u8]) -> Result<(), Error> {
+        let data = magic_packet(target, password);
+        let mut p = ethernet::Packet::new();
+        p.set_mac_destination(ethernet::MAC_BROADCAST);
+        p.set_size(data.len());
+        p.data_mut().copy_from_slice(&data);
+        self.socket.send(p)
+    }
+
+    // Sends a magic packet in a udp datagram, usually to a broadcast address
+    pub async fn wake_udp(
+        socket: &udp::Socket,
+        target: ethernet::MacAddress,
+        password: &[u8],
+        destination: ip::Address,
+        port: u16,
+    ) -> Result<(), Error> {
+        socket
+            .send(destination, port, &magic_packet(target, password))
+            .await
+    }
+
+    // The password is SecureOn, 4 or 6 bytes, empty without one
+    fn report(source: fmt::Arguments, password: &[u8]) {
+        info!(
+            "wake-on-lan magic packet from {}, {} byte password",
+            source,
+            password.len()
+        );
+    }
+
+    async fn task_receive(self: Arc<Self>) {
+        loop {
+            let p = self.socket.receive().await;
+            if let Some(password) = parse(p.data(), self.mac) {
+                Self::report(format_args!("{}", p.mac_source()), &password);
+            }
+        }
+    }
+
+    async fn task_receive_udp(self: Arc<Self>) {
+        let Some(socket) = self.udp.as_ref() else {
+            return;
+        };
+        loop {
+            let Ok(p) = socket.receive(1.0).await else {
+                continue;
+            };
+            if let Some(password) = parse(p.data(), self.mac) {
+                Self::report(
+                    format_args!("{}:{}", p.ip.source_address(), p.source_port()),
+                    &password,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_packets() {
+        let mac = ethernet::MacAddress([0xff, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let other = ethernet::MacAddress([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let data = magic_packet(mac, &[1, 2, 3, 4]);
+        assert_eq!(data.len(), 6 + 16 * 6 + 4);
+        assert_eq!(parse(&data, mac), Some(alloc::vec![1, 2, 3, 4]));
+        assert_eq!(parse(&data, other), None);
+
+        let mut framed = alloc::vec![0xaa, 0xff];
+        framed.extend_from_slice(&magic_packet(mac, &[]));
+        assert_eq!(parse(&framed, mac), Some(Vec::new()));
+        assert_eq!(parse(&framed[..50], mac), None);
+    }
+}
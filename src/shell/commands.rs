@@ -0,0 +1,701 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::future::Future;
+use uefi::{
+    proto::media::file::FileMode,
+    runtime::{self, ResetType},
+};
+
+use super::{Command, CommandFuture, Shell};
+use crate::{
+    asyn, chainload, fs, memory,
+    network::{capture, ethernet, icmp, ip, stack::NetworkStack, tftp, wol},
+    shutdown::{self, Exit, Shutdown},
+};
+
+// What the built-in commands operate on
+pub struct Services {
+    pub executor: Arc<dyn asyn::Executor>,
+    pub stack: Arc<NetworkStack>,
+    pub shutdown: Arc<Shutdown>,
+}
+
+pub fn register_builtins(shell: &mut Shell, services: Arc<Services>) {
+    let s = &services;
+    shell.register("ifconfig", "[address [netmask]]", command(s, ifconfig));
+    shell.register(
+        "link",
+        "[stats [reset] | promisc on|off | mac <address>|permanent | join <group> | leave <group>]",
+        command(s, link),
+    );
+    shell.register("arp", "show the arp table", command(s, arp));
+    shell.register("lldp", "show the lldp neighbors", command(s, lldp));
+    shell.register("route", "[default <gateway>]", command(s, route));
+    shell.register("ping", "<host> [count]", command(s, ping));
+    shell.register("traceroute", "<host> [udp]", command(s, traceroute));
+    shell.register("wol", "<mac> [password] [udp [address]]", command(s, wake));
+    shell.register(
+        "dhcp",
+        "[request] show the lease, or get one and keep renewing it",
+        command(s, dhcp),
+    );
+    shell.register("dns", "[name | servers <address>...]", command(s, dns));
+    shell.register("tftp", "get|put <server> <file>", command(s, tftp));
+    shell.register(
+        "boot",
+        "<server> <kernel> [<initrd> | -] [command line] fetch over tftp and start",
+        command(s, boot),
+    );
+    shell.register(
+        "capture",
+        "start [ether <type>] [host <address>] | stop | save <file> | stream udp|tcp <address> <port>",
+        command(s, capture),
+    );
+    shell.register("mem", "show allocator statistics", command(s, mem));
+    shell.register("metrics", "[prometheus]", command(s, show_metrics));
+    shell.register(
+        "tasks",
+        "show the number of running tasks",
+        command(s, tasks),
+    );
+    shell.register("reboot", "reset the machine", command(s, reboot));
+    shell.register(
+        "exit",
+        "[status | boot-services] stop the network and return to the firmware, or leave it and reset",
+        command(s, exit),
+    );
+}
+
+fn command<F, R>(services: &Arc<Services>, f: F) -> impl Command
+where
+    F: Fn(Arc<Services>, Vec<String>) -> R,
+    R: Future<Output = ()> + 'static,
+{
+    let services = services.clone();
+    move |args| -> CommandFuture { Box::pin(f(services.clone(), args)) }
+}
+
+// Parses an address or looks the name up
+async fn resolve(s: &Services, host: &str) -> Option<ip::Address> {
+    if let Ok(address) = host.parse() {
+        return Some(address);
+    }
+    let Some(resolver) = s.stack.resolver() else {
+        outln!("{}: dns needs udp, which is turned off", host);
+        return None;
+    };
+    match resolver.lookup(host).await {
+        Ok(addresses) => addresses.first().copied(),
+        Err(e) => {
+            outln!("{}: {:?}", host, e);
+            None
+        }
+    }
+}
+
+async fn ifconfig(s: Arc<Services>, args: Vec<String>) {
+    let ip = s.stack.ip();
+    if let Some(address) = args.first() {
+        let Ok(address) = address.parse() else {
+            return outln!("ifconfig: invalid address {}", address);
+        };
+        let netmask = match args.get(1).map(|n| n.parse()) {
+            Some(Ok(n)) => n,
+            Some(Err(_)) => return outln!("ifconfig: invalid netmask"),
+            None => ip.netmask(),
+        };
+        ip.set_config(address, netmask, ip.gateway());
+    }
+    outln!("ether   {}", s.stack.mac_address());
+    if let Some(tag) = s.stack.vlan() {
+        outln!("vlan    {} priority {}", tag.id, tag.priority);
+    }
+    outln!("inet    {}", ip.address());
+    outln!("netmask {}", ip.netmask());
+}
+
+async fn link(s: Arc<Services>, args: Vec<String>) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let interface = s.stack.interface();
+    let ip = s.stack.ip();
+    let result = match args.as_slice() {
+        [] => {
+            let link = interface.link();
+            let media = match link.media_present {
+                Some(true) => "present",
+                Some(false) => "absent",
+                None => "unknown",
+            };
+            let filters = interface.receive_filters();
+            outln!("state     {}", if link.initialized { "up" } else { "down" });
+            outln!("media     {}", media);
+            outln!("ether     {}", interface.mac_address());
+            outln!("permanent {}", link.permanent_address);
+            outln!("mtu       {}", link.max_packet_size);
+            outln!(
+                "filters   {}{}{}{}",
+                if filters.unicast { "unicast " } else { "" },
+                if filters.broadcast { "broadcast " } else { "" },
+                if filters.promiscuous {
+                    "promiscuous "
+                } else {
+                    ""
+                },
+                if filters.all_multicast {
+                    "all-multicast"
+                } else {
+                    ""
+                },
+            );
+            for mac in filters.multicast.iter() {
+                outln!("multicast {}", mac);
+            }
+            for group in ip.groups().await {
+                outln!("group     {}", group);
+            }
+            Ok(())
+        }
+        ["stats"] => match interface.statistics() {
+            Ok(stats) => {
+                let rows = [
+                    ("rx frames", stats.rx_total_frames()),
+                    ("rx good", stats.rx_good_frames()),
+                    ("rx dropped", stats.rx_dropped_frames()),
+                    ("rx crc errors", stats.rx_crc_error_frames()),
+                    ("rx bytes", stats.rx_total_bytes()),
+                    ("tx frames", stats.tx_total_frames()),
+                    ("tx good", stats.tx_good_frames()),
+                    ("tx dropped", stats.tx_dropped_frames()),
+                    ("tx errors", stats.tx_error_frames()),
+                    ("tx bytes", stats.tx_total_bytes()),
+                    ("collisions", stats.collisions()),
+                ];
+                // the driver leaves out what it does not count
+                for (name, value) in rows {
+                    if let Some(v) = value {
+                        outln!("{:<14} {}", name, v);
+                    }
+                }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        ["stats", "reset"] => interface.reset_statistics(),
+        ["promisc", on @ ("on" | "off")] => {
+            let mut filters = interface.receive_filters();
+            filters.promiscuous = *on == "on";
+            interface.set_receive_filters(filters)
+        }
+        ["mac", "permanent"] => s.stack.set_mac_address(None),
+        ["mac", address] => match address.parse() {
+            Ok(address) => s.stack.set_mac_address(Some(address)),
+            Err(_) => return outln!("link: invalid mac address {}", address),
+        },
+        [verb @ ("join" | "leave"), group] => {
+            let Ok(group) = group.parse() else {
+                return outln!("link: invalid group {}", group);
+            };
+            match *verb {
+                "join" => ip.join(group).await,
+                _ => ip.leave(group).await,
+            }
+        }
+        _ => {
+            return outln!(
+                "usage: link [stats [reset] | promisc on|off | mac <address>|permanent | join <group> | leave <group>]"
+            )
+        }
+    };
+    if let Err(e) = result {
+        outln!("link: {:?}", e);
+    }
+}
+
+async fn arp(s: Arc<Services>, _: Vec<String>) {
+    let table = s.stack.arp().table.lock().await;
+    for (address, mac) in table.iter() {
+        outln!("{:<16} {}", address, mac);
+    }
+}
+
+async fn lldp(s: Arc<Services>, _: Vec<String>) {
+    let Some(lldp) = s.stack.lldp() else {
+        return outln!("lldp: not running");
+    };
+    for n in lldp.neighbors().await {
+        let d = &n.lldpdu;
+        outln!("chassis     {} ({})", d.chassis_id, n.source);
+        outln!("port        {}", d.port_id);
+        if let Some(description) = d.port_description.as_ref() {
+            outln!("description {}", description);
+        }
+        if let Some(name) = d.system_name.as_ref() {
+            outln!("system      {}", name);
+        }
+        if let Some(address) = d.management_address {
+            outln!("management  {}", address);
+        }
+        outln!("expires in  {:.0} s", n.remaining());
+        outln!();
+    }
+}
+
+async fn route(s: Arc<Services>, args: Vec<String>) {
+    let ip = s.stack.ip();
+    match args.as_slice() {
+        [] => {}
+        [default, gateway] if default == "default" => match gateway.parse() {
+            Ok(gateway) => ip.set_config(ip.address(), ip.netmask(), gateway),
+            Err(_) => return outln!("route: invalid gateway {}", gateway),
+        },
+        _ => return outln!("usage: route [default <gateway>]"),
+    }
+    let netmask = ip.netmask();
+    outln!("{:<16} {:<16} gateway", "destination", "netmask");
+    outln!("{:<16} {:<16} -", ip.address() & netmask, netmask);
+    outln!("{:<16} {:<16} {}", "default", "0.0.0.0", ip.gateway());
+}
+
+async fn ping(s: Arc<Services>, args: Vec<String>) {
+    let Some(host) = args.first() else {
+        return outln!("usage: ping <host> [count]");
+    };
+    let count = match args.get(1).map(|c| c.parse::<usize>()) {
+        Some(Ok(c)) => c,
+        Some(Err(_)) => return outln!("ping: invalid count"),
+        None => 4,
+    };
+    let Some(icmp) = s.stack.icmp() else {
+        return outln!("ping: icmp is turned off");
+    };
+    let Some(address) = resolve(&s, host).await else {
+        return;
+    };
+
+    let socket = icmp.open(address).await;
+    let mut received = 0;
+    for i in 0..count {
+        let start = asyn::timestamp();
+        let reply = match socket.send(&[0; 32]).await {
+            Ok(()) => socket.receive(1.0).await,
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(_) => {
+                received += 1;
+                outln!(
+                    "reply from {}: time={:.3} ms",
+                    address,
+                    asyn::elapsed(start) * 1000.0
+                );
+            }
+            Err(e) => outln!("{}: {}", address, e),
+        }
+        if i + 1 < count {
+            asyn::sleep(1.0 - asyn::elapsed(start)).await;
+        }
+    }
+    outln!("{} sent, {} received", count, received);
+}
+
+async fn traceroute(s: Arc<Services>, args: Vec<String>) {
+    let Some(host) = args.first() else {
+        return outln!("usage: traceroute <host> [udp]");
+    };
+    let Some(icmp) = s.stack.icmp() else {
+        return outln!("traceroute: icmp is turned off");
+    };
+    let Some(address) = resolve(&s, host).await else {
+        return;
+    };
+    let mut options = icmp::Options::default();
+    if args.get(1).is_some_and(|m| m == "udp") {
+        options.mode = icmp::Mode::Udp;
+    }
+
+    let tracer = icmp.traceroute(address, options).await;
+    let hops = match tracer.run().await {
+        Ok(hops) => hops,
+        Err(e) => return outln!("traceroute: {}", e),
+    };
+    for hop in hops {
+        let mut line = format!("{:>2}", hop.ttl);
+        for probe in hop.probes.iter() {
+            match probe {
+                Some(p) => line += &format!("  {} {:.3} ms", p.address, p.rtt * 1000.0),
+                None => line += "  *",
+            }
+        }
+        outln!("{}", line);
+    }
+}
+
+async fn wake(s: Arc<Services>, args: Vec<String>) {
+    let usage = "usage: wol <mac> [password] [udp [address]]";
+    let Some(wol) = s.stack.wol() else {
+        return outln!("wol: wake-on-lan is turned off");
+    };
+    let mut args = args.iter().map(String::as_str);
+    let Some(Ok(target)) = args.next().map(str::parse::<ethernet::MacAddress>) else {
+        return outln!("{}", usage);
+    };
+    let mut next = args.next();
+    // SecureOn passwords are written like a mac or an ipv4 address
+    let password = match next.filter(|a| *a != "udp") {
+        Some(p) => {
+            next = args.next();
+            if let Ok(mac) = p.parse::<ethernet::MacAddress>() {
+                mac.0.to_vec()
+            } else if let Ok(address) = p.parse::<ip::Address>() {
+                address.0.to_vec()
+            } else {
+                return outln!("wol: invalid password {}", p);
+            }
+        }
+        None => Vec::new(),
+    };
+    let sent = match (next, args.next()) {
+        (None, _) => wol.wake(target, &password),
+        (Some("udp"), address) => {
+            let destination = match address.map(str::parse) {
+                Some(Ok(a)) => a,
+                Some(Err(_)) => return outln!("{}", usage),
+                None => ip::Address([255; 4]),
+            };
+            let Some(udp) = s.stack.udp() else {
+                return outln!("wol: udp is turned off");
+            };
+            let socket = match udp.open(0).await {
+                Ok(socket) => socket,
+                Err(e) => return outln!("wol: {}", e),
+            };
+            wol::Service::wake_udp(&socket, target, &password, destination, wol::PORT).await
+        }
+        _ => return outln!("{}", usage),
+    };
+    if let Err(e) = sent {
+        return outln!("wol: {}", e);
+    }
+    outln!("sent magic packet for {}", target);
+}
+
+async fn dhcp(s: Arc<Services>, args: Vec<String>) {
+    let Some(client) = s.stack.dhcp() else {
+        return outln!("dhcp: udp is turned off");
+    };
+    let lease = match args.first().map(String::as_str) {
+        None => match client.lease().await {
+            Some(lease) => lease,
+            None => return outln!("no lease"),
+        },
+        Some("request") => match client.configure().await {
+            Ok(lease) => {
+                client.clone().start(s.executor.clone());
+                lease
+            }
+            Err(e) => return outln!("dhcp: {}", e),
+        },
+        Some(_) => return outln!("usage: dhcp [request]"),
+    };
+    outln!("inet    {}", lease.address);
+    outln!("netmask {}", lease.netmask);
+    outln!("gateway {}", lease.gateway);
+    outln!("server  {}", lease.server);
+    for server in lease.dns.iter() {
+        outln!("dns     {}", server);
+    }
+    let left = lease.time as f64 - asyn::elapsed(lease.acquired);
+    outln!("expires in {:.0} s", left.max(0.0));
+}
+
+async fn dns(s: Arc<Services>, args: Vec<String>) {
+    let Some(resolver) = s.stack.resolver() else {
+        return outln!("dns: udp is turned off");
+    };
+    match args.split_first() {
+        None => {
+            for server in resolver.servers().await {
+                outln!("server {}", server);
+            }
+        }
+        Some((first, servers)) if first == "servers" => {
+            let mut addresses = Vec::new();
+            for server in servers {
+                match server.parse() {
+                    Ok(a) => addresses.push(a),
+                    Err(_) => return outln!("dns: invalid address {}", server),
+                }
+            }
+            resolver.set_servers(addresses).await;
+        }
+        Some((name, _)) => {
+            let v4 = resolver.lookup(name).await;
+            let v6 = resolver.lookup_ipv6(name).await;
+            if let (Err(e), Err(_)) = (&v4, &v6) {
+                return outln!("{}: {:?}", name, e);
+            }
+            for address in v4.unwrap_or_default() {
+                outln!("{} has address {}", name, address);
+            }
+            for address in v6.unwrap_or_default() {
+                outln!("{} has IPv6 address {}", name, ipv6(&address));
+            }
+        }
+    }
+}
+
+// Eight groups of hex digits, without shortening runs of zeros
+fn ipv6(address: &[u8; 16]) -> String {
+    let groups: Vec<String> = address
+        .chunks(2)
+        .map(|g| format!("{:x}", u16::from_be_bytes([g[0], g[1]])))
+        .collect();
+    groups.join(":")
+}
+
+// Files on the volume are named after the last part of the path
+async fn tftp(s: Arc<Services>, args: Vec<String>) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let [direction @ ("get" | "put"), host, path] = args.as_slice() else {
+        return outln!("usage: tftp get|put <server> <file>");
+    };
+    let Some(udp) = s.stack.udp() else {
+        return outln!("tftp: udp is turned off");
+    };
+    let Some(server) = resolve(&s, host).await else {
+        return;
+    };
+    let socket = match udp.open(0).await {
+        Ok(socket) => socket,
+        Err(e) => return outln!("tftp: {}", e),
+    };
+    let client = tftp::Client::new(socket, tftp::Options::default());
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let transferred = if *direction == "get" {
+        match fs::create(name) {
+            Ok(mut file) => client.read(server, path, &mut file).await,
+            Err(e) => return outln!("tftp: failed to create {}: {:?}", name, e.status()),
+        }
+    } else {
+        match fs::open(name, FileMode::Read) {
+            Ok(mut file) => client.write(server, path, &mut file).await,
+            Err(e) => return outln!("tftp: failed to open {}: {:?}", name, e.status()),
+        }
+    };
+    match transferred {
+        Ok(size) => outln!("{} bytes", size),
+        Err(e) => outln!("tftp: {}", e),
+    }
+}
+
+// Fetches a kernel and optionally its initrd over tftp and starts it. The
+// interface goes down on the way, so the app exits if the kernel returns.
+async fn boot(s: Arc<Services>, args: Vec<String>) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let [host, kernel, rest @ ..] = args.as_slice() else {
+        return outln!("usage: boot <server> <kernel> [<initrd> | -] [command line]");
+    };
+    let (initrd, command_line) = match rest {
+        [] => (None, String::new()),
+        [initrd, options @ ..] => (Some(*initrd).filter(|i| *i != "-"), options.join(" ")),
+    };
+    let Some(udp) = s.stack.udp() else {
+        return outln!("boot: udp is turned off");
+    };
+    let Some(server) = resolve(&s, host).await else {
+        return;
+    };
+    let socket = match udp.open(0).await {
+        Ok(socket) => socket,
+        Err(e) => return outln!("boot: {}", e),
+    };
+    let client = tftp::Client::new(socket, tftp::Options::default());
+
+    let mut image = Vec::new();
+    if let Err(e) = client.read(server, kernel, &mut image).await {
+        return outln!("boot: failed to fetch {}: {}", kernel, e);
+    }
+    let mut initrd_data = Vec::new();
+    if let Some(initrd) = initrd {
+        if let Err(e) = client.read(server, initrd, &mut initrd_data).await {
+            return outln!("boot: failed to fetch {}: {}", initrd, e);
+        }
+    }
+    drop(client);
+
+    let options = chainload::Options {
+        command_line: &command_line,
+        initrd: initrd.map(|_| initrd_data.as_slice()),
+    };
+    outln!("booting {} ({} bytes)", kernel, image.len());
+    asyn::console_flush();
+    let status = match chainload::chainload(&image, &options, s.stack.ethernet()) {
+        Ok(()) => uefi::Status::SUCCESS,
+        Err(e) => {
+            outln!("boot: {:?}", e.status());
+            e.status()
+        }
+    };
+    // a failed load leaves the interface up
+    if s.stack.ethernet().stopped() {
+        s.shutdown.request(Exit::Return(status));
+    }
+}
+
+async fn capture(s: Arc<Services>, args: Vec<String>) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let capture = s.stack.capture();
+    match args.as_slice() {
+        [] => {
+            let (captured, dropped) = capture.counts();
+            let filter = capture.filter().await;
+            outln!(
+                "{}, {} frames captured, {} not streamed",
+                if capture.running() { "running" } else { "stopped" },
+                captured,
+                dropped
+            );
+            if let Some(t) = filter.ether_type {
+                outln!("ether   {:#06x}", t.0);
+            }
+            if let Some(host) = filter.host {
+                outln!("host    {}", host);
+            }
+        }
+        ["start", rest @ ..] => {
+            let mut filter = capture::Filter::default();
+            for pair in rest.chunks(2) {
+                match pair {
+                    ["ether", t] => match parse_ether_type(t) {
+                        Some(t) => filter.ether_type = Some(t),
+                        None => return outln!("capture: invalid ether type {}", t),
+                    },
+                    ["host", host] => match resolve(&s, host).await {
+                        Some(address) => filter.host = Some(address),
+                        None => return,
+                    },
+                    _ => return outln!("usage: capture start [ether <type>] [host <address>]"),
+                }
+            }
+            capture.start(filter).await;
+        }
+        ["stop"] => capture.stop().await,
+        ["save", path] => match capture.save(path).await {
+            Ok(size) => outln!("saved {} bytes to {}", size, path),
+            Err(e) => outln!("capture: failed to save {}: {:?}", path, e),
+        },
+        ["stream", transport, host, port] => {
+            let Ok(port) = port.parse() else {
+                return outln!("capture: invalid port {}", port);
+            };
+            let Some(address) = resolve(&s, host).await else {
+                return;
+            };
+            match *transport {
+                "udp" => {
+                    let Some(udp) = s.stack.udp() else {
+                        return outln!("capture: udp is turned off");
+                    };
+                    let socket = match udp.open(0).await {
+                        Ok(socket) => socket,
+                        Err(e) => return outln!("capture: {}", e),
+                    };
+                    capture
+                        .stream_udp(socket, address, port, s.executor.clone())
+                        .await;
+                }
+                "tcp" => {
+                    let Some(tcp) = s.stack.tcp() else {
+                        return outln!("capture: tcp is turned off");
+                    };
+                    let stream = capture.stream_tcp(tcp, address, port, s.executor.clone());
+                    if let Err(e) = stream.await {
+                        return outln!("capture: failed to connect: {:?}", e);
+                    }
+                }
+                _ => return outln!("capture: unknown transport {}", transport),
+            }
+            outln!("streaming to {}:{}", address, port);
+        }
+        _ => outln!("usage: capture [start [ether <type>] [host <address>] | stop | save <file> | stream udp|tcp <address> <port>]"),
+    }
+}
+
+// Names for the common ether types, or a number like 0x86dd
+fn parse_ether_type(s: &str) -> Option<ethernet::Type> {
+    match s {
+        "ipv4" | "ip" => Some(ethernet::Type::IPV4),
+        "arp" => Some(ethernet::Type::ARP),
+        _ => match s.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok().map(ethernet::Type),
+            None => s.parse().ok().map(ethernet::Type),
+        },
+    }
+}
+
+async fn mem(_: Arc<Services>, _: Vec<String>) {
+    let stats = memory::stats();
+    outln!("pages         {}", stats.pages);
+    outln!("allocated     {} bytes", stats.allocated);
+    outln!("allocations   {}", stats.allocations);
+    outln!("deallocations {}", stats.deallocations);
+}
+
+async fn show_metrics(s: Arc<Services>, args: Vec<String>) {
+    match args.first().map(String::as_str) {
+        None => out!("{}", s.stack.metrics().text()),
+        Some("prometheus") => out!("{}", s.stack.metrics().prometheus()),
+        Some(_) => outln!("usage: metrics [prometheus]"),
+    }
+}
+
+async fn tasks(s: Arc<Services>, _: Vec<String>) {
+    outln!("{} tasks", s.executor.task_count());
+}
+
+async fn reboot(_: Arc<Services>, _: Vec<String>) {
+    outln!("rebooting");
+    asyn::console_flush();
+    runtime::reset(ResetType::COLD, uefi::Status::SUCCESS, None);
+}
+
+async fn exit(s: Arc<Services>, args: Vec<String>) {
+    let Some(exit) = exit_request(args.first().map(String::as_str)) else {
+        return outln!("usage: exit [status | boot-services]");
+    };
+    outln!("exiting");
+    s.shutdown.request(exit);
+}
+
+// A status to return to the firmware with, or boot-services to leave it
+// behind and reset
+fn exit_request(arg: Option<&str>) -> Option<Exit> {
+    match arg {
+        None => Some(Exit::Return(uefi::Status::SUCCESS)),
+        Some("boot-services") => Some(Exit::ExitBootServices(shutdown::reset)),
+        Some(code) => code.parse().ok().map(|c| Exit::Return(uefi::Status(c))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_arguments() {
+        assert!(matches!(
+            exit_request(None),
+            Some(Exit::Return(uefi::Status::SUCCESS))
+        ));
+        assert!(matches!(
+            exit_request(Some("14")),
+            Some(Exit::Return(uefi::Status(14)))
+        ));
+        assert!(matches!(
+            exit_request(Some("boot-services")),
+            Some(Exit::ExitBootServices(_))
+        ));
+        assert!(exit_request(Some("later")).is_none());
+    }
+}
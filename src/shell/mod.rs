@@ -0,0 +1,171 @@
+extern crate alloc;
+
+// Shell output is queued for the console like the log, so a long listing
+// does not hold up the other tasks and keeps its order with log lines
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::asyn::console_write(&alloc::format!($($arg)*))
+    };
+}
+
+macro_rules! outln {
+    () => {
+        $crate::asyn::console_write("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::asyn::console_write(&alloc::format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod commands;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{future::Future, pin::Pin};
+use uefi::proto::console::text::{Key, ScanCode};
+
+use crate::asyn;
+
+pub use commands::{register_builtins, Services};
+
+const PROMPT: &str = "> ";
+const MAX_LINE: usize = 256;
+const HISTORY: usize = 32;
+
+pub type CommandFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+// A shell command, called with the words following its name
+pub trait Command {
+    fn run(&self, args: Vec<String>) -> CommandFuture;
+}
+
+impl<F: Fn(Vec<String>) -> CommandFuture> Command for F {
+    fn run(&self, args: Vec<String>) -> CommandFuture {
+        self(args)
+    }
+}
+
+struct Entry {
+    usage: &'static str,
+    command: Box<dyn Command>,
+}
+
+// Line editing shell on the text console. Commands are registered before
+// the shell starts and run one at a time in its task.
+pub struct Shell {
+    commands: BTreeMap<&'static str, Entry>,
+    history: VecDeque<String>,
+}
+
+impl Shell {
+    pub fn new() -> Shell {
+        Shell {
+            commands: BTreeMap::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    // Adds a command, replacing any other with the same name
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        command: impl Command + 'static,
+    ) {
+        self.commands.insert(
+            name,
+            Entry {
+                usage,
+                command: Box::new(command),
+            },
+        );
+    }
+
+    pub fn start(self, e: Arc<dyn asyn::Executor>) {
+        e.spawn(asyn::Task::new(self.task_read()));
+    }
+
+    async fn task_read(mut self) {
+        loop {
+            out!("{}", PROMPT);
+            let line = self.read_line().await;
+            let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+            let Some((name, args)) = words.split_first() else {
+                continue;
+            };
+            if self.history.back() != Some(&line) {
+                if self.history.len() == HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(line.clone());
+            }
+            self.run(name, args.to_vec()).await;
+        }
+    }
+
+    async fn run(&self, name: &str, args: Vec<String>) {
+        if name == "help" {
+            for (name, entry) in self.commands.iter() {
+                outln!("  {:<12} {}", name, entry.usage);
+            }
+            return;
+        }
+        match self.commands.get(name) {
+            Some(entry) => entry.command.run(args).await,
+            None => outln!("{}: command not found, try help", name),
+        }
+    }
+
+    // Reads a line, echoing it. Backspace deletes, escape clears the line
+    // and the up and down arrows walk the history.
+    async fn read_line(&self) -> String {
+        let mut line = String::new();
+        let mut position = self.history.len();
+        loop {
+            match asyn::read_key().await {
+                Key::Printable(c) => match char::from(c) {
+                    '\r' | '\n' => {
+                        outln!();
+                        return line;
+                    }
+                    '\u{8}' => {
+                        if line.pop().is_some() {
+                            out!("\u{8} \u{8}");
+                        }
+                    }
+                    c if !c.is_control() && line.len() < MAX_LINE => {
+                        line.push(c);
+                        out!("{}", c);
+                    }
+                    _ => {}
+                },
+                Key::Special(ScanCode::ESCAPE) => Self::replace(&mut line, ""),
+                Key::Special(ScanCode::UP) if position > 0 => {
+                    position -= 1;
+                    Self::replace(&mut line, &self.history[position]);
+                }
+                Key::Special(ScanCode::DOWN) if position < self.history.len() => {
+                    position += 1;
+                    let next = self.history.get(position).map_or("", |s| s.as_str());
+                    Self::replace(&mut line, next);
+                }
+                Key::Special(_) => {}
+            }
+        }
+    }
+
+    // Erases the echoed line and shows another one instead
+    fn replace(line: &mut String, with: &str) {
+        for _ in line.chars() {
+            out!("\u{8} \u{8}");
+        }
+        line.clear();
+        line.push_str(with);
+        out!("{}", line);
+    }
+}
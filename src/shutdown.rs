@@ -0,0 +1,99 @@
+use core::{cell::Cell, future::poll_fn, task::Poll};
+use uefi::{
+    boot::{self, MemoryType},
+    mem::memory_map::MemoryMapOwned,
+    runtime::{self, ResetType},
+    Status,
+};
+
+use crate::logger;
+
+// How the app ends once the network stack is stopped
+#[derive(Debug, Clone, Copy)]
+pub enum Exit {
+    // Back to whatever started the app
+    Return(Status),
+    // Leaves the firmware behind and continues in the function, which gets
+    // the final memory map. Boot services, the console and the allocator
+    // are gone by then.
+    ExitBootServices(fn(MemoryMapOwned) -> !),
+}
+
+// Asked for by any task, carried out by init: the service tasks are
+// cancelled, queued frames sent and the interface stopped
+pub struct Shutdown {
+    exit: Cell<Option<Exit>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            exit: Cell::new(None),
+        }
+    }
+
+    // Only the first request counts
+    pub fn request(&self, exit: Exit) {
+        if self.exit.get().is_none() {
+            self.exit.set(Some(exit));
+        }
+    }
+
+    // Ready once a shutdown was requested
+    pub async fn requested(&self) -> Exit {
+        poll_fn(|_| match self.exit.get() {
+            Some(exit) => Poll::Ready(exit),
+            None => Poll::Pending,
+        })
+        .await
+    }
+
+    pub fn exit(&self) -> Option<Exit> {
+        self.exit.get()
+    }
+}
+
+// Continues after ExitBootServices with what outlives boot services, the
+// runtime services, and resets the machine
+pub fn reset(_: MemoryMapOwned) -> ! {
+    runtime::reset(ResetType::COLD, Status::SUCCESS, None)
+}
+
+// Carries out the exit after the executor returned and everything using
+// boot services is dropped
+pub fn finish(exit: Exit) -> Status {
+    match exit {
+        Exit::Return(status) => status,
+        Exit::ExitBootServices(next) => {
+            logger::close();
+            let memory_map = unsafe { boot::exit_boot_services(MemoryType::LOADER_DATA) };
+            next(memory_map)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{Context, Waker},
+    };
+
+    #[test]
+    fn first_request_counts() {
+        let shutdown = Shutdown::new();
+        let mut requested = pin!(shutdown.requested());
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(requested.as_mut().poll(&mut cx).is_pending());
+
+        shutdown.request(Exit::ExitBootServices(reset));
+        shutdown.request(Exit::Return(Status::ABORTED));
+        assert!(matches!(
+            requested.as_mut().poll(&mut cx),
+            Poll::Ready(Exit::ExitBootServices(_))
+        ));
+        assert!(matches!(shutdown.exit(), Some(Exit::ExitBootServices(_))));
+    }
+}
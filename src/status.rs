@@ -0,0 +1,361 @@
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use uefi::runtime::{self, ResetType};
+
+use crate::{
+    asyn, memory, metrics,
+    network::{
+        arp, ethernet,
+        http::{self, Method, ServerRequest, ServerResponse},
+        icmp, ip, tcp, udp,
+    },
+};
+
+const MAX_PINGS: usize = 10;
+// Lets the response reach the client before a reboot or address change
+const CONTROL_DELAY: f64 = 1.0;
+
+// JSON status and control endpoints for operators
+pub struct Status {
+    executor: Arc<dyn asyn::Executor>,
+    mac_address: ethernet::MacAddress,
+    ethernet: Arc<ethernet::Counters>,
+    arp: Arc<arp::Service>,
+    ip: Arc<ip::Service>,
+    icmp: Arc<icmp::Service>,
+    udp: Arc<udp::Service>,
+    tcp: Arc<tcp::Service>,
+    // Required by the POST endpoints, which are disabled without it
+    token: Option<String>,
+    requests: metrics::Counter,
+    errors: metrics::Counter,
+}
+
+impl Status {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        executor: Arc<dyn asyn::Executor>,
+        mac_address: ethernet::MacAddress,
+        ethernet: Arc<ethernet::Counters>,
+        arp: Arc<arp::Service>,
+        ip: Arc<ip::Service>,
+        icmp: Arc<icmp::Service>,
+        udp: Arc<udp::Service>,
+        tcp: Arc<tcp::Service>,
+        token: Option<String>,
+    ) -> Status {
+        let metrics = ip.metrics();
+        Status {
+            executor,
+            mac_address,
+            ethernet,
+            arp,
+            ip,
+            icmp,
+            udp,
+            tcp,
+            token,
+            requests: metrics.counter("http_requests_total", "Status API requests.", &[]),
+            errors: metrics.counter(
+                "http_errors_total",
+                "Status API requests answered with an error.",
+                &[],
+            ),
+        }
+    }
+
+    async fn route(&self, request: &ServerRequest) -> ServerResponse {
+        let path = request.path.as_str();
+        let allowed = match path {
+            "/api/interface" | "/api/arp" | "/api/sockets" | "/api/counters" | "/api/memory"
+            | "/api/metrics" => Method::Get,
+            "/api/ping" | "/api/reboot" | "/api/ip" => Method::Post,
+            _ => return ServerResponse::error(404, "not found"),
+        };
+        let method = match request.method {
+            Method::Head => Method::Get,
+            m => m,
+        };
+        if method != allowed {
+            return ServerResponse::error(405, "method not allowed");
+        }
+        if method == Method::Post {
+            if let Err(response) = authorize(self.token.as_deref(), request) {
+                return response;
+            }
+        }
+
+        match path {
+            "/api/interface" => self.interface(),
+            "/api/arp" => self.arp().await,
+            "/api/sockets" => self.sockets().await,
+            "/api/counters" => self.counters(),
+            "/api/memory" => Self::memory(),
+            "/api/metrics" => self.metrics(),
+            "/api/ping" => self.ping(request).await,
+            "/api/reboot" => self.reboot(request),
+            _ => self.set_ip(request),
+        }
+    }
+
+    fn interface(&self) -> ServerResponse {
+        ServerResponse::json(
+            200,
+            format!(
+                "{{\"mac\":\"{}\",\"address\":\"{}\",\"netmask\":\"{}\",\"gateway\":\"{}\"}}",
+                self.mac_address,
+                self.ip.address(),
+                self.ip.netmask(),
+                self.ip.gateway(),
+            ),
+        )
+    }
+
+    async fn arp(&self) -> ServerResponse {
+        let table = self.arp.table.lock().await;
+        let entries = table
+            .iter()
+            .map(|(ip, mac)| format!("{{\"address\":\"{}\",\"mac\":\"{}\"}}", ip, mac));
+        ServerResponse::json(200, list(entries))
+    }
+
+    async fn sockets(&self) -> ServerResponse {
+        let protocols = self
+            .ip
+            .protocols()
+            .await
+            .into_iter()
+            .map(|p| format!("{}", p.0));
+        let icmp = self
+            .icmp
+            .sockets()
+            .await
+            .into_iter()
+            .map(|(address, identifier)| {
+                format!(
+                    "{{\"address\":\"{}\",\"identifier\":{}}}",
+                    address, identifier
+                )
+            });
+        let udp = self.udp.ports().await.into_iter().map(|p| format!("{}", p));
+        let listeners = self
+            .tcp
+            .listeners()
+            .await
+            .into_iter()
+            .map(|p| format!("{}", p));
+        let connections = self
+            .tcp
+            .connections()
+            .await
+            .into_iter()
+            .map(|(local, address, port)| {
+                format!(
+                    "{{\"local_port\":{},\"remote_address\":\"{}\",\"remote_port\":{}}}",
+                    local, address, port
+                )
+            });
+        ServerResponse::json(
+            200,
+            format!(
+                "{{\"ip\":{},\"icmp\":{},\"udp\":{},\"tcp\":{{\"listeners\":{},\"connections\":{}}}}}",
+                list(protocols),
+                list(icmp),
+                list(udp),
+                list(listeners),
+                list(connections),
+            ),
+        )
+    }
+
+    fn counters(&self) -> ServerResponse {
+        ServerResponse::json(
+            200,
+            format!(
+                "{{\"ethernet\":{{\"received\":{},\"sent\":{},\"dropped\":{}}},\"http\":{{\"requests\":{},\"errors\":{}}}}}",
+                self.ethernet.received.get(),
+                self.ethernet.sent.get(),
+                self.ethernet.dropped(),
+                self.requests.get(),
+                self.errors.get(),
+            ),
+        )
+    }
+
+    fn memory() -> ServerResponse {
+        let stats = memory::stats();
+        ServerResponse::json(
+            200,
+            format!(
+                "{{\"pages\":{},\"allocated\":{},\"allocations\":{},\"deallocations\":{}}}",
+                stats.pages, stats.allocated, stats.allocations, stats.deallocations,
+            ),
+        )
+    }
+
+    // Prometheus scrape target
+    fn metrics(&self) -> ServerResponse {
+        ServerResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: self.ip.metrics().prometheus().into_bytes(),
+        }
+    }
+
+    // POST /api/ping?address=a.b.c.d&count=n
+    async fn ping(&self, request: &ServerRequest) -> ServerResponse {
+        let address = match request.query("address").and_then(|a| a.parse().ok()) {
+            Some(a) => a,
+            None => return ServerResponse::error(400, "invalid address"),
+        };
+        let count = match request.query("count") {
+            Some(c) => match c.parse::<usize>() {
+                Ok(c) if (1..=MAX_PINGS).contains(&c) => c,
+                _ => return ServerResponse::error(400, "invalid count"),
+            },
+            None => 4,
+        };
+
+        let socket = self.icmp.open(address).await;
+        let mut rtts = Vec::new();
+        for _ in 0..count {
+            let start = asyn::timestamp();
+            let reply = match socket.send(&[0; 32]).await {
+                Ok(()) => socket.receive(1.0).await,
+                Err(e) => Err(e),
+            };
+            rtts.push(match reply {
+                Ok(_) => format!("{:.3}", asyn::elapsed(start) * 1000.0),
+                Err(_) => String::from("null"),
+            });
+        }
+
+        let received = rtts.iter().filter(|r| *r != "null").count();
+        ServerResponse::json(
+            200,
+            format!(
+                "{{\"address\":\"{}\",\"sent\":{},\"received\":{},\"rtt_ms\":{}}}",
+                address,
+                count,
+                received,
+                list(rtts.into_iter()),
+            ),
+        )
+    }
+
+    // POST /api/reboot
+    fn reboot(&self, request: &ServerRequest) -> ServerResponse {
+        log::info!("reboot requested over http by {}", request.remote_address);
+        self.executor.spawn(asyn::Task::new(async {
+            asyn::sleep(CONTROL_DELAY).await;
+            runtime::reset(ResetType::COLD, uefi::Status::SUCCESS, None);
+        }));
+        ServerResponse::json(202, String::from("{\"rebooting\":true}"))
+    }
+
+    // POST /api/ip?address=&netmask=&gateway=, missing values are kept
+    fn set_ip(&self, request: &ServerRequest) -> ServerResponse {
+        let mut config = [self.ip.address(), self.ip.netmask(), self.ip.gateway()];
+        for (name, value) in ["address", "netmask", "gateway"]
+            .iter()
+            .zip(config.iter_mut())
+        {
+            if let Some(v) = request.query(name) {
+                match v.parse() {
+                    Ok(a) => *value = a,
+                    Err(_) => return ServerResponse::error(400, &format!("invalid {}", name)),
+                }
+            }
+        }
+        let [address, netmask, gateway] = config;
+        log::info!(
+            "ip config changed over http by {}: {} {} {}",
+            request.remote_address,
+            address,
+            netmask,
+            gateway
+        );
+
+        let ip = self.ip.clone();
+        self.executor.spawn(asyn::Task::new(async move {
+            asyn::sleep(CONTROL_DELAY).await;
+            ip.set_config(address, netmask, gateway);
+        }));
+        ServerResponse::json(
+            202,
+            format!(
+                "{{\"address\":\"{}\",\"netmask\":\"{}\",\"gateway\":\"{}\"}}",
+                address, netmask, gateway
+            ),
+        )
+    }
+}
+
+impl http::Handler for Status {
+    async fn handle(&self, request: ServerRequest) -> ServerResponse {
+        self.requests.inc();
+        let response = self.route(&request).await;
+        if response.status >= 400 {
+            self.errors.inc();
+        }
+        response
+    }
+}
+
+// Checks the bearer token of a control request
+fn authorize(token: Option<&str>, request: &ServerRequest) -> Result<(), ServerResponse> {
+    let token = token.ok_or_else(|| ServerResponse::error(403, "control endpoints disabled"))?;
+    let given = request
+        .header("authorization")
+        .and_then(|a| a.strip_prefix("Bearer "))
+        .unwrap_or("");
+    // compares every byte so the time taken does not give the token away
+    let differences = given
+        .bytes()
+        .zip(token.bytes())
+        .fold(0, |d, (a, b)| d | (a ^ b));
+    if differences != 0 || given.len() != token.len() {
+        return Err(ServerResponse::error(401, "unauthorized"));
+    }
+    Ok(())
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(authorization: Option<&str>) -> ServerRequest {
+        ServerRequest {
+            method: Method::Post,
+            path: String::from("/api/reboot"),
+            query: String::new(),
+            headers: authorization
+                .map(|a| Vec::from([(String::from("authorization"), String::from(a))]))
+                .unwrap_or_default(),
+            remote_address: ip::Address([10, 0, 0, 1]),
+        }
+    }
+
+    fn status(r: Result<(), ServerResponse>) -> u16 {
+        r.err().map_or(200, |response| response.status)
+    }
+
+    #[test]
+    fn control_needs_the_token() {
+        // no token configured, nothing gets in
+        assert_eq!(status(authorize(None, &post(None))), 403);
+        assert_eq!(status(authorize(None, &post(Some("Bearer ")))), 403);
+
+        let token = Some("s3cret");
+        assert_eq!(status(authorize(token, &post(None))), 401);
+        assert_eq!(status(authorize(token, &post(Some("s3cret")))), 401);
+        assert_eq!(status(authorize(token, &post(Some("Bearer s3cre")))), 401);
+        assert_eq!(status(authorize(token, &post(Some("Bearer s3cret!")))), 401);
+        assert_eq!(status(authorize(token, &post(Some("Bearer s3cret")))), 200);
+    }
+}